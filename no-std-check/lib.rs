@@ -0,0 +1,56 @@
+//! Links `gameboy-rom` into a `no_std` static library, which fails with a
+//! duplicate `panic_impl` lang item if anything it depends on pulls in `std`.
+//!
+//! Build it with `cargo no-std-check`.
+#![no_std]
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+use gameboy_rom::{GameBoyRom, Opcode};
+
+/// Parses the header of the ROM at `rom`, returning the ROM size it declares
+/// in bytes or 0 if it couldn't be parsed.
+///
+/// # Safety
+///
+/// `rom` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gb_rom_size(rom: *const u8, len: usize) -> usize {
+    let rom = GameBoyRom::new(core::slice::from_raw_parts(rom, len));
+    match rom.parse_header() {
+        Ok(header) => header.rom_size as usize * 0x4000,
+        Err(_) => 0,
+    }
+}
+
+/// Counts the instructions from `start` until the first `jp`.
+///
+/// # Safety
+///
+/// `rom` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gb_count_until_jump(rom: *const u8, len: usize, start: usize) -> usize {
+    GameBoyRom::new(core::slice::from_raw_parts(rom, len))
+        .get_instructions_at(start)
+        .take_while(|opcode| !matches!(opcode, Opcode::Jp(..)))
+        .count()
+}
+
+/// Firmware would provide a real allocator, this only has to link.
+struct NoAlloc;
+
+unsafe impl GlobalAlloc for NoAlloc {
+    unsafe fn alloc(&self, _: Layout) -> *mut u8 {
+        core::ptr::null_mut()
+    }
+
+    unsafe fn dealloc(&self, _: *mut u8, _: Layout) {}
+}
+
+#[global_allocator]
+static ALLOCATOR: NoAlloc = NoAlloc;
+
+#[panic_handler]
+fn panic(_: &PanicInfo) -> ! {
+    loop {}
+}
@@ -0,0 +1,775 @@
+//! The audio processing unit rendering to PCM samples.
+//!
+//! All four channels are emulated along with the frame sequencer which clocks
+//! their length counters, volume envelopes and frequency sweep. The channels
+//! are mixed through NR50 and NR51 into interleaved stereo `i16` samples at
+//! the requested sample rate, with a high pass filter removing the DC offset
+//! like the capacitors on the real hardware.
+//!
+//! The APU is a [`Bus`] covering the sound registers (0xFF10-0xFF26) and wave
+//! RAM (0xFF30-0xFF3F). The frame sequencer runs from its own 512 Hz counter
+//! rather than from DIV.
+//!
+//! ```
+//! use gameboy_rom::apu::Apu;
+//! use gameboy_rom::cpu::Bus;
+//!
+//! // one sample every 128 T-cycles
+//! let mut apu = Apu::new(32_768);
+//! // a 50% duty square wave on channel 2 at full volume, panned both ways
+//! apu.write(0xFF26, 0x80);
+//! apu.write(0xFF24, 0x77);
+//! apu.write(0xFF25, 0x22);
+//! apu.write(0xFF16, 0x80);
+//! apu.write(0xFF17, 0xF0);
+//! apu.write(0xFF18, 0x00);
+//! apu.write(0xFF19, 0x87);
+//! apu.step(128 * 100);
+//! let samples = apu.take_samples();
+//! assert_eq!(samples.len(), 100 * 2);
+//! assert!(samples.iter().any(|sample| *sample != 0));
+//! ```
+//!
+//! Behavior is based on the [Pan Docs].
+//!
+//! [Pan Docs]: https://gbdev.io/pandocs/Audio_details.html
+
+use crate::cpu::Bus;
+
+use serde::{Deserialize, Serialize};
+
+/// The number of T-cycles per second.
+pub const CPU_CLOCK: u32 = 4_194_304;
+
+/// T-cycles between frame sequencer steps (512 Hz).
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+/// Pulse waveforms for each duty cycle, one bit per step.
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// The noise channel's timer divisors selected by NR43.
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Bits that always read as 1 for each register from 0xFF10 to 0xFF2F.
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // unused
+];
+
+/// Counts down to silence a channel after a set time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct LengthCounter {
+    enabled: bool,
+    remaining: u16,
+}
+
+impl LengthCounter {
+    /// Clock the counter, returning false when it expires.
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.remaining > 0 {
+            self.remaining -= 1;
+            return self.remaining != 0;
+        }
+        true
+    }
+
+    fn trigger(&mut self, max: u16) {
+        if self.remaining == 0 {
+            self.remaining = max;
+        }
+    }
+}
+
+/// Periodically raises or lowers a channel's volume.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Envelope {
+    /// NRx2
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period();
+            if self.register & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if self.register & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+    }
+}
+
+/// Channel 1's frequency sweep.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Sweep {
+    /// NR10
+    register: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    /// The next frequency, or `None` if it overflows and silences the
+    /// channel.
+    fn next_frequency(&self) -> Option<u16> {
+        let delta = self.shadow >> self.shift();
+        let frequency = if self.register & 0x08 != 0 {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        };
+        if frequency > 2047 {
+            None
+        } else {
+            Some(frequency)
+        }
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+}
+
+/// Channels 1 and 2.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct PulseChannel {
+    enabled: bool,
+    /// Only channel 1 has a sweep unit.
+    sweep: Option<Sweep>,
+    length: LengthCounter,
+    envelope: Envelope,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u32,
+}
+
+impl PulseChannel {
+    fn new(sweep: bool) -> Self {
+        Self {
+            sweep: if sweep { Some(Sweep::default()) } else { None },
+            ..Self::default()
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    fn tick(&mut self) {
+        if self.timer <= 1 {
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && DUTY_PATTERNS[self.duty as usize] & (0x80 >> self.duty_step) != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(64);
+        self.envelope.trigger();
+        self.timer = self.period();
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+            if sweep.shift() != 0 && sweep.next_frequency().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        let sweep = match &mut self.sweep {
+            Some(sweep) => sweep,
+            None => return,
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+        match sweep.next_frequency() {
+            Some(frequency) if sweep.shift() != 0 => {
+                sweep.shadow = frequency;
+                self.frequency = frequency;
+                // the new frequency is checked for overflow straight away
+                if sweep.next_frequency().is_none() {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => (),
+            None => self.enabled = false,
+        }
+    }
+}
+
+/// Channel 3, playing back wave RAM.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: LengthCounter,
+    /// NR32 bits 5-6.
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    ram: [u8; 16],
+}
+
+impl WaveChannel {
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    fn tick(&mut self) {
+        if self.timer <= 1 {
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x1F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.volume_code == 0 {
+            return 0;
+        }
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if self.position.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+        sample >> (self.volume_code - 1)
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(256);
+        self.timer = self.period();
+        self.position = 0;
+    }
+}
+
+/// Channel 4, pseudo-random noise from a linear feedback shift register.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct NoiseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    /// NR43
+    register: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl NoiseChannel {
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[(self.register & 0x07) as usize] << (self.register >> 4)
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period();
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        if self.register & 0x08 != 0 {
+            // 7 bit mode
+            self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0x01 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(64);
+        self.envelope.trigger();
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+    }
+}
+
+/// The audio processing unit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Apu {
+    sample_rate: u32,
+    powered: bool,
+    /// The last value written to each register from 0xFF10 to 0xFF2F.
+    registers: [u8; 0x20],
+    channel1: PulseChannel,
+    channel2: PulseChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    frame_sequencer_timer: u32,
+    frame_sequencer_step: u8,
+    /// Accumulates `sample_rate` each T-cycle, a sample is taken each time it
+    /// passes `CPU_CLOCK`.
+    sample_timer: u32,
+    /// The high pass filter's capacitor charge for each side.
+    capacitors: [f32; 2],
+    /// How much charge the capacitors keep between samples.
+    capacitor_factor: f32,
+    #[serde(skip)]
+    samples: Vec<i16>,
+}
+
+impl Apu {
+    /// Create a powered off APU producing `sample_rate` stereo samples per
+    /// second, a sample rate of 0 emulates the registers without rendering
+    /// any audio.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            powered: false,
+            registers: [0; 0x20],
+            channel1: PulseChannel::new(true),
+            channel2: PulseChannel::new(false),
+            channel3: WaveChannel::default(),
+            channel4: NoiseChannel::default(),
+            frame_sequencer_timer: FRAME_SEQUENCER_PERIOD,
+            frame_sequencer_step: 0,
+            sample_timer: 0,
+            capacitors: [0.0; 2],
+            capacitor_factor: capacitor_factor(sample_rate),
+            samples: vec![],
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Change the rate samples are rendered at, 0 stops rendering.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_timer = 0;
+        self.capacitor_factor = capacitor_factor(sample_rate);
+    }
+
+    /// Remove and return the samples rendered so far, interleaved left then
+    /// right.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Run for the given number of T-cycles, rendering samples as it goes.
+    pub fn step(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if self.powered {
+                self.tick();
+            }
+            if self.sample_rate == 0 {
+                continue;
+            }
+            self.sample_timer += self.sample_rate;
+            if self.sample_timer >= CPU_CLOCK {
+                self.sample_timer -= CPU_CLOCK;
+                self.render_sample();
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        self.channel1.tick();
+        self.channel2.tick();
+        self.channel3.tick();
+        self.channel4.tick();
+
+        self.frame_sequencer_timer -= 1;
+        if self.frame_sequencer_timer != 0 {
+            return;
+        }
+        self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+        let step = self.frame_sequencer_step;
+        self.frame_sequencer_step = (step + 1) & 0x07;
+        if step.is_multiple_of(2) {
+            self.channel1.enabled &= self.channel1.length.clock();
+            self.channel2.enabled &= self.channel2.length.clock();
+            self.channel3.enabled &= self.channel3.length.clock();
+            self.channel4.enabled &= self.channel4.length.clock();
+        }
+        if step == 2 || step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if step == 7 {
+            self.channel1.envelope.clock();
+            self.channel2.envelope.clock();
+            self.channel4.envelope.clock();
+        }
+    }
+
+    fn render_sample(&mut self) {
+        let nr50 = self.registers[0x14];
+        let nr51 = self.registers[0x15];
+        // each DAC maps 0-15 to an amplitude between -15 and 15, disabled
+        // DACs output nothing
+        let outputs = [
+            (self.channel1.envelope.dac_enabled(), self.channel1.output()),
+            (self.channel2.envelope.dac_enabled(), self.channel2.output()),
+            (self.channel3.dac_enabled, self.channel3.output()),
+            (self.channel4.envelope.dac_enabled(), self.channel4.output()),
+        ];
+
+        for side in 0..2 {
+            let (panning_shift, volume) = if side == 0 {
+                (4, (nr50 >> 4) & 0x07)
+            } else {
+                (0, nr50 & 0x07)
+            };
+            let mut mixed = 0i32;
+            if self.powered {
+                for (channel, (dac_enabled, output)) in outputs.iter().enumerate() {
+                    if *dac_enabled && nr51 & (1 << (channel + panning_shift)) != 0 {
+                        mixed += *output as i32 * 2 - 15;
+                    }
+                }
+            }
+            // at most 4 channels * 15 * 8 = 480
+            let input = (mixed * (volume as i32 + 1)) as f32 / 480.0;
+            let output = input - self.capacitors[side];
+            self.capacitors[side] = input - output * self.capacitor_factor;
+            self.samples.push((output * i16::MAX as f32 * 0.5) as i16);
+        }
+    }
+
+    fn power_off(&mut self) {
+        let wave_ram = self.channel3.ram;
+        *self = Self {
+            samples: std::mem::take(&mut self.samples),
+            capacitors: self.capacitors,
+            sample_timer: self.sample_timer,
+            ..Self::new(self.sample_rate)
+        };
+        self.channel3.ram = wave_ram;
+    }
+
+    fn status(&self) -> u8 {
+        (if self.powered { 0x80 } else { 0 })
+            | (if self.channel1.enabled { 0x01 } else { 0 })
+            | (if self.channel2.enabled { 0x02 } else { 0 })
+            | (if self.channel3.enabled { 0x04 } else { 0 })
+            | (if self.channel4.enabled { 0x08 } else { 0 })
+    }
+}
+
+impl Bus for Apu {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0xFF26 => READ_MASKS[0x16] | self.status(),
+            0xFF10..=0xFF2F => {
+                let index = address as usize - 0xFF10;
+                READ_MASKS[index] | self.registers[index]
+            }
+            0xFF30..=0xFF3F => self.channel3.ram[address as usize - 0xFF30],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF26 => {
+                if value & 0x80 == 0 {
+                    self.power_off();
+                } else if !self.powered {
+                    self.powered = true;
+                    self.frame_sequencer_step = 0;
+                }
+                return;
+            }
+            0xFF30..=0xFF3F => {
+                self.channel3.ram[address as usize - 0xFF30] = value;
+                return;
+            }
+            0xFF10..=0xFF2F if self.powered => (),
+            _ => return,
+        }
+        self.registers[address as usize - 0xFF10] = value;
+
+        match address {
+            0xFF10 => {
+                if let Some(sweep) = &mut self.channel1.sweep {
+                    sweep.register = value;
+                }
+            }
+            0xFF11 => write_duty_length(&mut self.channel1, value),
+            0xFF12 => write_envelope(
+                &mut self.channel1.envelope,
+                &mut self.channel1.enabled,
+                value,
+            ),
+            0xFF13 => self.channel1.frequency = (self.channel1.frequency & 0x700) | value as u16,
+            0xFF14 => {
+                write_frequency_high(
+                    &mut self.channel1.frequency,
+                    &mut self.channel1.length,
+                    value,
+                );
+                if value & 0x80 != 0 {
+                    self.channel1.trigger();
+                }
+            }
+            0xFF16 => write_duty_length(&mut self.channel2, value),
+            0xFF17 => write_envelope(
+                &mut self.channel2.envelope,
+                &mut self.channel2.enabled,
+                value,
+            ),
+            0xFF18 => self.channel2.frequency = (self.channel2.frequency & 0x700) | value as u16,
+            0xFF19 => {
+                write_frequency_high(
+                    &mut self.channel2.frequency,
+                    &mut self.channel2.length,
+                    value,
+                );
+                if value & 0x80 != 0 {
+                    self.channel2.trigger();
+                }
+            }
+            0xFF1A => {
+                self.channel3.dac_enabled = value & 0x80 != 0;
+                self.channel3.enabled &= self.channel3.dac_enabled;
+            }
+            0xFF1B => self.channel3.length.remaining = 256 - value as u16,
+            0xFF1C => self.channel3.volume_code = (value >> 5) & 0x03,
+            0xFF1D => self.channel3.frequency = (self.channel3.frequency & 0x700) | value as u16,
+            0xFF1E => {
+                write_frequency_high(
+                    &mut self.channel3.frequency,
+                    &mut self.channel3.length,
+                    value,
+                );
+                if value & 0x80 != 0 {
+                    self.channel3.trigger();
+                }
+            }
+            0xFF20 => self.channel4.length.remaining = 64 - (value & 0x3F) as u16,
+            0xFF21 => write_envelope(
+                &mut self.channel4.envelope,
+                &mut self.channel4.enabled,
+                value,
+            ),
+            0xFF22 => self.channel4.register = value,
+            0xFF23 => {
+                self.channel4.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.channel4.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// How much charge the high pass filter's capacitors keep between samples.
+fn capacitor_factor(sample_rate: u32) -> f32 {
+    if sample_rate == 0 {
+        return 0.0;
+    }
+    0.999_958f32.powf(CPU_CLOCK as f32 / sample_rate as f32)
+}
+
+/// Handle a write to NR11 or NR21.
+fn write_duty_length(channel: &mut PulseChannel, value: u8) {
+    channel.duty = value >> 6;
+    channel.length.remaining = 64 - (value & 0x3F) as u16;
+}
+
+/// Handle a write to NR12, NR22 or NR42, turning the DAC off silences the
+/// channel.
+fn write_envelope(envelope: &mut Envelope, enabled: &mut bool, value: u8) {
+    envelope.register = value;
+    if !envelope.dac_enabled() {
+        *enabled = false;
+    }
+}
+
+/// Handle a write to NR14, NR24 or NR34 apart from the trigger bit.
+fn write_frequency_high(frequency: &mut u16, length: &mut LengthCounter, value: u8) {
+    *frequency = (*frequency & 0xFF) | (((value & 0x07) as u16) << 8);
+    length.enabled = value & 0x40 != 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cycles until the frame sequencer's first step.
+    const STEP: u32 = FRAME_SEQUENCER_PERIOD;
+
+    fn powered(sample_rate: u32) -> Apu {
+        let mut apu = Apu::new(sample_rate);
+        apu.write(0xFF26, 0x80);
+        apu
+    }
+
+    /// The channel status bits of NR52.
+    fn status(apu: &mut Apu) -> u8 {
+        apu.read(0xFF26) & 0x0F
+    }
+
+    #[test]
+    fn length_expiry() {
+        let mut apu = powered(0);
+        // channel 2 with a length of 1, channel 1 with length disabled
+        apu.write(0xFF16, 0x3F);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0xC0);
+        apu.write(0xFF11, 0x3F);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x80);
+        assert_eq!(apu.read(0xFF26), 0xF3);
+
+        apu.step(STEP - 1);
+        assert_eq!(status(&mut apu), 0x03);
+        apu.step(1);
+        assert_eq!(status(&mut apu), 0x01);
+
+        // turning off the DAC disables the channel too
+        apu.write(0xFF12, 0x00);
+        assert_eq!(apu.read(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn sweep_overflow() {
+        let mut apu = powered(0);
+        // period 1, adding the frequency shifted right by 1
+        apu.write(0xFF10, 0x11);
+        apu.write(0xFF12, 0xF0);
+        // 0x500 + 0x280 fits in 11 bits, the next step doesn't
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x85);
+        assert_eq!(status(&mut apu), 0x01);
+        // the sweep is clocked on the frame sequencer's third step
+        apu.step(STEP * 3 - 1);
+        assert_eq!(status(&mut apu), 0x01);
+        apu.step(1);
+        assert_eq!(apu.channel1.frequency, 0x780);
+        assert_eq!(status(&mut apu), 0x00);
+
+        // a frequency that overflows straight away disables it on trigger
+        apu.write(0xFF14, 0x87);
+        assert_eq!(status(&mut apu), 0x00);
+    }
+
+    #[test]
+    fn panning() {
+        let mut apu = powered(32_768);
+        apu.write(0xFF24, 0x77);
+        // channel 2 on the right only
+        apu.write(0xFF25, 0x02);
+        apu.write(0xFF16, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0x87);
+        apu.step(128 * 100);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 200);
+        assert!(samples.iter().step_by(2).all(|sample| *sample == 0));
+        assert!(samples.iter().skip(1).step_by(2).any(|sample| *sample != 0));
+    }
+
+    #[test]
+    fn power_off() {
+        let mut apu = powered(0);
+        apu.write(0xFF30, 0x12);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0xFF);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0x80);
+        apu.write(0xFF26, 0x00);
+        assert_eq!(apu.read(0xFF26), 0x70);
+        assert_eq!(apu.read(0xFF24), 0x00);
+        assert_eq!(apu.read(0xFF25), 0x00);
+        assert_eq!(apu.read(0xFF17), 0x00);
+        assert_eq!(apu.read(0xFF30), 0x12);
+
+        // registers ignore writes until it's powered on, wave RAM doesn't
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF31, 0x34);
+        assert_eq!(apu.read(0xFF24), 0x00);
+        assert_eq!(apu.read(0xFF31), 0x34);
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF24, 0x77);
+        assert_eq!(apu.read(0xFF24), 0x77);
+    }
+
+    #[test]
+    fn sample_count() {
+        // a second's worth of cycles in uneven steps
+        let mut apu = powered(48_000);
+        let mut remaining = CPU_CLOCK;
+        while remaining > 0 {
+            let cycles = remaining.min(70_224);
+            apu.step(cycles);
+            remaining -= cycles;
+        }
+        assert_eq!(apu.take_samples().len(), 48_000 * 2);
+
+        // a sample every 95.1 cycles
+        apu.set_sample_rate(44_100);
+        apu.step(1000);
+        assert_eq!(apu.take_samples().len(), 10 * 2);
+        apu.step(CPU_CLOCK - 1000);
+        assert_eq!(apu.take_samples().len(), (44_100 - 10) * 2);
+
+        apu.set_sample_rate(0);
+        apu.step(CPU_CLOCK);
+        assert!(apu.take_samples().is_empty());
+    }
+}
@@ -1,34 +1,256 @@
+//! Print the headers of ROMs, with what they decode to, whether they pass
+//! validation and their hashes, as a single JSON document.
+//!
+//! Usage: `gb2json [--format json|ndjson|yaml|toml] [FILE...]`
+//!
+//! With no files, or a file of `-`, the ROM is read from stdin. Zip and gzip
+//! archives are read with every ROM in them. NDJSON prints a line per ROM
+//! instead of one document. YAML and TOML need the `serde_yaml` and `toml`
+//! features.
+//!
+//! Exits with 0 if every ROM was read and its header parsed, 1 if any
+//! couldn't be and 2 if the arguments are invalid.
+use gameboy_rom::hash::RomHashes;
+use gameboy_rom::ips::ChecksumStatus;
+use gameboy_rom::loader::{self, LoadedRom};
+use gameboy_rom::{save, GameBoyRom, GameboyColorCompatibility, RomHeader, RomType};
+use serde::Serialize;
 use std::io::Read;
 
+const USAGE: &str = "Usage: gb2json [--format json|ndjson|yaml|toml] [FILE...]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Ndjson,
+    Yaml,
+    Toml,
+}
+
+#[derive(Debug, Serialize)]
+struct Document<'a> {
+    roms: Vec<RomReport<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct RomReport<'a> {
+    name: &'a str,
+    /// Why the file couldn't be read or its header parsed, if it couldn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hashes: Option<RomHashes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    header: Option<RomHeader<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decoded: Option<Decoded>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<Validation>,
+}
+
+/// What the header's codes mean.
+#[derive(Debug, Serialize)]
+struct Decoded {
+    cartridge: RomType,
+    color: GameboyColorCompatibility,
+    supports_color: bool,
+    rom_size_bytes: usize,
+    save_ram_bytes: usize,
+    battery: bool,
+    rtc: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Validation {
+    passes: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    header_checksum_valid: bool,
+    global_checksum_valid: bool,
+}
+
+/// An input that couldn't be loaded, or the ROMs loaded from it.
+enum Input {
+    Failed { name: String, error: String },
+    Loaded(Vec<LoadedRom>),
+}
+
 fn main() {
-    let mut args = std::env::args();
-    args.next().unwrap();
-    let rom_file_path = if let Some(arg) = args.next() {
-        arg
-    } else {
-        eprintln!("Must supply a path to a gameboy ROM");
-        std::process::exit(-1);
-    };
-    let mut file = std::fs::File::open(rom_file_path).expect("gameboy rom file");
-    let mut bytes = vec![];
-    file.read_to_end(&mut bytes).expect("read bytes from file");
-    let gbr = gameboy_rom::GameBoyRom::new(&bytes[..]);
-
-    match gbr.parse_header() {
-        Ok(rh) => {
-            println!("{}", serde_json::to_string_pretty(&rh).unwrap());
-            println!(
-                "ROM passes validation check? {}",
-                if let Err(err) = rh.validate() {
-                    format!("NO: {:?}", err)
-                } else {
-                    "YES".to_string()
+    let mut format = Format::Json;
+    let mut paths = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" | "-f" => {
+                format = match args.next().as_deref() {
+                    Some("json") => Format::Json,
+                    Some("ndjson") => Format::Ndjson,
+                    Some("yaml") if cfg!(feature = "serde_yaml") => Format::Yaml,
+                    Some("toml") if cfg!(feature = "toml") => Format::Toml,
+                    Some("yaml") => usage_error("YAML output needs the serde_yaml feature"),
+                    Some("toml") => usage_error("TOML output needs the toml feature"),
+                    other => usage_error(&format!("Unknown format {:?}", other)),
                 }
-            );
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => usage_error(&format!("Unknown option {}", arg)),
+            _ => paths.push(arg),
         }
-        Err(e) => {
-            eprintln!("Failed to parse ROM: {:?}", e);
-            std::process::exit(-1);
+    }
+    if paths.is_empty() {
+        paths.push("-".to_string());
+    }
+
+    let inputs = paths.iter().map(|path| load(path)).collect::<Vec<_>>();
+    let mut failed = false;
+    let mut document = Document { roms: vec![] };
+    for input in &inputs {
+        match input {
+            Input::Failed { name, error } => {
+                eprintln!("Failed to load {}: {}", name, error);
+                failed = true;
+                document.roms.push(RomReport::failed(name, error.clone()));
+            }
+            Input::Loaded(roms) => {
+                for rom in roms {
+                    let report = RomReport::new(rom);
+                    if let Some(error) = &report.error {
+                        eprintln!("Failed to parse {}: {}", rom.name, error);
+                        failed = true;
+                    }
+                    document.roms.push(report);
+                }
+            }
+        }
+    }
+
+    if let Err(e) = print(format, &document) {
+        eprintln!("Failed to write output: {}", e);
+        std::process::exit(1);
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn load(path: &str) -> Input {
+    let result = if path == "-" {
+        let mut bytes = vec![];
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())
+            .and_then(|_| loader::load_bytes("<stdin>", bytes).map_err(|e| e.to_string()))
+    } else {
+        loader::load_path(path).map_err(|e| e.to_string())
+    };
+    match result {
+        Ok(roms) => Input::Loaded(roms),
+        Err(error) => Input::Failed {
+            name: path.to_string(),
+            error,
+        },
+    }
+}
+
+impl<'a> RomReport<'a> {
+    fn failed(name: &'a str, error: String) -> Self {
+        Self {
+            name,
+            error: Some(error),
+            size: None,
+            hashes: None,
+            header: None,
+            decoded: None,
+            validation: None,
         }
     }
+
+    fn new(rom: &'a LoadedRom) -> Self {
+        let gbr = GameBoyRom::new(&rom.bytes);
+        let mut report = Self {
+            name: &rom.name,
+            error: None,
+            size: Some(rom.bytes.len()),
+            hashes: Some(gbr.hashes()),
+            header: None,
+            decoded: None,
+            validation: None,
+        };
+        let header = match gbr.parse_header() {
+            Ok(header) => header,
+            Err(_) => {
+                // the parser's error includes the rest of the ROM
+                report.error = Some("failed to parse the header".to_string());
+                return report;
+            }
+        };
+        report.decoded = Some(Decoded {
+            cartridge: header.rom_type,
+            color: header.gameboy_color,
+            supports_color: header.gameboy_color.supports_color(),
+            rom_size_bytes: header.rom_size as usize * 0x4000,
+            save_ram_bytes: save::sram_size(&header),
+            battery: save::has_battery(header.rom_type),
+            rtc: save::has_rtc(header.rom_type),
+        });
+        let checksums = ChecksumStatus::new(&rom.bytes);
+        let validation = header.validate();
+        report.validation = Some(Validation {
+            passes: validation.is_ok(),
+            error: validation.err().map(|e| format!("{:?}", e)),
+            header_checksum_valid: checksums.as_ref().is_some_and(|c| c.header_checksum_valid),
+            global_checksum_valid: checksums.as_ref().is_some_and(|c| c.global_checksum_valid),
+        });
+        report.header = Some(header);
+        report
+    }
+}
+
+fn print(format: Format, document: &Document) -> Result<(), String> {
+    let stdout = std::io::stdout();
+    match format {
+        Format::Json => serde_json::to_writer_pretty(stdout.lock(), document)
+            .map(|()| println!())
+            .map_err(|e| e.to_string()),
+        Format::Ndjson => document.roms.iter().try_for_each(|rom| {
+            serde_json::to_string(rom)
+                .map(|line| println!("{}", line))
+                .map_err(|e| e.to_string())
+        }),
+        Format::Yaml => print_yaml(document),
+        Format::Toml => print_toml(document),
+    }
+}
+
+#[cfg(feature = "serde_yaml")]
+fn print_yaml(document: &Document) -> Result<(), String> {
+    serde_yaml::to_writer(std::io::stdout().lock(), document).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "serde_yaml"))]
+fn print_yaml(_: &Document) -> Result<(), String> {
+    unreachable!("the format is checked when parsing arguments")
+}
+
+#[cfg(feature = "toml")]
+fn print_toml(document: &Document) -> Result<(), String> {
+    toml::to_string(document)
+        .map(|toml| print!("{}", toml))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "toml"))]
+fn print_toml(_: &Document) -> Result<(), String> {
+    unreachable!("the format is checked when parsing arguments")
 }
@@ -0,0 +1,123 @@
+//! Run a test ROM headlessly and report whether it passed.
+//!
+//! Blargg's test ROMs print their results to the serial port, ending with
+//! "Passed" or "Failed". Mooneye's test ROMs execute `ld b, b` when they're
+//! done and leave the Fibonacci numbers 3, 5, 8, 13, 21 and 34 in B, C, D, E,
+//! H and L if they passed.
+//!
+//! The audio can be saved by passing a path to write a WAV file to.
+//!
+//! Exits with 0 if the ROM passed, 1 if it failed and 2 if it timed out,
+//! couldn't be run or the WAV file couldn't be written.
+use gameboy_rom::machine::{Machine, StopReason};
+use gameboy_rom::{wav, GameBoyRom};
+
+/// Frames to run for if no limit is given, 2 minutes of emulated time.
+const DEFAULT_MAX_FRAMES: u64 = 60 * 120;
+/// The sample rate of the WAV file written.
+const SAMPLE_RATE: u32 = 48_000;
+
+fn main() {
+    let mut args = std::env::args();
+    args.next().unwrap();
+    let rom_file_path = if let Some(arg) = args.next() {
+        arg
+    } else {
+        eprintln!("Usage: gbrun <rom> [max frames] [output wav]");
+        std::process::exit(2);
+    };
+    let max_frames = match args.next().map(|arg| arg.parse::<u64>()) {
+        Some(Ok(frames)) => frames,
+        Some(Err(e)) => {
+            eprintln!("Invalid frame count: {}", e);
+            std::process::exit(2);
+        }
+        None => DEFAULT_MAX_FRAMES,
+    };
+    let wav_path = args.next();
+
+    let bytes = match std::fs::read(&rom_file_path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", rom_file_path, e);
+            std::process::exit(2);
+        }
+    };
+
+    let mut machine = match Machine::new(&GameBoyRom::new(bytes.as_slice())) {
+        Ok(machine) => machine,
+        Err(e) => {
+            eprintln!("Failed to start ROM: {:?}", e);
+            std::process::exit(2);
+        }
+    };
+    machine.break_on_ld_b_b = true;
+    if wav_path.is_some() {
+        machine.bus.apu.set_sample_rate(SAMPLE_RATE);
+    }
+
+    let mut serial_len = 0;
+    let reason = machine.run_until(max_frames, |machine| {
+        let output = machine.bus.serial_output();
+        if output.len() == serial_len {
+            return false;
+        }
+        serial_len = output.len();
+        serial_result(output).is_some()
+    });
+
+    if let Some(wav_path) = wav_path {
+        let samples = machine.bus.apu.take_samples();
+        let result = std::fs::File::create(&wav_path).and_then(|file| {
+            wav::write_wav(std::io::BufWriter::new(file), SAMPLE_RATE, 2, &samples)
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to write {}: {}", wav_path, e);
+            std::process::exit(2);
+        }
+    }
+
+    let output = String::from_utf8_lossy(machine.bus.serial_output()).into_owned();
+    if !output.is_empty() {
+        println!("{}", output.trim_end());
+    }
+
+    let passed = match reason {
+        StopReason::Condition => serial_result(machine.bus.serial_output()),
+        StopReason::SoftwareBreakpoint(_) => {
+            let r = &machine.cpu.registers;
+            Some([r.b, r.c, r.d, r.e, r.h, r.l] == [3, 5, 8, 13, 21, 34])
+        }
+        StopReason::Locked => {
+            eprintln!("CPU locked up at ${:04X}", machine.cpu.registers.pc);
+            Some(false)
+        }
+        StopReason::FrameLimit | StopReason::Breakpoint(_) => None,
+    };
+
+    match passed {
+        Some(true) => {
+            println!("PASS");
+        }
+        Some(false) => {
+            println!("FAIL");
+            std::process::exit(1);
+        }
+        None => {
+            println!("TIMEOUT after {} frames", max_frames);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Whether Blargg style serial output says the test passed, if it's finished.
+fn serial_result(output: &[u8]) -> Option<bool> {
+    let text = String::from_utf8_lossy(output);
+    if text.contains("Passed") {
+        Some(true)
+    } else if text.contains("Failed") {
+        Some(false)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,81 @@
+//! Render a song from a GBS file to a WAV file.
+//!
+//! Usage: gbs2wav <gbs> <output wav> [song] [seconds]
+//!
+//! Songs are numbered from 1 and default to the header's first song.
+//!
+//! Exits with 0 once the WAV file is written and 2 if the arguments are
+//! invalid or the GBS file couldn't be read, played or rendered.
+use gameboy_rom::gbs::{GbsFile, GbsPlayer};
+use gameboy_rom::wav;
+
+/// The sample rate of the WAV file written.
+const SAMPLE_RATE: u32 = 48_000;
+/// How long to render if no length is given.
+const DEFAULT_SECONDS: u32 = 60;
+
+fn main() {
+    let mut args = std::env::args();
+    args.next().unwrap();
+    let (gbs_file_path, wav_path) = match (args.next(), args.next()) {
+        (Some(gbs), Some(wav)) => (gbs, wav),
+        _ => {
+            eprintln!("Usage: gbs2wav <gbs> <output wav> [song] [seconds]");
+            std::process::exit(2);
+        }
+    };
+    let song = match args.next().map(|arg| arg.parse::<u8>()) {
+        Some(Ok(song)) => Some(song),
+        Some(Err(e)) => {
+            eprintln!("Invalid song number: {}", e);
+            std::process::exit(2);
+        }
+        None => None,
+    };
+    let seconds = match args.next().map(|arg| arg.parse::<u32>()) {
+        Some(Ok(seconds)) => seconds,
+        Some(Err(e)) => {
+            eprintln!("Invalid number of seconds: {}", e);
+            std::process::exit(2);
+        }
+        None => DEFAULT_SECONDS,
+    };
+
+    let bytes = match std::fs::read(&gbs_file_path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", gbs_file_path, e);
+            std::process::exit(2);
+        }
+    };
+
+    let gbs = match GbsFile::parse(&bytes) {
+        Ok(gbs) => gbs,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    println!(
+        "{} - {} ({}), {} songs",
+        gbs.header.title, gbs.header.author, gbs.header.copyright, gbs.header.song_count
+    );
+
+    // songs are numbered from 1 on the command line like in the header
+    let song = song.unwrap_or(gbs.header.first_song).saturating_sub(1);
+    let mut player = match GbsPlayer::new(&gbs, song, SAMPLE_RATE) {
+        Ok(player) => player,
+        Err(e) => {
+            eprintln!("Failed to play song {}: {:?}", song + 1, e);
+            std::process::exit(2);
+        }
+    };
+    let samples = player.render_seconds(seconds);
+
+    let result = std::fs::File::create(&wav_path)
+        .and_then(|file| wav::write_wav(std::io::BufWriter::new(file), SAMPLE_RATE, 2, &samples));
+    if let Err(e) = result {
+        eprintln!("Failed to write {}: {}", wav_path, e);
+        std::process::exit(2);
+    }
+}
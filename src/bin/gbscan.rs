@@ -0,0 +1,175 @@
+//! Scan a directory of ROMs, printing a JSON or CSV report with a row for
+//! every ROM found, including those in zip and gzip archives. Files are
+//! scanned in parallel and a file that can't be
+//! read or parsed gets a row with its error rather than stopping the scan.
+//!
+//! Usage: `gbscan [--csv] <directory>`
+//!
+//! Exits with 0 once the report is printed, even if some ROMs had errors, and
+//! 2 if the arguments are invalid or the report couldn't be written.
+use gameboy_rom::ips::ChecksumStatus;
+use gameboy_rom::loader::{self, LoadedRom};
+use gameboy_rom::{hash::to_hex, GameBoyRom};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Extensions of archives scanned along with ROMs, compared ignoring case.
+const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "gz"];
+
+/// A row of the report. Kept flat so it can be written as CSV.
+#[derive(Debug, Default, Serialize)]
+struct ScanEntry {
+    path: String,
+    file_size: Option<usize>,
+    title: Option<String>,
+    cart_type: Option<String>,
+    /// The ROM size declared in the header, in bytes.
+    rom_size: Option<usize>,
+    /// The RAM size declared in the header, in bytes.
+    ram_size: Option<usize>,
+    cgb: Option<String>,
+    sgb: Option<bool>,
+    mask_rom_version: Option<u8>,
+    header_checksum_valid: Option<bool>,
+    global_checksum_valid: Option<bool>,
+    /// Why the header failed validation, if it did.
+    validation_error: Option<String>,
+    crc32: Option<String>,
+    md5: Option<String>,
+    sha1: Option<String>,
+    sha256: Option<String>,
+    /// Why the file couldn't be read or parsed, if it couldn't.
+    error: Option<String>,
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut csv = false;
+    let mut directory = None;
+    for arg in &mut args {
+        match arg.as_str() {
+            "--csv" => csv = true,
+            _ if directory.is_none() => directory = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("Unexpected argument {:?}", arg);
+                std::process::exit(2);
+            }
+        }
+    }
+    let directory = if let Some(directory) = directory {
+        directory
+    } else {
+        eprintln!("Usage: gbscan [--csv] <directory>");
+        std::process::exit(2);
+    };
+
+    let mut paths = vec![];
+    let mut entries = vec![];
+    for dir_entry in walkdir::WalkDir::new(&directory).sort_by_file_name() {
+        match dir_entry {
+            Ok(dir_entry) if dir_entry.file_type().is_file() && is_rom(dir_entry.path()) => {
+                paths.push(dir_entry.into_path())
+            }
+            Ok(_) => (),
+            Err(e) => entries.push(ScanEntry {
+                path: e
+                    .path()
+                    .unwrap_or(&directory)
+                    .to_string_lossy()
+                    .into_owned(),
+                error: Some(e.to_string()),
+                ..ScanEntry::default()
+            }),
+        }
+    }
+    entries.par_extend(paths.par_iter().flat_map_iter(|path| {
+        // a bug in the parser shouldn't lose the rest of the report
+        std::panic::catch_unwind(|| scan(path)).unwrap_or_else(|_| {
+            vec![ScanEntry {
+                path: path.to_string_lossy().into_owned(),
+                error: Some("panicked while scanning".to_string()),
+                ..ScanEntry::default()
+            }]
+        })
+    }));
+
+    let stdout = std::io::stdout();
+    let result = if csv {
+        let mut writer = csv::Writer::from_writer(stdout.lock());
+        entries
+            .iter()
+            .try_for_each(|entry| writer.serialize(entry))
+            .and_then(|()| writer.flush().map_err(csv::Error::from))
+            .map_err(|e| e.to_string())
+    } else {
+        serde_json::to_writer_pretty(stdout.lock(), &entries)
+            .map(|()| println!())
+            .map_err(|e| e.to_string())
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to write report: {}", e);
+        std::process::exit(2);
+    }
+}
+
+fn is_rom(path: &Path) -> bool {
+    loader::is_rom_path(path)
+        || path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                ARCHIVE_EXTENSIONS
+                    .iter()
+                    .any(|archive_extension| archive_extension.eq_ignore_ascii_case(extension))
+            })
+}
+
+/// Scan every ROM in the file at `path`.
+fn scan(path: &Path) -> Vec<ScanEntry> {
+    match loader::load_path(path) {
+        Ok(roms) => roms.iter().map(scan_rom).collect(),
+        Err(e) => vec![ScanEntry {
+            path: path.to_string_lossy().into_owned(),
+            error: Some(e.to_string()),
+            ..ScanEntry::default()
+        }],
+    }
+}
+
+fn scan_rom(loaded: &LoadedRom) -> ScanEntry {
+    let mut entry = ScanEntry {
+        path: loaded.name.clone(),
+        ..ScanEntry::default()
+    };
+    let bytes = &loaded.bytes;
+    let rom = GameBoyRom::new(bytes);
+    let hashes = rom.hashes();
+    entry.file_size = Some(bytes.len());
+    entry.crc32 = Some(format!("{:08x}", hashes.crc32));
+    entry.md5 = Some(to_hex(&hashes.md5));
+    entry.sha1 = Some(to_hex(&hashes.sha1));
+    entry.sha256 = Some(to_hex(&hashes.sha256));
+
+    let header = match rom.parse_header() {
+        Ok(header) => header,
+        Err(_) => {
+            // the parser's error includes the rest of the ROM, too big to report
+            entry.error = Some("failed to parse the header".to_string());
+            return entry;
+        }
+    };
+    entry.title = Some(header.game_title.trim_end_matches('\0').to_string());
+    entry.cart_type = Some(format!("{:?}", header.rom_type));
+    entry.rom_size = Some(header.rom_size as usize * 0x4000);
+    entry.ram_size = Some(header.ram_banks as usize * header.ram_bank_size as usize);
+    entry.cgb = Some(format!("{:?}", header.gameboy_color));
+    entry.sgb = Some(header.super_gameboy);
+    entry.mask_rom_version = Some(header.mask_rom_version);
+    if let Some(checksums) = ChecksumStatus::new(bytes) {
+        entry.header_checksum_valid = Some(checksums.header_checksum_valid);
+        entry.global_checksum_valid = Some(checksums.global_checksum_valid);
+    }
+    entry.validation_error = header.validate().err().map(|e| format!("{:?}", e));
+    entry
+}
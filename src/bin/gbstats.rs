@@ -1,75 +1,245 @@
-//! A hacky little program to count the frequency of Gameboy instructions.
-//! Please note that this doesn't handle relative jumps, has no awareness of ROM
-//! banks, and double counts instructions if they were arrived at through a
-//! different entry-point.
+//! Count the instructions in Gameboy ROMs, found by following control flow
+//! with the `Disassembler`.
 //!
-//! This program is for API demonstration purposes only.
-use std::io::Read;
+//! Instructions are counted once each, by mnemonic, for the whole ROM, for
+//! every ROM bank and for every function, along with how much of the ROM was
+//! identified as code.
+//!
+//! Usage: `gbstats [--json] <ROM>`
+//!
+//! `--json` needs the `serde_json` feature.
+//!
+//! Exits with 0 once the statistics are printed and 2 if the arguments are
+//! invalid or the ROM couldn't be loaded.
+use gameboy_rom::disasm::Disassembly;
+use gameboy_rom::{loader, GameBoyRom};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How many of the most common mnemonics and biggest functions are printed.
+const TOP: usize = 10;
+
+/// Instruction counts keyed by mnemonic.
+type Histogram = BTreeMap<&'static str, usize>;
+
+#[derive(Debug, Serialize)]
+struct RomStats {
+    name: String,
+    rom_size: usize,
+    /// Bytes of the ROM decoded as instructions.
+    code_bytes: usize,
+    /// Bytes of the ROM in detected jump tables.
+    jump_table_bytes: usize,
+    /// The percentage of the ROM that's code or jump tables.
+    coverage_percent: f64,
+    instructions: usize,
+    mnemonics: Histogram,
+    groups: BTreeMap<&'static str, usize>,
+    banks: BTreeMap<u16, Stats>,
+    /// Keyed by where the function starts, as `BB:AAAA`.
+    functions: BTreeMap<String, Stats>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    instructions: usize,
+    code_bytes: usize,
+    mnemonics: Histogram,
+}
 
-use gameboy_rom::{opcodes::Opcode, GameBoyRom};
-use std::collections::*;
+impl Stats {
+    fn add(&mut self, mnemonic: &'static str, size: u8) {
+        self.instructions += 1;
+        self.code_bytes += size as usize;
+        *self.mnemonics.entry(mnemonic).or_default() += 1;
+    }
+}
 
 fn main() {
-    let mut args = std::env::args();
-    args.next().unwrap();
-    let rom_file_path = if let Some(arg) = args.next() {
-        arg
+    let mut json = false;
+    let mut rom_file_path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" if cfg!(feature = "serde_json") => json = true,
+            "--json" => {
+                eprintln!("JSON output needs the serde_json feature");
+                std::process::exit(2);
+            }
+            _ if rom_file_path.is_none() => rom_file_path = Some(arg),
+            _ => {
+                eprintln!("Unexpected argument {:?}", arg);
+                std::process::exit(2);
+            }
+        }
+    }
+    let rom_file_path = if let Some(path) = rom_file_path {
+        path
     } else {
         eprintln!("Must supply a path to a gameboy ROM");
-        std::process::exit(-1);
+        std::process::exit(2);
     };
-    let mut file = std::fs::File::open(rom_file_path).expect("gameboy rom file");
-    let mut bytes = vec![];
-    file.read_to_end(&mut bytes).expect("read bytes from file");
-
-    let gbr = GameBoyRom::new(bytes.as_slice());
-    // seen jump locations
-    let mut seen_locations: HashSet<usize> = HashSet::new();
-    // locations we want to analyze
-    let mut to_inspect: VecDeque<usize> = VecDeque::new();
-    // instructions we've seen
-    let mut seen_instructions: HashMap<Opcode, usize> = HashMap::new();
-
-    // init start location
-    seen_locations.insert(0x100);
-    to_inspect.push_back(0x100);
-
-    while let Some(loc) = to_inspect.pop_front() {
-        for o in gbr.get_instructions_at(loc) {
-            match o {
-                Opcode::Call(_, address) | Opcode::Jp(_, address) => {
-                    let adj_adr = address as usize;
-                    if !seen_locations.contains(&adj_adr) {
-                        seen_locations.insert(address as _);
-                        to_inspect.push_back(address as _);
-                    }
-                }
-                _ => (),
+    let roms = match loader::load_path(&rom_file_path) {
+        Ok(roms) => roms,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", rom_file_path, e);
+            std::process::exit(2);
+        }
+    };
+
+    let stats = roms
+        .iter()
+        .map(|rom| {
+            let disassembly = GameBoyRom::new(&rom.bytes).disassembler().run();
+            RomStats::new(&rom.name, rom.bytes.len(), &disassembly)
+        })
+        .collect::<Vec<_>>();
+    if json {
+        print_json(&stats);
+    } else {
+        for (i, rom) in stats.iter().enumerate() {
+            if i > 0 {
+                println!();
             }
-            let ent = seen_instructions.entry(o.clone()).or_default();
-            *ent += 1;
+            rom.print();
         }
     }
+}
 
-    let mut vec = seen_instructions
-        .into_iter()
-        .collect::<Vec<(Opcode, usize)>>();
-    vec.sort_by(|(_, a), (_, b)| b.cmp(&a));
+#[cfg(feature = "serde_json")]
+fn print_json(stats: &[RomStats]) {
+    println!("{}", serde_json::to_string_pretty(stats).unwrap());
+}
+
+#[cfg(not(feature = "serde_json"))]
+fn print_json(_: &[RomStats]) {
+    unreachable!("--json is rejected without the serde_json feature")
+}
 
-    println!("The top 10 most common instructions were:");
-    for i in 0..10 {
+/// The kind of work an instruction does.
+fn group(mnemonic: &str) -> &'static str {
+    match mnemonic {
+        "ld" | "ldh" | "push" | "pop" => "load",
+        "add" | "adc" | "sub" | "sbc" | "inc" | "dec" | "cp" | "daa" => "arithmetic",
+        "and" | "xor" | "or" | "cpl" | "scf" | "ccf" => "logic",
+        "rlca" | "rla" | "rrca" | "rra" | "rlc" | "rrc" | "rl" | "rr" | "sla" | "sra" | "srl"
+        | "swap" => "rotate and shift",
+        "bit" | "res" | "set" => "bit",
+        "jp" | "jr" | "call" | "ret" | "reti" | "rst" => "control flow",
+        _ => "system",
+    }
+}
+
+impl RomStats {
+    fn new(name: &str, rom_size: usize, disassembly: &Disassembly) -> Self {
+        let mut total = Stats::default();
+        let mut banks: BTreeMap<u16, Stats> = BTreeMap::new();
+        for (location, instruction) in &disassembly.instructions {
+            let mnemonic = instruction.opcode.mnemonic();
+            total.add(mnemonic, instruction.size);
+            banks
+                .entry(location.bank)
+                .or_default()
+                .add(mnemonic, instruction.size);
+        }
+
+        let mut functions = BTreeMap::new();
+        for (start, body) in disassembly.functions() {
+            let mut stats = Stats::default();
+            for instruction in body
+                .iter()
+                .map(|location| &disassembly.instructions[location])
+            {
+                stats.add(instruction.opcode.mnemonic(), instruction.size);
+            }
+            functions.insert(start.to_string(), stats);
+        }
+
+        let mut groups = BTreeMap::new();
+        for (mnemonic, count) in &total.mnemonics {
+            *groups.entry(group(mnemonic)).or_default() += count;
+        }
+        let jump_table_bytes = disassembly
+            .jump_tables
+            .values()
+            .map(|entries| entries.len() * 2)
+            .sum::<usize>();
+        let coverage_percent = if rom_size == 0 {
+            0.0
+        } else {
+            (total.code_bytes + jump_table_bytes) as f64 * 100.0 / rom_size as f64
+        };
+
+        Self {
+            name: name.to_string(),
+            rom_size,
+            code_bytes: total.code_bytes,
+            jump_table_bytes,
+            coverage_percent,
+            instructions: total.instructions,
+            mnemonics: total.mnemonics,
+            groups,
+            banks,
+            functions,
+        }
+    }
+
+    fn print(&self) {
+        println!("{}", self.name);
         println!(
-            "{:>2}. {:?} appearing {} times",
-            (i + 1),
-            vec[i].0,
-            vec[i].1
+            "{} instructions in {} bytes of code and {} bytes of jump tables, {:.2}% of the ROM",
+            self.instructions, self.code_bytes, self.jump_table_bytes, self.coverage_percent
         );
+
+        println!();
+        println!("The most common instructions were:");
+        print_histogram(&self.mnemonics, self.instructions);
+
+        println!();
+        println!("By kind:");
+        print_histogram(&self.groups, self.instructions);
+
+        println!();
+        println!("By bank:");
+        for (bank, stats) in &self.banks {
+            println!(
+                "  {:02X}: {:>7} instructions, {:>7} bytes of code",
+                bank, stats.instructions, stats.code_bytes
+            );
+        }
+
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.instructions));
+        println!();
+        println!("The largest of {} functions were:", self.functions.len());
+        for (start, stats) in functions.iter().take(TOP) {
+            let mut mnemonics = stats.mnemonics.iter().collect::<Vec<_>>();
+            mnemonics.sort_by(|(_, a), (_, b)| b.cmp(a));
+            let common = mnemonics
+                .iter()
+                .take(3)
+                .map(|(mnemonic, count)| format!("{} {}", mnemonic, count))
+                .collect::<Vec<_>>();
+            println!(
+                "  {}: {:>5} instructions ({})",
+                start,
+                stats.instructions,
+                common.join(", ")
+            );
+        }
     }
+}
 
-    println!("");
-    println!("The top 10 least common instructions were:");
-    for i in 0..10 {
-        let elem = vec[vec.len() - i - 1];
-        println!("{:>2}. {:?} appearing {} times", (i + 1), elem.0, elem.1);
+/// Print the `TOP` entries of a histogram, most common first.
+fn print_histogram(histogram: &BTreeMap<&'static str, usize>, total: usize) {
+    let mut entries = histogram.iter().collect::<Vec<_>>();
+    entries.sort_by(|(_, a), (_, b)| b.cmp(a));
+    for (i, (name, count)) in entries.iter().take(TOP).enumerate() {
+        println!(
+            "  {:>2}. {:<16} {:>7} ({:.1}%)",
+            i + 1,
+            name,
+            count,
+            **count as f64 * 100.0 / total as f64
+        );
     }
 }
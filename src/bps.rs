@@ -0,0 +1,645 @@
+//! BPS patches, which carry CRC32s of the ROM they apply to, the ROM they
+//! produce and the patch itself.
+//!
+//! A BPS patch is `BPS1`, the source and target sizes, optional metadata and
+//! then actions building the target from the source, the target built so far
+//! or bytes in the patch. Numbers are encoded with a variable length encoding
+//! shared with UPS.
+//!
+//! Applying a patch to the wrong ROM fails with [`PatchError::WrongRom`],
+//! which describes the ROM that was given so a different revision of the
+//! game can be spotted.
+//!
+//! ```
+//! use gameboy_rom::bps::BpsPatch;
+//!
+//! let source = b"The quick brown fox".to_vec();
+//! let target = b"The quick red fox jumps".to_vec();
+//! let patch = BpsPatch::create(&source, &target, "");
+//! assert_eq!(BpsPatch::parse(&patch).unwrap().apply(&source).unwrap(), target);
+//! ```
+
+use crate::ips::PatchReport;
+use crate::{GameBoyRom, MAX_ROM_SIZE};
+
+use std::collections::HashMap;
+use std::fmt;
+
+const MAGIC: &[u8] = b"BPS1";
+/// The three CRC32s at the end of the patch.
+pub(crate) const FOOTER_SIZE: usize = 12;
+/// Matching runs shorter than this are written as bytes in the patch.
+const MIN_SOURCE_READ: usize = 4;
+/// The length of the windows of the source indexed to find moved data.
+const WINDOW: usize = 8;
+/// Every this many bytes of the source is indexed.
+const INDEX_STRIDE: usize = 4;
+
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+
+/// Errors that may occur while reading or applying a BPS or UPS patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch doesn't start with the format's magic bytes.
+    MissingHeader,
+    /// The patch is corrupt, its CRC32 doesn't match.
+    PatchChecksum { expected: u32, actual: u32 },
+    /// The ROM isn't the one the patch was made for.
+    WrongRom(WrongRom),
+    /// The ROM is the one the patch produces.
+    AlreadyApplied,
+    /// The patch is malformed at this offset into it.
+    Malformed(usize),
+    /// The patch declares a ROM bigger than `MAX_ROM_SIZE`.
+    TooLarge(u64),
+    /// The ROM produced doesn't match the CRC32 in the patch.
+    TargetChecksum { expected: u32, actual: u32 },
+}
+
+/// What was expected of the ROM a patch was applied to and what was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrongRom {
+    pub expected_crc32: u32,
+    pub expected_size: usize,
+    pub actual_crc32: u32,
+    pub actual_size: usize,
+    /// From the ROM's header, if it could be parsed.
+    pub title: Option<String>,
+    /// From the ROM's header, if it could be parsed.
+    pub mask_rom_version: Option<u8>,
+}
+
+impl WrongRom {
+    pub(crate) fn new(rom: &[u8], expected_crc32: u32, expected_size: usize) -> Self {
+        let header = GameBoyRom::new(rom).parse_header().ok();
+        Self {
+            expected_crc32,
+            expected_size,
+            actual_crc32: crc32fast::hash(rom),
+            actual_size: rom.len(),
+            title: header
+                .as_ref()
+                .map(|header| header.game_title.trim_end_matches('\0').to_string()),
+            mask_rom_version: header.map(|header| header.mask_rom_version),
+        }
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::MissingHeader => write!(f, "not a patch in the expected format"),
+            PatchError::PatchChecksum { expected, actual } => write!(
+                f,
+                "the patch is corrupt, its CRC32 is {:08X} but should be {:08X}",
+                actual, expected
+            ),
+            PatchError::WrongRom(wrong) => {
+                write!(
+                    f,
+                    "the patch is for a ROM with CRC32 {:08X} ({} bytes) but was given ",
+                    wrong.expected_crc32, wrong.expected_size
+                )?;
+                if let (Some(title), Some(version)) = (&wrong.title, wrong.mask_rom_version) {
+                    write!(f, "{:?} revision {} ", title, version)?;
+                }
+                write!(
+                    f,
+                    "with CRC32 {:08X} ({} bytes)",
+                    wrong.actual_crc32, wrong.actual_size
+                )?;
+                if wrong.mask_rom_version.is_some() && wrong.expected_size == wrong.actual_size {
+                    write!(f, ", the patch may be for a different revision of the game")?;
+                }
+                Ok(())
+            }
+            PatchError::AlreadyApplied => write!(f, "the patch has already been applied"),
+            PatchError::Malformed(offset) => {
+                write!(f, "the patch is malformed at offset {}", offset)
+            }
+            PatchError::TooLarge(size) => write!(
+                f,
+                "the patch is for a ROM of {} bytes, bigger than {}",
+                size, MAX_ROM_SIZE
+            ),
+            PatchError::TargetChecksum { expected, actual } => write!(
+                f,
+                "the patched ROM has CRC32 {:08X} but should have {:08X}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+/// A parsed BPS patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BpsPatch<'a> {
+    pub source_size: usize,
+    pub target_size: usize,
+    /// Usually XML, may be empty.
+    pub metadata: &'a str,
+    pub source_crc32: u32,
+    pub target_crc32: u32,
+    pub patch_crc32: u32,
+    /// The patch without its footer.
+    body: &'a [u8],
+    /// Where the actions start in `body`.
+    actions_offset: usize,
+}
+
+impl<'a> BpsPatch<'a> {
+    /// Parse a BPS patch, checking its CRC32.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, PatchError> {
+        let body_end = verify_footer(bytes, MAGIC)?;
+        let mut reader = Reader::new(&bytes[..body_end], MAGIC.len());
+        let source_size = reader.rom_size()?;
+        let target_size = reader.rom_size()?;
+        let metadata_size = reader.number()? as usize;
+        let metadata_start = reader.position;
+        let metadata = std::str::from_utf8(reader.take(metadata_size)?)
+            .map_err(|_| PatchError::Malformed(metadata_start))?;
+        let footer = read_footer(bytes);
+        Ok(Self {
+            source_size,
+            target_size,
+            metadata,
+            source_crc32: footer[0],
+            target_crc32: footer[1],
+            patch_crc32: footer[2],
+            body: &bytes[..body_end],
+            actions_offset: reader.position,
+        })
+    }
+
+    /// Apply the patch to `source`, checking that it's the ROM the patch was
+    /// made for and that the result is the ROM the patch should produce.
+    pub fn apply(&self, source: &[u8]) -> Result<Vec<u8>, PatchError> {
+        check_source(
+            source,
+            self.source_crc32,
+            self.source_size,
+            self.target_crc32,
+        )?;
+        let mut target = Vec::with_capacity(self.target_size);
+        let mut reader = Reader::new(self.body, self.actions_offset);
+        let mut source_offset = 0;
+        let mut target_offset = 0;
+        while reader.position < self.body.len() {
+            let start = reader.position;
+            let malformed = PatchError::Malformed(start);
+            let action = reader.number()?;
+            let len = (action >> 2) as usize + 1;
+            if len > self.target_size - target.len() {
+                return Err(malformed);
+            }
+            match action & 3 {
+                SOURCE_READ => {
+                    let bytes = source
+                        .get(target.len()..target.len() + len)
+                        .ok_or(malformed)?;
+                    target.extend_from_slice(bytes);
+                }
+                TARGET_READ => target.extend_from_slice(reader.take(len)?),
+                SOURCE_COPY => {
+                    source_offset = reader.relative(source_offset)?;
+                    let bytes = source
+                        .get(source_offset..source_offset + len)
+                        .ok_or(malformed)?;
+                    target.extend_from_slice(bytes);
+                    source_offset += len;
+                }
+                // target copy
+                _ => {
+                    target_offset = reader.relative(target_offset)?;
+                    // the copy may overlap what it writes, so go byte by byte
+                    for _ in 0..len {
+                        let byte = *target.get(target_offset).ok_or_else(|| malformed.clone())?;
+                        target.push(byte);
+                        target_offset += 1;
+                    }
+                }
+            }
+        }
+        check_target(&target, self.target_crc32)?;
+        Ok(target)
+    }
+
+    /// Create a patch turning `source` into `target`.
+    ///
+    /// Unchanged data is copied from the same offset in the source, and data
+    /// moved within the ROM is found by indexing the source. Data repeated
+    /// within the target isn't searched for.
+    pub fn create(source: &[u8], target: &[u8], metadata: &str) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_number(&mut out, source.len() as u64);
+        write_number(&mut out, target.len() as u64);
+        write_number(&mut out, metadata.len() as u64);
+        out.extend_from_slice(metadata.as_bytes());
+
+        let mut index: HashMap<&[u8], usize> = HashMap::new();
+        for offset in (0..source.len().saturating_sub(WINDOW - 1)).step_by(INDEX_STRIDE) {
+            index
+                .entry(&source[offset..offset + WINDOW])
+                .or_insert(offset);
+        }
+
+        let mut literal_start = None;
+        let mut source_offset = 0;
+        let mut position = 0;
+        while position < target.len() {
+            let same = matching_len(source.get(position..).unwrap_or(&[]), &target[position..]);
+            let moved = target
+                .get(position..position + WINDOW)
+                .and_then(|window| index.get(window))
+                .map(|&offset| (offset, matching_len(&source[offset..], &target[position..])));
+            let action = if same >= MIN_SOURCE_READ {
+                Some((SOURCE_READ, same))
+            } else {
+                moved.map(|(_, len)| (SOURCE_COPY, len))
+            };
+            let (command, len) = match action {
+                Some(action) => action,
+                None => {
+                    literal_start.get_or_insert(position);
+                    position += 1;
+                    continue;
+                }
+            };
+            if let Some(start) = literal_start.take() {
+                write_action(&mut out, TARGET_READ, position - start);
+                out.extend_from_slice(&target[start..position]);
+            }
+            write_action(&mut out, command, len);
+            if let (SOURCE_COPY, Some((offset, _))) = (command, moved) {
+                write_relative(&mut out, source_offset, offset);
+                source_offset = offset + len;
+            }
+            position += len;
+        }
+        if let Some(start) = literal_start {
+            write_action(&mut out, TARGET_READ, target.len() - start);
+            out.extend_from_slice(&target[start..]);
+        }
+
+        write_footer(&mut out, source, target);
+        out
+    }
+}
+
+/// Apply a BPS patch to `rom`, returning the patched ROM and what changed.
+pub fn patch_rom(rom: &[u8], patch: &[u8]) -> Result<(Vec<u8>, PatchReport), PatchError> {
+    let patched = BpsPatch::parse(patch)?.apply(rom)?;
+    let report = PatchReport::new(rom, &patched);
+    Ok((patched, report))
+}
+
+fn write_action(out: &mut Vec<u8>, command: u64, len: usize) {
+    write_number(out, ((len as u64 - 1) << 2) | command);
+}
+
+/// Write the signed distance from `from` to `to`.
+fn write_relative(out: &mut Vec<u8>, from: usize, to: usize) {
+    let number = if to >= from {
+        ((to - from) as u64) << 1
+    } else {
+        (((from - to) as u64) << 1) | 1
+    };
+    write_number(out, number);
+}
+
+fn matching_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Reads the numbers and bytes in a BPS or UPS patch.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pub position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], position: usize) -> Self {
+        Self { bytes, position }
+    }
+
+    pub fn byte(&mut self) -> Result<u8, PatchError> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or(PatchError::Malformed(self.position))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.saturating_add(len))
+            .ok_or(PatchError::Malformed(self.position))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    /// Read a number, 7 bits per byte with the top bit marking the last byte.
+    pub fn number(&mut self) -> Result<u64, PatchError> {
+        let start = self.position;
+        let mut number: u64 = 0;
+        let mut shift: u64 = 1;
+        loop {
+            let byte = self.byte()?;
+            number = (byte as u64 & 0x7F)
+                .checked_mul(shift)
+                .and_then(|value| number.checked_add(value))
+                .ok_or(PatchError::Malformed(start))?;
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+            shift = shift.checked_mul(128).ok_or(PatchError::Malformed(start))?;
+            number = number
+                .checked_add(shift)
+                .ok_or(PatchError::Malformed(start))?;
+        }
+    }
+
+    /// Read the size of a ROM, which can't be bigger than `MAX_ROM_SIZE`.
+    pub fn rom_size(&mut self) -> Result<usize, PatchError> {
+        let size = self.number()?;
+        if size > MAX_ROM_SIZE {
+            return Err(PatchError::TooLarge(size));
+        }
+        Ok(size as usize)
+    }
+
+    /// Read a signed distance and apply it to `offset`.
+    fn relative(&mut self, offset: usize) -> Result<usize, PatchError> {
+        let start = self.position;
+        let number = self.number()?;
+        let distance = (number >> 1) as usize;
+        if number & 1 == 0 {
+            offset.checked_add(distance)
+        } else {
+            offset.checked_sub(distance)
+        }
+        .ok_or(PatchError::Malformed(start))
+    }
+}
+
+pub(crate) fn write_number(out: &mut Vec<u8>, mut number: u64) {
+    loop {
+        let byte = (number & 0x7F) as u8;
+        number >>= 7;
+        if number == 0 {
+            out.push(0x80 | byte);
+            return;
+        }
+        out.push(byte);
+        number -= 1;
+    }
+}
+
+/// Check the magic bytes and the patch's CRC32, returning where the footer
+/// starts.
+pub(crate) fn verify_footer(bytes: &[u8], magic: &[u8]) -> Result<usize, PatchError> {
+    if !bytes.starts_with(magic) {
+        return Err(PatchError::MissingHeader);
+    }
+    if bytes.len() < magic.len() + FOOTER_SIZE {
+        return Err(PatchError::Malformed(bytes.len()));
+    }
+    let expected = read_footer(bytes)[2];
+    let actual = crc32fast::hash(&bytes[..bytes.len() - 4]);
+    if expected != actual {
+        return Err(PatchError::PatchChecksum { expected, actual });
+    }
+    Ok(bytes.len() - FOOTER_SIZE)
+}
+
+/// The source, target and patch CRC32s.
+pub(crate) fn read_footer(bytes: &[u8]) -> [u32; 3] {
+    let footer = &bytes[bytes.len() - FOOTER_SIZE..];
+    let read = |idx: usize| {
+        u32::from_le_bytes([
+            footer[idx * 4],
+            footer[idx * 4 + 1],
+            footer[idx * 4 + 2],
+            footer[idx * 4 + 3],
+        ])
+    };
+    [read(0), read(1), read(2)]
+}
+
+pub(crate) fn write_footer(out: &mut Vec<u8>, source: &[u8], target: &[u8]) {
+    out.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+    out.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+    let patch_crc32 = crc32fast::hash(out);
+    out.extend_from_slice(&patch_crc32.to_le_bytes());
+}
+
+/// Check that the ROM is the one a patch expects.
+pub(crate) fn check_source(
+    rom: &[u8],
+    source_crc32: u32,
+    source_size: usize,
+    target_crc32: u32,
+) -> Result<(), PatchError> {
+    let crc32 = crc32fast::hash(rom);
+    if crc32 == source_crc32 && rom.len() == source_size {
+        Ok(())
+    } else if crc32 == target_crc32 {
+        Err(PatchError::AlreadyApplied)
+    } else {
+        Err(PatchError::WrongRom(WrongRom::new(
+            rom,
+            source_crc32,
+            source_size,
+        )))
+    }
+}
+
+pub(crate) fn check_target(rom: &[u8], target_crc32: u32) -> Result<(), PatchError> {
+    let actual = crc32fast::hash(rom);
+    if actual != target_crc32 {
+        return Err(PatchError::TargetChecksum {
+            expected: target_crc32,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The start of a patch from `source` to a target of `target_size`.
+    fn header(source: &[u8], target_size: u64) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_number(&mut out, source.len() as u64);
+        write_number(&mut out, target_size);
+        write_number(&mut out, 0);
+        out
+    }
+
+    #[test]
+    fn huge_target_size() {
+        let mut patch = header(b"", 1 << 61);
+        write_footer(&mut patch, b"", b"");
+        assert_eq!(BpsPatch::parse(&patch), Err(PatchError::TooLarge(1 << 61)));
+
+        let mut patch = header(b"", MAX_ROM_SIZE);
+        write_footer(&mut patch, b"", b"");
+        assert!(BpsPatch::parse(&patch).is_ok());
+    }
+
+    #[test]
+    fn target_copy_past_target_size() {
+        let source = b"ABCD";
+        let mut patch = header(source, 4);
+        write_action(&mut patch, TARGET_READ, 1);
+        patch.push(b'A');
+        let copy = patch.len();
+        // target copy
+        write_action(&mut patch, 3, 1 << 40);
+        write_relative(&mut patch, 0, 0);
+        write_footer(&mut patch, source, b"AAAA");
+        assert_eq!(
+            BpsPatch::parse(&patch).unwrap().apply(source),
+            Err(PatchError::Malformed(copy))
+        );
+    }
+
+    /// A 32KB ROM with a parsable header.
+    fn rom(mask_rom_version: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x138].copy_from_slice(b"GAME");
+        rom[0x14C] = mask_rom_version;
+        rom
+    }
+
+    #[test]
+    fn wrong_rom() {
+        let source = rom(0);
+        let mut target = source.clone();
+        target[0x200] = 1;
+        let patch = BpsPatch::create(&source, &target, "");
+        let patch = BpsPatch::parse(&patch).unwrap();
+
+        let other = rom(1);
+        let error = patch.apply(&other).unwrap_err();
+        assert_eq!(
+            error,
+            PatchError::WrongRom(WrongRom {
+                expected_crc32: crc32fast::hash(&source),
+                expected_size: 0x8000,
+                actual_crc32: crc32fast::hash(&other),
+                actual_size: 0x8000,
+                title: Some("GAME".to_string()),
+                mask_rom_version: Some(1),
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "the patch is for a ROM with CRC32 {:08X} (32768 bytes) but was given \
+                 \"GAME\" revision 1 with CRC32 {:08X} (32768 bytes), the patch may be \
+                 for a different revision of the game",
+                crc32fast::hash(&source),
+                crc32fast::hash(&other)
+            )
+        );
+
+        // without a header or with a different size it's just the wrong ROM
+        let error = patch.apply(b"not a ROM").unwrap_err();
+        assert!(error.to_string().ends_with(&format!(
+            "but was given with CRC32 {:08X} (9 bytes)",
+            crc32fast::hash(b"not a ROM")
+        )));
+        let error = patch.apply(&other[..0x4000]).unwrap_err();
+        assert!(!error.to_string().contains("revision of the game"));
+    }
+
+    #[test]
+    fn already_applied() {
+        let source = rom(0);
+        let target = rom(1);
+        let patch = BpsPatch::create(&source, &target, "");
+        assert_eq!(
+            BpsPatch::parse(&patch).unwrap().apply(&target),
+            Err(PatchError::AlreadyApplied)
+        );
+    }
+
+    #[test]
+    fn patch_checksum() {
+        let mut patch = BpsPatch::create(b"ABCD", b"ABCE", "");
+        let expected = read_footer(&patch)[2];
+        patch[MAGIC.len()] ^= 0x01;
+        let actual = crc32fast::hash(&patch[..patch.len() - 4]);
+        assert_eq!(
+            BpsPatch::parse(&patch),
+            Err(PatchError::PatchChecksum { expected, actual })
+        );
+    }
+
+    #[test]
+    fn target_checksum() {
+        let source = b"ABCD";
+        let mut patch = header(source, 4);
+        write_action(&mut patch, TARGET_READ, 4);
+        patch.extend_from_slice(b"WXYZ");
+        write_footer(&mut patch, source, b"ABCE");
+        assert_eq!(
+            BpsPatch::parse(&patch).unwrap().apply(source),
+            Err(PatchError::TargetChecksum {
+                expected: crc32fast::hash(b"ABCE"),
+                actual: crc32fast::hash(b"WXYZ"),
+            })
+        );
+    }
+
+    #[test]
+    fn source_copy() {
+        // swap the halves of the source
+        let source = b"ABCDEFGH";
+        let mut patch = header(source, 8);
+        write_action(&mut patch, SOURCE_COPY, 4);
+        write_relative(&mut patch, 0, 4);
+        write_action(&mut patch, SOURCE_COPY, 4);
+        // relative to the end of the last copy
+        write_relative(&mut patch, 8, 0);
+        write_footer(&mut patch, source, b"EFGHABCD");
+        assert_eq!(
+            BpsPatch::parse(&patch).unwrap().apply(source).unwrap(),
+            b"EFGHABCD"
+        );
+
+        // create finds data moved within the ROM
+        let source = (0..=255).cycle().take(0x1000).collect::<Vec<u8>>();
+        let mut target = source.clone();
+        target[0x800..0x900].copy_from_slice(&source[0x10..0x110]);
+        let patch = BpsPatch::create(&source, &target, "");
+        assert!(patch.len() < 0x100);
+        assert_eq!(
+            BpsPatch::parse(&patch).unwrap().apply(&source).unwrap(),
+            target
+        );
+    }
+
+    #[test]
+    fn overlapping_target_copy() {
+        let source = b"";
+        let mut patch = header(source, 9);
+        write_action(&mut patch, TARGET_READ, 3);
+        patch.extend_from_slice(b"ABC");
+        // reads the bytes it writes
+        write_action(&mut patch, 3, 6);
+        write_relative(&mut patch, 0, 0);
+        write_footer(&mut patch, source, b"ABCABCABC");
+        assert_eq!(
+            BpsPatch::parse(&patch).unwrap().apply(source).unwrap(),
+            b"ABCABCABC"
+        );
+    }
+}
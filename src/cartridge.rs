@@ -0,0 +1,870 @@
+//! Memory bank controllers (MBCs) making the cartridge's ROM and RAM visible
+//! to the CPU.
+//!
+//! A [`Cartridge`] is a [`Bus`] covering the ROM area (0x0000-0x7FFF) and the
+//! external RAM area (0xA000-0xBFFF). Writes to the ROM area are used to
+//! configure the MBC.
+//!
+//! MBC behavior is based on the [Pan Docs].
+//!
+//! ```
+//! use gameboy_rom::cartridge::Mbc1;
+//! use gameboy_rom::cpu::Bus;
+//!
+//! let mut rom = vec![0; 4 * 0x4000];
+//! rom[0x4000] = 1;
+//! rom[3 * 0x4000] = 3;
+//! let mut mbc = Mbc1::new(rom, 0);
+//! mbc.write(0x2000, 3);
+//! assert_eq!(mbc.read(0x4000), 3);
+//! // bank 0 can't be selected, it's remapped to bank 1
+//! mbc.write(0x2000, 0);
+//! assert_eq!(mbc.read(0x4000), 1);
+//! ```
+//!
+//! [Pan Docs]: https://gbdev.io/pandocs/MBCs.html
+
+use crate::cpu::Bus;
+use crate::header::{RomHeader, RomType};
+use crate::rtc::{Rtc, SystemTimeSource, TimeSource};
+use crate::GameBoyRom;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// The size of a ROM bank in bytes.
+pub const ROM_BANK_SIZE: usize = 0x4000;
+/// The size of a RAM bank in bytes.
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// A cartridge as seen by the CPU.
+pub trait Cartridge: Bus {
+    /// The contents of the cartridge's external RAM.
+    fn ram(&self) -> &[u8];
+    /// Mutable access to the external RAM, e.g. for loading a save.
+    fn ram_mut(&mut self) -> &mut [u8];
+    /// The ROM bank currently mapped into 0x4000-0x7FFF.
+    fn rom_bank(&self) -> usize;
+    /// Whether the rumble motor is currently on.
+    fn rumble(&self) -> bool {
+        false
+    }
+    /// The cartridge's real time clock, if it has one.
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+    /// Mutable access to the real time clock, e.g. for loading a save.
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+    /// The MBC registers, RAM and clock encoded for a save state.
+    fn save_state(&self) -> Vec<u8>;
+    /// Restore what `save_state` returned, keeping the ROM.
+    fn load_state(&mut self, state: &[u8]) -> Result<(), CartridgeError>;
+}
+
+/// Errors that may occur while creating a `Cartridge`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The ROM's MBC isn't supported.
+    UnsupportedRomType(RomType),
+    /// A save state's cartridge data doesn't belong to this kind of MBC.
+    InvalidState,
+}
+
+/// Create the right `Cartridge` for the ROM based on its header, a real time
+/// clock follows the host's wall clock.
+pub fn from_rom(
+    rom: &GameBoyRom,
+    header: &RomHeader,
+) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    from_rom_with_time_source(rom, header, system_time_source())
+}
+
+/// Create the right `Cartridge` for the ROM based on its header, a real time
+/// clock is driven by `time_source`.
+pub fn from_rom_with_time_source(
+    rom: &GameBoyRom,
+    header: &RomHeader,
+    time_source: Rc<dyn TimeSource>,
+) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    let rom_data = rom.rom_data.to_vec();
+    let ram_size = header.ram_banks as usize * header.ram_bank_size as usize;
+    Ok(match header.rom_type {
+        RomType::RomOnly | RomType::RomRam | RomType::RomRamBattery => {
+            Box::new(RomOnly::new(rom_data, ram_size))
+        }
+        RomType::Mbc1 | RomType::Mbc1Ram | RomType::Mbc1RamBattery => {
+            if Mbc1::is_multicart(&rom_data) {
+                Box::new(Mbc1::multicart(rom_data, ram_size))
+            } else {
+                Box::new(Mbc1::new(rom_data, ram_size))
+            }
+        }
+        RomType::Mbc2 | RomType::Mbc2Battery => Box::new(Mbc2::new(rom_data)),
+        RomType::Mbc3TimerBattery | RomType::Mbc3TimerRamBattery => {
+            Box::new(Mbc3::with_rtc(rom_data, ram_size, time_source))
+        }
+        RomType::Mbc3 | RomType::Mbc3Ram | RomType::Mbc3RamBattery => {
+            Box::new(Mbc3::new(rom_data, ram_size))
+        }
+        RomType::Mbc5 | RomType::Mbc5Ram | RomType::Mbc5RamBattery => {
+            Box::new(Mbc5::new(rom_data, ram_size, false))
+        }
+        RomType::Mbc5Rumble | RomType::Mbc5RumbleSram | RomType::Mbc5RumbleSramBattery => {
+            Box::new(Mbc5::new(rom_data, ram_size, true))
+        }
+        otherwise => return Err(CartridgeError::UnsupportedRomType(otherwise)),
+    })
+}
+
+fn system_time_source() -> Rc<dyn TimeSource> {
+    Rc::new(SystemTimeSource)
+}
+
+fn encode_state<T: Serialize>(mbc: &T) -> Vec<u8> {
+    bincode::serialize(mbc).expect("MBC state can always be encoded")
+}
+
+fn decode_state<T: DeserializeOwned>(state: &[u8]) -> Result<T, CartridgeError> {
+    bincode::deserialize(state).map_err(|_| CartridgeError::InvalidState)
+}
+
+/// Pad the ROM to a whole number of banks (and at least 2 banks).
+fn pad_rom(mut rom: Vec<u8>) -> Vec<u8> {
+    let banks = rom.len().div_ceil(ROM_BANK_SIZE).max(2);
+    rom.resize(banks * ROM_BANK_SIZE, 0xFF);
+    rom
+}
+
+/// Read from a banked ROM, wrapping bank numbers that are out of range.
+fn read_banked(rom: &[u8], bank: usize, address: u16) -> u8 {
+    let banks = rom.len() / ROM_BANK_SIZE;
+    rom[(bank % banks) * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1))]
+}
+
+/// The offset into banked RAM, wrapping bank numbers that are out of range.
+fn ram_offset(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((bank * RAM_BANK_SIZE + (address as usize - 0xA000)) % ram.len())
+}
+
+/// A cartridge with no MBC, optionally with up to 8KB of RAM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RomOnly {
+    #[serde(skip)]
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Self {
+            rom: pad_rom(rom),
+            ram: vec![0; ram_size.min(RAM_BANK_SIZE)],
+        }
+    }
+}
+
+impl Bus for RomOnly {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF => read_banked(&self.rom, address as usize / ROM_BANK_SIZE, address),
+            0xA000..=0xBFFF => ram_offset(&self.ram, 0, address)
+                .map(|offset| self.ram[offset])
+                .unwrap_or(0xFF),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if let 0xA000..=0xBFFF = address {
+            if let Some(offset) = ram_offset(&self.ram, 0, address) {
+                self.ram[offset] = value;
+            }
+        }
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        encode_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), CartridgeError> {
+        let state: Self = decode_state(state)?;
+        *self = Self {
+            rom: std::mem::take(&mut self.rom),
+            ..state
+        };
+        Ok(())
+    }
+}
+
+/// The MBC1, supporting up to 2MB of ROM and 32KB of RAM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mbc1 {
+    #[serde(skip)]
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// The 5 bit register at 0x2000-0x3FFF.
+    bank1: u8,
+    /// The 2 bit register at 0x4000-0x5FFF.
+    bank2: u8,
+    /// The banking mode selected at 0x6000-0x7FFF.
+    advanced_banking: bool,
+    /// Multicarts only connect 4 bits of `bank1`.
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Self {
+            rom: pad_rom(rom),
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_banking: false,
+            multicart: false,
+        }
+    }
+
+    /// Create an MBC1M as used by multi-game compilations, where the
+    /// upper bit of the ROM bank register isn't connected.
+    pub fn multicart(rom: Vec<u8>, ram_size: usize) -> Self {
+        Self {
+            multicart: true,
+            ..Self::new(rom, ram_size)
+        }
+    }
+
+    /// Multicarts are 1MB and have a second Nintendo logo at the start of
+    /// their second game, in bank 0x10.
+    pub fn is_multicart(rom: &[u8]) -> bool {
+        const LOGO: std::ops::Range<usize> = 0x104..0x134;
+        let second_game = 0x10 * ROM_BANK_SIZE;
+        rom.len() == 64 * ROM_BANK_SIZE
+            && rom[LOGO] == rom[second_game + LOGO.start..second_game + LOGO.end]
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_banking {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl Bus for Mbc1 {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => {
+                let bank = if self.advanced_banking {
+                    (self.bank2 as usize) << self.bank2_shift()
+                } else {
+                    0
+                };
+                read_banked(&self.rom, bank, address)
+            }
+            0x4000..=0x7FFF => read_banked(&self.rom, self.rom_bank(), address),
+            0xA000..=0xBFFF if self.ram_enabled => ram_offset(&self.ram, self.ram_bank(), address)
+                .map(|offset| self.ram[offset])
+                .unwrap_or(0xFF),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // the zero check happens on all 5 bits even if fewer are used
+            0x2000..=0x3FFF => self.bank1 = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            0x6000..=0x7FFF => self.advanced_banking = value & 0x01 != 0,
+            0xA000..=0xBFFF if self.ram_enabled => {
+                if let Some(offset) = ram_offset(&self.ram, self.ram_bank(), address) {
+                    self.ram[offset] = value;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Cartridge for Mbc1 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        let bank1 = if self.multicart {
+            self.bank1 & 0x0F
+        } else {
+            self.bank1
+        };
+        ((self.bank2 as usize) << self.bank2_shift()) | bank1 as usize
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        encode_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), CartridgeError> {
+        let state: Self = decode_state(state)?;
+        *self = Self {
+            rom: std::mem::take(&mut self.rom),
+            ..state
+        };
+        Ok(())
+    }
+}
+
+/// The MBC2, supporting up to 256KB of ROM with 512 half-bytes of built-in
+/// RAM.
+///
+/// ```
+/// use gameboy_rom::cartridge::Mbc2;
+/// use gameboy_rom::cpu::Bus;
+///
+/// let mut mbc = Mbc2::new(vec![0; 4 * 0x4000]);
+/// // address bit 8 picks between RAM enable and ROM bank select
+/// mbc.write(0x0000, 0x0A);
+/// mbc.write(0xA000, 0xAB);
+/// // only the lower 4 bits are stored, the upper bits read as 1s
+/// assert_eq!(mbc.read(0xA000), 0xFB);
+/// // the 512 bytes are mirrored throughout 0xA000-0xBFFF
+/// assert_eq!(mbc.read(0xA200), 0xFB);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mbc2 {
+    #[serde(skip)]
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    /// The number of half-bytes of RAM built in to the MBC2.
+    pub const RAM_SIZE: usize = 512;
+
+    pub fn new(rom: Vec<u8>) -> Self {
+        Self {
+            rom: pad_rom(rom),
+            ram: vec![0; Self::RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Bus for Mbc2 {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_banked(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_banked(&self.rom, self.rom_bank as usize, address),
+            0xA000..=0xBFFF if self.ram_enabled => {
+                0xF0 | self.ram[address as usize & (Self::RAM_SIZE - 1)]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0000..=0x3FFF => self.rom_bank = (value & 0x0F).max(1),
+            0xA000..=0xBFFF if self.ram_enabled => {
+                self.ram[address as usize & (Self::RAM_SIZE - 1)] = value & 0x0F;
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        encode_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), CartridgeError> {
+        let state: Self = decode_state(state)?;
+        *self = Self {
+            rom: std::mem::take(&mut self.rom),
+            ..state
+        };
+        Ok(())
+    }
+}
+
+/// The MBC3, supporting up to 2MB of ROM, 32KB of RAM and optionally a real
+/// time clock.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mbc3 {
+    #[serde(skip)]
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    /// 0x00-0x03 select a RAM bank, 0x08-0x0C select an RTC register.
+    ram_bank: u8,
+    rtc: Option<Rtc>,
+    #[serde(skip, default = "system_time_source")]
+    time_source: Rc<dyn TimeSource>,
+}
+
+impl Mbc3 {
+    /// Create an MBC3 without a clock.
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Self {
+            rom: pad_rom(rom),
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: None,
+            time_source: Rc::new(SystemTimeSource),
+        }
+    }
+
+    /// Create an MBC3 with a clock driven by `time_source`.
+    pub fn with_rtc(rom: Vec<u8>, ram_size: usize, time_source: Rc<dyn TimeSource>) -> Self {
+        Self {
+            rtc: Some(Rtc::new(time_source.now())),
+            time_source,
+            ..Self::new(rom, ram_size)
+        }
+    }
+}
+
+impl Bus for Mbc3 {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_banked(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_banked(&self.rom, self.rom_bank as usize, address),
+            0xA000..=0xBFFF if self.ram_enabled => match (self.ram_bank, &self.rtc) {
+                (0x00..=0x03, _) => ram_offset(&self.ram, self.ram_bank as usize, address)
+                    .map(|offset| self.ram[offset])
+                    .unwrap_or(0xFF),
+                (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_bank),
+                _ => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value,
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value, self.time_source.now());
+                }
+            }
+            0xA000..=0xBFFF if self.ram_enabled => match (self.ram_bank, &mut self.rtc) {
+                (0x00..=0x03, _) => {
+                    if let Some(offset) = ram_offset(&self.ram, self.ram_bank as usize, address) {
+                        self.ram[offset] = value;
+                    }
+                }
+                (0x08..=0x0C, Some(rtc)) => rtc.write(self.ram_bank, value, self.time_source.now()),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        encode_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), CartridgeError> {
+        let state: Self = decode_state(state)?;
+        *self = Self {
+            rom: std::mem::take(&mut self.rom),
+            time_source: self.time_source.clone(),
+            ..state
+        };
+        Ok(())
+    }
+}
+
+/// The MBC5, supporting up to 8MB of ROM, 128KB of RAM and a rumble motor.
+///
+/// ```
+/// use gameboy_rom::cartridge::{Cartridge, Mbc5};
+/// use gameboy_rom::cpu::Bus;
+///
+/// let mut mbc = Mbc5::new(vec![0; 4 * 0x4000], 0x2000, true);
+/// // unlike the other MBCs bank 0 can be mapped to 0x4000-0x7FFF
+/// mbc.write(0x2000, 0);
+/// assert_eq!(mbc.rom_bank(), 0);
+/// // on rumble carts bit 3 of the RAM bank register drives the motor
+/// mbc.write(0x4000, 0x08);
+/// assert!(mbc.rumble());
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mbc5 {
+    #[serde(skip)]
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// The 9 bit ROM bank number.
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        Self {
+            rom: pad_rom(rom),
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl Bus for Mbc5 {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_banked(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_banked(&self.rom, self.rom_bank as usize, address),
+            0xA000..=0xBFFF if self.ram_enabled => {
+                ram_offset(&self.ram, self.ram_bank as usize, address)
+                    .map(|offset| self.ram[offset])
+                    .unwrap_or(0xFF)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 0x01) << 8)
+            }
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = value & 0x08 != 0;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            }
+            0xA000..=0xBFFF if self.ram_enabled => {
+                if let Some(offset) = ram_offset(&self.ram, self.ram_bank as usize, address) {
+                    self.ram[offset] = value;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        encode_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), CartridgeError> {
+        let state: Self = decode_state(state)?;
+        *self = Self {
+            rom: std::mem::take(&mut self.rom),
+            ..state
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtc::ManualTimeSource;
+
+    /// A ROM whose banks start with their bank number, low byte first.
+    fn numbered_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE..][..2].copy_from_slice(&(bank as u16).to_le_bytes());
+        }
+        rom
+    }
+
+    /// The bank mapped at `address`, read from a `numbered_rom`.
+    fn bank_at(cartridge: &mut impl Bus, address: u16) -> usize {
+        u16::from_le_bytes([cartridge.read(address), cartridge.read(address + 1)]) as usize
+    }
+
+    #[test]
+    fn rom_only() {
+        let mut cartridge = RomOnly::new(numbered_rom(2), 0x2000);
+        assert_eq!(bank_at(&mut cartridge, 0x0000), 0);
+        assert_eq!(bank_at(&mut cartridge, 0x4000), 1);
+        // there's no MBC to configure
+        cartridge.write(0x2000, 0x05);
+        cartridge.write(0x0000, 0xFF);
+        assert_eq!(bank_at(&mut cartridge, 0x4000), 1);
+        assert_eq!(cartridge.read(0x0000), 0);
+        assert_eq!(cartridge.rom_bank(), 1);
+        // RAM is always enabled
+        cartridge.write(0xA123, 0x42);
+        assert_eq!(cartridge.read(0xA123), 0x42);
+        assert_eq!(cartridge.ram()[0x123], 0x42);
+
+        let mut cartridge = RomOnly::new(numbered_rom(2), 0);
+        cartridge.write(0xA000, 0x42);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn mbc1_bank_0_remapping() {
+        let mut mbc = Mbc1::new(numbered_rom(128), 0);
+        for bank2 in 0..4 {
+            mbc.write(0x4000, bank2);
+            mbc.write(0x2000, 0);
+            let expected = ((bank2 as usize) << 5) + 1;
+            assert_eq!(mbc.rom_bank(), expected);
+            assert_eq!(bank_at(&mut mbc, 0x4000), expected);
+        }
+        // only the lower 5 bits are checked for 0
+        mbc.write(0x4000, 0);
+        mbc.write(0x2000, 0xE0);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 1);
+        mbc.write(0x2000, 0x1F);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x1F);
+    }
+
+    #[test]
+    fn mbc1_advanced_banking() {
+        let mut mbc = Mbc1::new(numbered_rom(128), 0x8000);
+        mbc.write(0x4000, 2);
+        // in mode 0 bank 0 is always at 0x0000-0x3FFF
+        assert_eq!(bank_at(&mut mbc, 0x0000), 0);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x41);
+
+        mbc.write(0x6000, 1);
+        assert_eq!(bank_at(&mut mbc, 0x0000), 0x40);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x41);
+        // and bank2 selects the RAM bank
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x22);
+        assert_eq!(mbc.ram()[2 * RAM_BANK_SIZE], 0x22);
+
+        mbc.write(0x6000, 0);
+        assert_eq!(bank_at(&mut mbc, 0x0000), 0);
+        assert_eq!(mbc.read(0xA000), 0);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn mbc1_multicart() {
+        let mut rom = numbered_rom(64);
+        let logo = [0xCE, 0xED, 0x66, 0x66];
+        rom[0x104..0x108].copy_from_slice(&logo);
+        assert!(!Mbc1::is_multicart(&rom));
+        rom[0x10 * ROM_BANK_SIZE + 0x104..][..4].copy_from_slice(&logo);
+        assert!(Mbc1::is_multicart(&rom));
+        // only 1MB compilations are multicarts
+        let mut bigger = rom.clone();
+        bigger.resize(128 * ROM_BANK_SIZE, 0);
+        assert!(!Mbc1::is_multicart(&bigger));
+
+        let mut mbc = Mbc1::multicart(rom, 0);
+        // bank2 selects the game, 4 bits of bank1 select a bank in it
+        mbc.write(0x4000, 1);
+        mbc.write(0x2000, 0x02);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x12);
+        mbc.write(0x2000, 0x0F);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x1F);
+        // 0x10 isn't remapped as the zero check sees all 5 bits
+        mbc.write(0x2000, 0x10);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x10);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x11);
+        // mode 1 puts the selected game's first bank at 0x0000
+        mbc.write(0x6000, 1);
+        mbc.write(0x4000, 3);
+        assert_eq!(bank_at(&mut mbc, 0x0000), 0x30);
+    }
+
+    #[test]
+    fn mbc2_address_bit_8() {
+        let mut mbc = Mbc2::new(numbered_rom(16));
+        // bit 8 set selects the ROM bank, even with the RAM enable value
+        mbc.write(0x0100, 0x0A);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x0A);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        // bit 8 clear enables RAM, even in the upper half
+        mbc.write(0x2000, 0x0A);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x0A);
+        mbc.write(0xA000, 0x05);
+        assert_eq!(mbc.read(0xA000), 0xF5);
+        mbc.write(0x3F00, 0x00);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 1);
+        mbc.write(0x2100, 0x1F);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x0F);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn mbc3_bank_0_remapping() {
+        let mut mbc = Mbc3::new(numbered_rom(128), 0);
+        mbc.write(0x2000, 0);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 1);
+        mbc.write(0x2000, 0x7F);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x7F);
+        // all 7 bits are checked for 0
+        mbc.write(0x2000, 0x80);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 1);
+        mbc.write(0x2000, 0x20);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x20);
+    }
+
+    #[test]
+    fn mbc3_rtc_register_select() {
+        let time = ManualTimeSource::new(1000);
+        let mut mbc = Mbc3::with_rtc(numbered_rom(4), 0x8000, Rc::new(time.clone()));
+        mbc.write(0x0000, 0x0A);
+        let values = [0x05, 0x06, 0x07, 0x08, 0x01];
+        for (register, value) in (0x08..=0x0C).zip(values.iter()) {
+            mbc.write(0x4000, register);
+            mbc.write(0xA000, *value);
+        }
+        mbc.write(0x6000, 0);
+        mbc.write(0x6000, 1);
+        for (register, value) in (0x08..=0x0C).zip(values.iter()) {
+            mbc.write(0x4000, register);
+            assert_eq!(mbc.read(0xA000), *value, "register {:02X}", register);
+            // the register is the same throughout 0xA000-0xBFFF
+            assert_eq!(mbc.read(0xBFFF), *value, "register {:02X}", register);
+        }
+        // writing the RTC left RAM alone
+        mbc.write(0x4000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0);
+        assert!(mbc.ram().iter().all(|byte| *byte == 0));
+        // nothing is selected by 0x04-0x07 or after 0x0C
+        for register in [0x04, 0x07, 0x0D].iter() {
+            mbc.write(0x4000, *register);
+            assert_eq!(mbc.read(0xA000), 0xFF);
+        }
+
+        let mut mbc = Mbc3::new(numbered_rom(4), 0x8000);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x08);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn mbc5_9_bit_bank() {
+        let mut mbc = Mbc5::new(numbered_rom(512), 0, false);
+        mbc.write(0x2000, 0xFF);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x1FF);
+        // the high bit is kept when the low byte changes
+        mbc.write(0x2000, 0x00);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0x100);
+        // bank 0 isn't remapped
+        mbc.write(0x3000, 0x00);
+        assert_eq!(mbc.rom_bank(), 0);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0);
+        // only bit 0 of 0x3000-0x3FFF is used
+        mbc.write(0x3000, 0xFE);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 0);
+        // banks wrap to the size of the ROM
+        let mut mbc = Mbc5::new(numbered_rom(4), 0, false);
+        mbc.write(0x2000, 0x05);
+        assert_eq!(bank_at(&mut mbc, 0x4000), 1);
+    }
+}
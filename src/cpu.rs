@@ -0,0 +1,803 @@
+//! An interpreter for the Gameboy's SM83 CPU that executes [`Opcode`]s.
+//!
+//! Memory is accessed through the [`Bus`] trait so that the interpreter can be
+//! used with any memory map. Cycle counts are given in T-cycles (4.19 MHz
+//! clock ticks).
+//!
+//! Interrupts are dispatched by `Cpu::handle_interrupts` between instructions.
+//! `ei` only takes effect after the instruction following it, and `halt`
+//! executed with IME off while an interrupt is pending triggers the halt bug.
+//!
+//! Flag and cycle behavior is based on the [opcode table] linked from the crate
+//! docs and [Pan Docs].
+//!
+//! [opcode table]: https://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
+//! [Pan Docs]: https://gbdev.io/pandocs/CPU_Instruction_Set.html
+
+use crate::interrupts::Interrupt;
+use crate::opcodes::*;
+use crate::parser;
+
+use serde::{Deserialize, Serialize};
+
+/// The memory as seen by the CPU.
+pub trait Bus {
+    /// Read a byte from the given address.
+    fn read(&mut self, address: u16) -> u8;
+    /// Write a byte to the given address.
+    fn write(&mut self, address: u16, value: u8);
+}
+
+/// The zero flag, bit 7 of F.
+pub const FLAG_Z: u8 = 0x80;
+/// The subtract flag, bit 6 of F.
+pub const FLAG_N: u8 = 0x40;
+/// The half carry flag, bit 5 of F.
+pub const FLAG_H: u8 = 0x20;
+/// The carry flag, bit 4 of F.
+pub const FLAG_C: u8 = 0x10;
+
+/// The CPU's registers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registers {
+    pub a: u8,
+    /// The flags register, only the top 4 bits are used.
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl Registers {
+    /// The register values left behind by the DMG boot ROM.
+    pub const fn dmg_post_boot() -> Self {
+        Self {
+            a: 0x01,
+            f: 0xB0,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xD8,
+            h: 0x01,
+            l: 0x4D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        }
+    }
+
+    /// The register values left behind by the CGB boot ROM.
+    pub const fn cgb_post_boot() -> Self {
+        Self {
+            a: 0x11,
+            f: 0x80,
+            b: 0x00,
+            c: 0x00,
+            d: 0xFF,
+            e: 0x56,
+            h: 0x00,
+            l: 0x0D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        }
+    }
+
+    /// Get the value of a 16 bit register.
+    pub fn get16(&self, register: Register16) -> u16 {
+        match register {
+            Register16::BC => u16::from_be_bytes([self.b, self.c]),
+            Register16::DE => u16::from_be_bytes([self.d, self.e]),
+            Register16::HL => u16::from_be_bytes([self.h, self.l]),
+            Register16::AF => u16::from_be_bytes([self.a, self.f]),
+            Register16::SP => self.sp,
+        }
+    }
+
+    /// Set the value of a 16 bit register, the low 4 bits of F can't be set.
+    pub fn set16(&mut self, register: Register16, value: u16) {
+        let [hi, lo] = value.to_be_bytes();
+        match register {
+            Register16::BC => {
+                self.b = hi;
+                self.c = lo;
+            }
+            Register16::DE => {
+                self.d = hi;
+                self.e = lo;
+            }
+            Register16::HL => {
+                self.h = hi;
+                self.l = lo;
+            }
+            Register16::AF => {
+                self.a = hi;
+                self.f = lo & 0xF0;
+            }
+            Register16::SP => self.sp = value,
+        }
+    }
+
+    /// Whether the given condition holds.
+    pub fn condition(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::C => self.f & FLAG_C != 0,
+            Flag::NC => self.f & FLAG_C == 0,
+            Flag::Z => self.f & FLAG_Z != 0,
+            Flag::NZ => self.f & FLAG_Z == 0,
+        }
+    }
+
+    fn set_flags(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        self.f = (if z { FLAG_Z } else { 0 })
+            | (if n { FLAG_N } else { 0 })
+            | (if h { FLAG_H } else { 0 })
+            | (if c { FLAG_C } else { 0 });
+    }
+
+    fn carry(&self) -> bool {
+        self.f & FLAG_C != 0
+    }
+}
+
+/// The SM83 CPU.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cpu {
+    pub registers: Registers,
+    /// The interrupt master enable flag.
+    pub ime: bool,
+    /// Set by `ei`, IME is enabled after the following instruction.
+    pub ime_pending: bool,
+    /// Set by `halt`, the CPU won't execute instructions until it's cleared.
+    pub halted: bool,
+    /// Set by `stop`, the CPU won't execute instructions until it's cleared.
+    pub stopped: bool,
+    /// Set when an illegal instruction is fetched, the real hardware hangs
+    /// until it's powered off.
+    pub locked: bool,
+    /// Set when `halt` is executed with IME off and an interrupt pending, the
+    /// CPU doesn't halt but fails to increment PC after the next byte is read.
+    pub halt_bug: bool,
+}
+
+impl Cpu {
+    pub fn new(registers: Registers) -> Self {
+        Self {
+            registers,
+            ..Self::default()
+        }
+    }
+
+    /// Decode the instruction at PC and advance PC past it.
+    ///
+    /// Returns `None` and locks the CPU if the instruction is illegal.
+    pub fn fetch(&mut self, bus: &mut impl Bus) -> Option<Opcode> {
+        let pc = self.registers.pc;
+        let bytes = if self.halt_bug {
+            [bus.read(pc), bus.read(pc), bus.read(pc.wrapping_add(1))]
+        } else {
+            [
+                bus.read(pc),
+                bus.read(pc.wrapping_add(1)),
+                bus.read(pc.wrapping_add(2)),
+            ]
+        };
+        let skipped = if self.halt_bug { 1 } else { 0 };
+        self.halt_bug = false;
+        match parser::parse_instruction(&bytes) {
+            Ok((rest, opcode)) => {
+                let size = (bytes.len() - rest.len()) as u16;
+                self.registers.pc = pc.wrapping_add(size - skipped);
+                Some(opcode)
+            }
+            Err(_) => {
+                self.locked = true;
+                None
+            }
+        }
+    }
+
+    /// Fetch and execute a single instruction, returning the number of
+    /// T-cycles it took.
+    ///
+    /// A halted, stopped or locked CPU idles for 4 cycles.
+    pub fn step(&mut self, bus: &mut impl Bus) -> u8 {
+        if self.halted || self.stopped || self.locked {
+            return 4;
+        }
+        match self.fetch(bus) {
+            Some(opcode) => self.execute(opcode, bus),
+            None => 4,
+        }
+    }
+
+    /// Wake from `halt` if an interrupt is pending and dispatch it if IME is
+    /// set, returning the number of T-cycles taken if one was dispatched.
+    ///
+    /// IE and IF are accessed through the bus at 0xFFFF and 0xFF0F.
+    pub fn handle_interrupts(&mut self, bus: &mut impl Bus) -> Option<u8> {
+        let interrupt = Interrupt::highest_priority(pending_interrupts(bus))?;
+        self.halted = false;
+        if !self.ime {
+            return None;
+        }
+        self.ime = false;
+        self.ime_pending = false;
+        let mut pc = self.registers.pc;
+        if self.halt_bug {
+            // `ei` followed by `halt` returns to the `halt`
+            pc = pc.wrapping_sub(1);
+            self.halt_bug = false;
+        }
+        self.push(bus, pc);
+        let flag = bus.read(0xFF0F);
+        bus.write(0xFF0F, flag & !interrupt.bit());
+        self.registers.pc = interrupt.vector();
+        Some(20)
+    }
+
+    /// Execute a single instruction, returning the number of T-cycles it took.
+    ///
+    /// PC must already point to the instruction following `opcode`, as it
+    /// does after `fetch`.
+    pub fn execute(&mut self, opcode: Opcode, bus: &mut impl Bus) -> u8 {
+        // the instruction after `ei` runs before IME is set, unless it's `di`
+        let enable_ime = self.ime_pending && opcode != Opcode::Di;
+        self.ime_pending = false;
+        let cycles = self.execute_opcode(opcode, bus);
+        if enable_ime {
+            self.ime = true;
+        }
+        cycles
+    }
+
+    fn execute_opcode(&mut self, opcode: Opcode, bus: &mut impl Bus) -> u8 {
+        match opcode {
+            Opcode::Nop => 4,
+            Opcode::Stop => {
+                self.stopped = true;
+                4
+            }
+            Opcode::Halt => {
+                if !self.ime && pending_interrupts(bus) != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+                4
+            }
+            Opcode::StoreImm16(register, value) => {
+                self.registers.set16(register, value);
+                12
+            }
+            Opcode::StoreImm8(register, value) => {
+                self.write8(bus, register, value);
+                if register == Register8::DerefHL {
+                    12
+                } else {
+                    8
+                }
+            }
+            Opcode::StoreAToHlAddr(inc) => {
+                let hl = self.registers.get16(Register16::HL);
+                bus.write(hl, self.registers.a);
+                self.registers.set16(Register16::HL, inc_or_dec(hl, inc));
+                8
+            }
+            Opcode::LoadAFromHlAddr(inc) => {
+                let hl = self.registers.get16(Register16::HL);
+                self.registers.a = bus.read(hl);
+                self.registers.set16(Register16::HL, inc_or_dec(hl, inc));
+                8
+            }
+            Opcode::StoreATo16(register) => {
+                bus.write(self.registers.get16(register), self.registers.a);
+                8
+            }
+            Opcode::LoadAFromReg16Addr(register) => {
+                self.registers.a = bus.read(self.registers.get16(register));
+                8
+            }
+            Opcode::Mov8(dst, src) => {
+                let value = self.read8(bus, src);
+                self.write8(bus, dst, value);
+                if dst == Register8::DerefHL || src == Register8::DerefHL {
+                    8
+                } else {
+                    4
+                }
+            }
+            Opcode::Jr(flag, offset) => {
+                if self.check(flag) {
+                    self.registers.pc = self.registers.pc.wrapping_add(offset as i8 as u16);
+                    12
+                } else {
+                    8
+                }
+            }
+            Opcode::Jp(flag, address) => {
+                if self.check(flag) {
+                    self.registers.pc = address;
+                    16
+                } else {
+                    12
+                }
+            }
+            Opcode::Inc8(register) => {
+                let value = self.read8(bus, register);
+                let result = value.wrapping_add(1);
+                self.write8(bus, register, result);
+                let carry = self.registers.carry();
+                self.registers
+                    .set_flags(result == 0, false, value & 0xF == 0xF, carry);
+                if register == Register8::DerefHL {
+                    12
+                } else {
+                    4
+                }
+            }
+            Opcode::Dec8(register) => {
+                let value = self.read8(bus, register);
+                let result = value.wrapping_sub(1);
+                self.write8(bus, register, result);
+                let carry = self.registers.carry();
+                self.registers
+                    .set_flags(result == 0, true, value & 0xF == 0, carry);
+                if register == Register8::DerefHL {
+                    12
+                } else {
+                    4
+                }
+            }
+            Opcode::Inc16(register) => {
+                let value = self.registers.get16(register);
+                self.registers.set16(register, value.wrapping_add(1));
+                8
+            }
+            Opcode::Dec16(register) => {
+                let value = self.registers.get16(register);
+                self.registers.set16(register, value.wrapping_sub(1));
+                8
+            }
+            Opcode::Push(register) => {
+                let value = self.registers.get16(register);
+                self.push(bus, value);
+                16
+            }
+            Opcode::Pop(register) => {
+                let value = self.pop(bus);
+                self.registers.set16(register, value);
+                12
+            }
+            Opcode::Add(register) => self.alu_register(bus, register, Cpu::add),
+            Opcode::Adc(register) => self.alu_register(bus, register, Cpu::adc),
+            Opcode::Sub(register) => self.alu_register(bus, register, Cpu::sub),
+            Opcode::Sbc(register) => self.alu_register(bus, register, Cpu::sbc),
+            Opcode::And(register) => self.alu_register(bus, register, Cpu::and),
+            Opcode::Xor(register) => self.alu_register(bus, register, Cpu::xor),
+            Opcode::Or(register) => self.alu_register(bus, register, Cpu::or),
+            Opcode::Cp(register) => self.alu_register(bus, register, Cpu::cp),
+            Opcode::Add8(value) => {
+                self.add(value);
+                8
+            }
+            Opcode::Adc8(value) => {
+                self.adc(value);
+                8
+            }
+            Opcode::Sub8(value) => {
+                self.sub(value);
+                8
+            }
+            Opcode::Sbc8(value) => {
+                self.sbc(value);
+                8
+            }
+            Opcode::And8(value) => {
+                self.and(value);
+                8
+            }
+            Opcode::Xor8(value) => {
+                self.xor(value);
+                8
+            }
+            Opcode::Or8(value) => {
+                self.or(value);
+                8
+            }
+            Opcode::Cp8(value) => {
+                self.cp(value);
+                8
+            }
+            Opcode::AddSp8(offset) => {
+                self.registers.sp = self.sp_plus_offset(offset);
+                16
+            }
+            Opcode::Daa => {
+                let regs = &mut self.registers;
+                let subtract = regs.f & FLAG_N != 0;
+                let half_carry = regs.f & FLAG_H != 0;
+                let mut carry = regs.carry();
+                let mut adjust = 0;
+                if subtract {
+                    if half_carry {
+                        adjust |= 0x06;
+                    }
+                    if carry {
+                        adjust |= 0x60;
+                    }
+                    regs.a = regs.a.wrapping_sub(adjust);
+                } else {
+                    if half_carry || regs.a & 0x0F > 0x09 {
+                        adjust |= 0x06;
+                    }
+                    if carry || regs.a > 0x99 {
+                        adjust |= 0x60;
+                        carry = true;
+                    }
+                    regs.a = regs.a.wrapping_add(adjust);
+                }
+                let a = regs.a;
+                regs.set_flags(a == 0, subtract, false, carry);
+                4
+            }
+            Opcode::Scf => {
+                let z = self.registers.f & FLAG_Z != 0;
+                self.registers.set_flags(z, false, false, true);
+                4
+            }
+            Opcode::Cpl => {
+                self.registers.a = !self.registers.a;
+                self.registers.f |= FLAG_N | FLAG_H;
+                4
+            }
+            Opcode::Ccf => {
+                let z = self.registers.f & FLAG_Z != 0;
+                let carry = self.registers.carry();
+                self.registers.set_flags(z, false, false, !carry);
+                4
+            }
+            Opcode::Rlca => {
+                self.registers.a = self.rotate(RotateKind::Rlc, self.registers.a);
+                self.registers.f &= !FLAG_Z;
+                4
+            }
+            Opcode::Rla => {
+                self.registers.a = self.rotate(RotateKind::Rl, self.registers.a);
+                self.registers.f &= !FLAG_Z;
+                4
+            }
+            Opcode::Rrca => {
+                self.registers.a = self.rotate(RotateKind::Rrc, self.registers.a);
+                self.registers.f &= !FLAG_Z;
+                4
+            }
+            Opcode::Rra => {
+                self.registers.a = self.rotate(RotateKind::Rr, self.registers.a);
+                self.registers.f &= !FLAG_Z;
+                4
+            }
+            Opcode::StoreImm16AddrSp(address) => {
+                let [lo, hi] = self.registers.sp.to_le_bytes();
+                bus.write(address, lo);
+                bus.write(address.wrapping_add(1), hi);
+                20
+            }
+            Opcode::AddHl(register) => {
+                let hl = self.registers.get16(Register16::HL);
+                let value = self.registers.get16(register);
+                let (result, carry) = hl.overflowing_add(value);
+                let half_carry = (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF;
+                let z = self.registers.f & FLAG_Z != 0;
+                self.registers.set16(Register16::HL, result);
+                self.registers.set_flags(z, false, half_carry, carry);
+                8
+            }
+            Opcode::Ret(None) => {
+                self.registers.pc = self.pop(bus);
+                16
+            }
+            Opcode::Ret(flag) => {
+                if self.check(flag) {
+                    self.registers.pc = self.pop(bus);
+                    20
+                } else {
+                    8
+                }
+            }
+            Opcode::Reti => {
+                self.registers.pc = self.pop(bus);
+                self.ime = true;
+                16
+            }
+            Opcode::Di => {
+                self.ime = false;
+                4
+            }
+            Opcode::Ei => {
+                self.ime_pending = true;
+                4
+            }
+            Opcode::Call(flag, address) => {
+                if self.check(flag) {
+                    let pc = self.registers.pc;
+                    self.push(bus, pc);
+                    self.registers.pc = address;
+                    24
+                } else {
+                    12
+                }
+            }
+            Opcode::JpHl => {
+                self.registers.pc = self.registers.get16(Register16::HL);
+                4
+            }
+            Opcode::Rst(n) => {
+                let pc = self.registers.pc;
+                self.push(bus, pc);
+                self.registers.pc = n as u16 * 8;
+                16
+            }
+            Opcode::LdHlSp8(offset) => {
+                let value = self.sp_plus_offset(offset as u8);
+                self.registers.set16(Register16::HL, value);
+                12
+            }
+            Opcode::LdSpHl => {
+                self.registers.sp = self.registers.get16(Register16::HL);
+                8
+            }
+            Opcode::StoreHA(offset) => {
+                bus.write(0xFF00 | offset as u16, self.registers.a);
+                12
+            }
+            Opcode::LoadHA(offset) => {
+                self.registers.a = bus.read(0xFF00 | offset as u16);
+                12
+            }
+            Opcode::StoreCA => {
+                bus.write(0xFF00 | self.registers.c as u16, self.registers.a);
+                8
+            }
+            Opcode::LoadCA => {
+                self.registers.a = bus.read(0xFF00 | self.registers.c as u16);
+                8
+            }
+            Opcode::StoreAAtAddress(address) => {
+                bus.write(address, self.registers.a);
+                16
+            }
+            Opcode::LoadAFromAddress(address) => {
+                self.registers.a = bus.read(address);
+                16
+            }
+            Opcode::Rlc(register) => self.cb_rotate(bus, register, RotateKind::Rlc),
+            Opcode::Rrc(register) => self.cb_rotate(bus, register, RotateKind::Rrc),
+            Opcode::Rl(register) => self.cb_rotate(bus, register, RotateKind::Rl),
+            Opcode::Rr(register) => self.cb_rotate(bus, register, RotateKind::Rr),
+            Opcode::Sla(register) => self.cb_rotate(bus, register, RotateKind::Sla),
+            Opcode::Sra(register) => self.cb_rotate(bus, register, RotateKind::Sra),
+            Opcode::Swap(register) => self.cb_rotate(bus, register, RotateKind::Swap),
+            Opcode::Srl(register) => self.cb_rotate(bus, register, RotateKind::Srl),
+            Opcode::Bit(bit, register) => {
+                let value = self.read8(bus, register);
+                let carry = self.registers.carry();
+                self.registers
+                    .set_flags(value & (1 << bit) == 0, false, true, carry);
+                if register == Register8::DerefHL {
+                    12
+                } else {
+                    8
+                }
+            }
+            Opcode::Res(bit, register) => {
+                let value = self.read8(bus, register);
+                self.write8(bus, register, value & !(1 << bit));
+                cb_cycles(register)
+            }
+            Opcode::Set(bit, register) => {
+                let value = self.read8(bus, register);
+                self.write8(bus, register, value | (1 << bit));
+                cb_cycles(register)
+            }
+        }
+    }
+
+    /// Push a value on to the stack.
+    pub fn push(&mut self, bus: &mut impl Bus, value: u16) {
+        let [hi, lo] = value.to_be_bytes();
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        bus.write(self.registers.sp, hi);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        bus.write(self.registers.sp, lo);
+    }
+
+    /// Pop a value off of the stack.
+    pub fn pop(&mut self, bus: &mut impl Bus) -> u16 {
+        let lo = bus.read(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let hi = bus.read(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        u16::from_be_bytes([hi, lo])
+    }
+
+    fn check(&self, flag: Option<Flag>) -> bool {
+        flag.map(|flag| self.registers.condition(flag))
+            .unwrap_or(true)
+    }
+
+    fn read8(&mut self, bus: &mut impl Bus, register: Register8) -> u8 {
+        match register {
+            Register8::A => self.registers.a,
+            Register8::B => self.registers.b,
+            Register8::C => self.registers.c,
+            Register8::D => self.registers.d,
+            Register8::E => self.registers.e,
+            Register8::H => self.registers.h,
+            Register8::L => self.registers.l,
+            Register8::DerefHL => bus.read(self.registers.get16(Register16::HL)),
+        }
+    }
+
+    fn write8(&mut self, bus: &mut impl Bus, register: Register8, value: u8) {
+        match register {
+            Register8::A => self.registers.a = value,
+            Register8::B => self.registers.b = value,
+            Register8::C => self.registers.c = value,
+            Register8::D => self.registers.d = value,
+            Register8::E => self.registers.e = value,
+            Register8::H => self.registers.h = value,
+            Register8::L => self.registers.l = value,
+            Register8::DerefHL => bus.write(self.registers.get16(Register16::HL), value),
+        }
+    }
+
+    fn alu_register(
+        &mut self,
+        bus: &mut impl Bus,
+        register: Register8,
+        op: fn(&mut Cpu, u8),
+    ) -> u8 {
+        let value = self.read8(bus, register);
+        op(self, value);
+        if register == Register8::DerefHL {
+            8
+        } else {
+            4
+        }
+    }
+
+    fn add(&mut self, value: u8) {
+        let a = self.registers.a;
+        let (result, carry) = a.overflowing_add(value);
+        self.registers.a = result;
+        self.registers
+            .set_flags(result == 0, false, (a & 0xF) + (value & 0xF) > 0xF, carry);
+    }
+
+    fn adc(&mut self, value: u8) {
+        let a = self.registers.a;
+        let carry_in = self.registers.carry() as u8;
+        let result = a as u16 + value as u16 + carry_in as u16;
+        self.registers.a = result as u8;
+        self.registers.set_flags(
+            result as u8 == 0,
+            false,
+            (a & 0xF) + (value & 0xF) + carry_in > 0xF,
+            result > 0xFF,
+        );
+    }
+
+    fn sub(&mut self, value: u8) {
+        self.cp(value);
+        self.registers.a = self.registers.a.wrapping_sub(value);
+    }
+
+    fn sbc(&mut self, value: u8) {
+        let a = self.registers.a;
+        let carry_in = self.registers.carry() as u8;
+        let result = a.wrapping_sub(value).wrapping_sub(carry_in);
+        self.registers.a = result;
+        self.registers.set_flags(
+            result == 0,
+            true,
+            (a & 0xF) < (value & 0xF) + carry_in,
+            (a as u16) < value as u16 + carry_in as u16,
+        );
+    }
+
+    fn and(&mut self, value: u8) {
+        self.registers.a &= value;
+        let a = self.registers.a;
+        self.registers.set_flags(a == 0, false, true, false);
+    }
+
+    fn xor(&mut self, value: u8) {
+        self.registers.a ^= value;
+        let a = self.registers.a;
+        self.registers.set_flags(a == 0, false, false, false);
+    }
+
+    fn or(&mut self, value: u8) {
+        self.registers.a |= value;
+        let a = self.registers.a;
+        self.registers.set_flags(a == 0, false, false, false);
+    }
+
+    fn cp(&mut self, value: u8) {
+        let a = self.registers.a;
+        self.registers
+            .set_flags(a == value, true, (a & 0xF) < (value & 0xF), a < value);
+    }
+
+    /// SP plus a signed offset, setting flags as `add sp, e8` and
+    /// `ld hl, sp+e8` do.
+    fn sp_plus_offset(&mut self, offset: u8) -> u16 {
+        let sp = self.registers.sp;
+        let half_carry = (sp & 0x000F) + (offset as u16 & 0x000F) > 0x000F;
+        let carry = (sp & 0x00FF) + offset as u16 > 0x00FF;
+        self.registers.set_flags(false, false, half_carry, carry);
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    fn rotate(&mut self, kind: RotateKind, value: u8) -> u8 {
+        let carry_in = self.registers.carry() as u8;
+        let (result, carry) = match kind {
+            RotateKind::Rlc => (value.rotate_left(1), value & 0x80 != 0),
+            RotateKind::Rrc => (value.rotate_right(1), value & 0x01 != 0),
+            RotateKind::Rl => ((value << 1) | carry_in, value & 0x80 != 0),
+            RotateKind::Rr => ((value >> 1) | (carry_in << 7), value & 0x01 != 0),
+            RotateKind::Sla => (value << 1, value & 0x80 != 0),
+            RotateKind::Sra => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            RotateKind::Swap => (value.rotate_left(4), false),
+            RotateKind::Srl => (value >> 1, value & 0x01 != 0),
+        };
+        self.registers.set_flags(result == 0, false, false, carry);
+        result
+    }
+
+    fn cb_rotate(&mut self, bus: &mut impl Bus, register: Register8, kind: RotateKind) -> u8 {
+        let value = self.read8(bus, register);
+        let result = self.rotate(kind, value);
+        self.write8(bus, register, result);
+        cb_cycles(register)
+    }
+}
+
+/// The shifts and rotates of the 0xCB instructions.
+#[derive(Debug, Clone, Copy)]
+enum RotateKind {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+/// Cycles taken by the read-modify-write 0xCB instructions.
+fn cb_cycles(register: Register8) -> u8 {
+    if register == Register8::DerefHL {
+        16
+    } else {
+        8
+    }
+}
+
+fn inc_or_dec(value: u16, inc: bool) -> u16 {
+    if inc {
+        value.wrapping_add(1)
+    } else {
+        value.wrapping_sub(1)
+    }
+}
+
+/// The interrupts that are both requested in IF and enabled in IE.
+fn pending_interrupts(bus: &mut impl Bus) -> u8 {
+    bus.read(0xFFFF) & bus.read(0xFF0F) & 0x1F
+}
@@ -0,0 +1,328 @@
+//! No-Intro and other Logiqx XML DAT files, catalogues of known good dumps
+//! used to identify a ROM by its hashes.
+//!
+//! ```
+//! use gameboy_rom::dat::Dat;
+//!
+//! let rom = vec![0; 0x8000];
+//! let dat = Dat::parse(
+//!     r#"<?xml version="1.0"?>
+//!     <datafile>
+//!         <header><name>Nintendo - Game Boy</name></header>
+//!         <game name="Blank (World) (Rev 1)">
+//!             <rom name="Blank (World) (Rev 1).gb" size="32768" crc="011FFCA6"/>
+//!         </game>
+//!     </datafile>"#,
+//! )
+//! .unwrap();
+//! let found = dat.identify(&rom).unwrap();
+//! assert_eq!(found.game.name, "Blank (World) (Rev 1)");
+//! assert_eq!(found.game.region.as_deref(), Some("World"));
+//! assert_eq!(found.game.revision.as_deref(), Some("Rev 1"));
+//! ```
+
+use crate::hash::{from_hex, RomHashes};
+
+use std::fmt;
+
+/// Errors that may occur while parsing a DAT.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DatError {
+    /// The file isn't well formed XML.
+    Xml(String),
+    /// The root element isn't `datafile`.
+    NotADat,
+    /// An attribute of a ROM couldn't be parsed.
+    InvalidAttribute {
+        rom: String,
+        attribute: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for DatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatError::Xml(e) => write!(f, "invalid XML: {}", e),
+            DatError::NotADat => write!(f, "not a Logiqx XML DAT file"),
+            DatError::InvalidAttribute {
+                rom,
+                attribute,
+                value,
+            } => write!(f, "invalid {} {:?} for ROM {:?}", attribute, value, rom),
+        }
+    }
+}
+
+/// A parsed DAT file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Dat {
+    /// The name of the DAT, such as `Nintendo - Game Boy`.
+    pub name: String,
+    pub description: Option<String>,
+    pub version: Option<String>,
+    pub games: Vec<DatGame>,
+}
+
+/// A game listed in a DAT.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DatGame {
+    /// The canonical name, such as `Tetris (World) (Rev 1)`.
+    pub name: String,
+    pub description: Option<String>,
+    /// The region from a `release` element, or else the first parenthesised
+    /// part of the name.
+    pub region: Option<String>,
+    /// The `Rev` part of the name, if the game has been revised.
+    pub revision: Option<String>,
+    pub roms: Vec<DatRom>,
+}
+
+/// A file making up a game, with whichever hashes the DAT lists for it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DatRom {
+    pub name: String,
+    pub size: Option<u64>,
+    pub crc32: Option<u32>,
+    pub md5: Option<[u8; 16]>,
+    pub sha1: Option<[u8; 20]>,
+    pub sha256: Option<[u8; 32]>,
+}
+
+impl DatRom {
+    /// Whether a ROM of `size` bytes with these hashes is this file. Every
+    /// hash listed must match, and at least one must be listed.
+    pub fn matches(&self, hashes: &RomHashes, size: usize) -> bool {
+        fn check<T: PartialEq>(listed: &Option<T>, actual: &T) -> bool {
+            listed.as_ref().is_none_or(|listed| listed == actual)
+        }
+        let any_hash = self.crc32.is_some()
+            || self.md5.is_some()
+            || self.sha1.is_some()
+            || self.sha256.is_some();
+        any_hash
+            && check(&self.size, &(size as u64))
+            && check(&self.crc32, &hashes.crc32)
+            && check(&self.md5, &hashes.md5)
+            && check(&self.sha1, &hashes.sha1)
+            && check(&self.sha256, &hashes.sha256)
+    }
+}
+
+/// A game found in a DAT, and which of its files matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatMatch<'a> {
+    pub game: &'a DatGame,
+    pub rom: &'a DatRom,
+}
+
+impl Dat {
+    /// Parse a DAT file. MAME style `machine` elements are read as games.
+    pub fn parse(xml: &str) -> Result<Self, DatError> {
+        let document = roxmltree::Document::parse(xml).map_err(|e| DatError::Xml(e.to_string()))?;
+        let root = document.root_element();
+        if !root.has_tag_name("datafile") {
+            return Err(DatError::NotADat);
+        }
+
+        let mut dat = Dat::default();
+        for node in root.children().filter(|node| node.is_element()) {
+            match node.tag_name().name() {
+                "header" => {
+                    dat.name = child_text(node, "name").unwrap_or_default();
+                    dat.description = child_text(node, "description");
+                    dat.version = child_text(node, "version");
+                }
+                "game" | "machine" => dat.games.push(parse_game(node)?),
+                _ => (),
+            }
+        }
+        Ok(dat)
+    }
+
+    /// Find the game `rom` is a dump of.
+    pub fn identify(&self, rom: &[u8]) -> Option<DatMatch<'_>> {
+        self.find(&RomHashes::new(rom), rom.len())
+    }
+
+    /// Find the game a ROM of `size` bytes with these hashes is a dump of.
+    pub fn find(&self, hashes: &RomHashes, size: usize) -> Option<DatMatch<'_>> {
+        self.games.iter().find_map(|game| {
+            game.roms
+                .iter()
+                .find(|rom| rom.matches(hashes, size))
+                .map(|rom| DatMatch { game, rom })
+        })
+    }
+}
+
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .map(|text| text.trim().to_string())
+}
+
+fn parse_game(node: roxmltree::Node) -> Result<DatGame, DatError> {
+    let name = node.attribute("name").unwrap_or_default().to_string();
+    let release_region = node
+        .children()
+        .find(|child| child.has_tag_name("release"))
+        .and_then(|release| release.attribute("region"));
+    let region = release_region
+        .or_else(|| name_tags(&name).next())
+        .map(str::to_string);
+    let revision = name_tags(&name)
+        .find(|tag| tag.starts_with("Rev "))
+        .map(str::to_string);
+    let roms = node
+        .children()
+        .filter(|child| child.has_tag_name("rom"))
+        .map(parse_rom)
+        .collect::<Result<_, _>>()?;
+    Ok(DatGame {
+        description: child_text(node, "description"),
+        name,
+        region,
+        revision,
+        roms,
+    })
+}
+
+/// The parenthesised parts of a No-Intro name, such as `World` and `Rev 1`
+/// in `Tetris (World) (Rev 1)`.
+fn name_tags(name: &str) -> impl Iterator<Item = &str> {
+    name.split('(')
+        .skip(1)
+        .filter_map(|part| part.split(')').next())
+}
+
+fn parse_rom(node: roxmltree::Node) -> Result<DatRom, DatError> {
+    Ok(DatRom {
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        size: attribute(node, "size", |value| value.parse().ok())?,
+        crc32: attribute(node, "crc", |value| from_hex(value).map(u32::from_be_bytes))?,
+        md5: attribute(node, "md5", from_hex)?,
+        sha1: attribute(node, "sha1", from_hex)?,
+        sha256: attribute(node, "sha256", from_hex)?,
+    })
+}
+
+/// Parse an attribute of a ROM. A missing or empty attribute is `None`.
+fn attribute<T>(
+    node: roxmltree::Node,
+    attribute: &'static str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Option<T>, DatError> {
+    node.attribute(attribute)
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            parse(value).ok_or_else(|| DatError::InvalidAttribute {
+                rom: node.attribute("name").unwrap_or_default().to_string(),
+                attribute,
+                value: value.to_string(),
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::to_hex;
+
+    fn datafile(games: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\"?><datafile><header><name>Test</name>\
+             <version>20240101</version></header>{}</datafile>",
+            games
+        )
+    }
+
+    #[test]
+    fn sha1_only() {
+        let rom = vec![0; 0x8000];
+        let hashes = RomHashes::new(&rom);
+        let listed = DatRom {
+            sha1: Some(hashes.sha1),
+            ..DatRom::default()
+        };
+        assert!(listed.matches(&hashes, rom.len()));
+        assert!(!listed.matches(&RomHashes::new(&rom[1..]), rom.len() - 1));
+
+        // nothing listed matches nothing
+        assert!(!DatRom::default().matches(&hashes, rom.len()));
+    }
+
+    #[test]
+    fn mismatched_size() {
+        let rom = vec![0; 0x8000];
+        let hashes = RomHashes::new(&rom);
+        let listed = DatRom {
+            size: Some(0x10000),
+            crc32: Some(hashes.crc32),
+            ..DatRom::default()
+        };
+        assert!(!listed.matches(&hashes, rom.len()));
+        assert!(listed.matches(&hashes, 0x10000));
+    }
+
+    #[test]
+    fn invalid_attribute() {
+        let xml = datafile(r#"<game name="Bad"><rom name="bad.gb" crc="XYZ"/></game>"#);
+        assert_eq!(
+            Dat::parse(&xml),
+            Err(DatError::InvalidAttribute {
+                rom: "bad.gb".to_string(),
+                attribute: "crc",
+                value: "XYZ".to_string(),
+            })
+        );
+        let xml = datafile(r#"<game name="Bad"><rom name="bad.gb" size="big"/></game>"#);
+        assert_eq!(
+            Dat::parse(&xml).unwrap_err().to_string(),
+            "invalid size \"big\" for ROM \"bad.gb\""
+        );
+        // empty attributes are treated as missing
+        let xml = datafile(r#"<game name="Ok"><rom name="ok.gb" md5=""/></game>"#);
+        assert_eq!(Dat::parse(&xml).unwrap().games[0].roms[0].md5, None);
+    }
+
+    #[test]
+    fn not_a_dat() {
+        assert_eq!(
+            Dat::parse("<softwarelist name=\"gameboy\"/>"),
+            Err(DatError::NotADat)
+        );
+        assert!(matches!(Dat::parse("<datafile>"), Err(DatError::Xml(_))));
+    }
+
+    #[test]
+    fn mame_machines() {
+        let rom = vec![0; 0x8000];
+        let hashes = RomHashes::new(&rom);
+        let xml = datafile(&format!(
+            r#"<machine name="blank"><description>Blank</description>
+               <rom name="blank.gb" size="32768" sha1="{}"/></machine>
+               <game name="Other (Japan) (Rev 2)"><release name="Other" region="USA"/>
+               <rom name="other.gb" crc="00000000"/></game>"#,
+            to_hex(&hashes.sha1)
+        ));
+        let dat = Dat::parse(&xml).unwrap();
+        assert_eq!(dat.name, "Test");
+        assert_eq!(dat.version.as_deref(), Some("20240101"));
+        assert_eq!(dat.games.len(), 2);
+
+        let found = dat.identify(&rom).unwrap();
+        assert_eq!(found.game.name, "blank");
+        assert_eq!(found.game.description.as_deref(), Some("Blank"));
+        assert_eq!(found.rom.name, "blank.gb");
+        assert_eq!(found.game.region, None);
+
+        // a release's region wins over the name
+        let other = &dat.games[1];
+        assert_eq!(other.region.as_deref(), Some("USA"));
+        assert_eq!(other.revision.as_deref(), Some("Rev 2"));
+        assert!(dat.identify(&rom[1..]).is_none());
+    }
+}
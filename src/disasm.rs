@@ -0,0 +1,1132 @@
+//! Turning [`Opcode`]s back into human readable assembly.
+//!
+//! The `Disassembler` finds code by following control flow from the ROM's
+//! entry points and the `DisassemblyFormatter` renders it using RGBDS syntax.
+//!
+//! ```
+//! use gameboy_rom::{disasm::DisassemblyFormatter, Opcode};
+//!
+//! let mut formatter = DisassemblyFormatter::new();
+//! assert_eq!(formatter.format(0x150, &Opcode::StoreHA(0x40)), "ldh [$FF40], a");
+//! formatter.symbolize_hardware_registers = true;
+//! assert_eq!(formatter.format(0x150, &Opcode::StoreHA(0x40)), "ldh [rLCDC], a");
+//! ```
+
+use crate::header::RomType;
+use crate::io_registers::lookup_hardware_register;
+use crate::opcodes::*;
+use crate::parser;
+use crate::symbols::{BankedAddress, SymbolTable};
+use crate::util::translate_rom_size;
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Formats [`Opcode`]s as RGBDS style assembly.
+#[derive(Debug, Clone, Default)]
+pub struct DisassemblyFormatter {
+    /// Render accesses to the 0xFF00-0xFFFF range with their hardware
+    /// register names, e.g. `ldh [rLCDC], a` instead of `ldh [$FF40], a`.
+    pub symbolize_hardware_registers: bool,
+    /// Labels used in place of addresses, these take priority over hardware
+    /// register names.
+    pub symbols: SymbolTable,
+}
+
+impl DisassemblyFormatter {
+    /// Create a new formatter with all symbolization disabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Format the given opcode, `address` is the address of the first byte of
+    /// the instruction and is used to resolve relative jumps.
+    ///
+    /// Code in the switchable ROM bank is assumed to be in bank 1, use
+    /// `format_banked` if the bank is known.
+    pub fn format(&self, address: u16, opcode: &Opcode) -> String {
+        self.format_banked(BankedAddress::resolve(0, address), opcode)
+    }
+
+    /// Format the given opcode located at `location`.
+    pub fn format_banked(&self, location: BankedAddress, opcode: &Opcode) -> String {
+        self.format_with_rom_bank(location.address, location.bank, opcode)
+    }
+
+    /// Format the given opcode located at `address` while `rom_bank` is mapped
+    /// into the switchable ROM bank.
+    pub fn format_with_rom_bank(&self, address: u16, rom_bank: u16, opcode: &Opcode) -> String {
+        let bank = rom_bank;
+        match *opcode {
+            Opcode::Nop => "nop".to_string(),
+            Opcode::Stop => "stop".to_string(),
+            Opcode::Halt => "halt".to_string(),
+            Opcode::StoreImm16(r16, imm) => format!("ld {}, ${:04X}", reg16(r16), imm),
+            Opcode::StoreImm8(r8, imm) => format!("ld {}, ${:02X}", reg8(r8), imm),
+            Opcode::StoreAToHlAddr(inc) => format!("ld [hl{}], a", inc_dec(inc)),
+            Opcode::LoadAFromHlAddr(inc) => format!("ld a, [hl{}]", inc_dec(inc)),
+            Opcode::StoreATo16(r16) => format!("ld [{}], a", reg16(r16)),
+            Opcode::LoadAFromReg16Addr(r16) => format!("ld a, [{}]", reg16(r16)),
+            Opcode::Mov8(dst, src) => format!("ld {}, {}", reg8(dst), reg8(src)),
+            Opcode::Jr(flag, offset) => {
+                let target = address.wrapping_add(2).wrapping_add(offset as i8 as u16);
+                with_flag("jr", flag, &self.address(bank, target))
+            }
+            Opcode::Jp(flag, target) => with_flag("jp", flag, &self.address(bank, target)),
+            Opcode::Inc8(r8) => format!("inc {}", reg8(r8)),
+            Opcode::Dec8(r8) => format!("dec {}", reg8(r8)),
+            Opcode::Inc16(r16) => format!("inc {}", reg16(r16)),
+            Opcode::Dec16(r16) => format!("dec {}", reg16(r16)),
+            Opcode::Push(r16) => format!("push {}", reg16(r16)),
+            Opcode::Pop(r16) => format!("pop {}", reg16(r16)),
+            Opcode::Add(r8) => format!("add a, {}", reg8(r8)),
+            Opcode::Adc(r8) => format!("adc a, {}", reg8(r8)),
+            Opcode::Sub(r8) => format!("sub a, {}", reg8(r8)),
+            Opcode::Sbc(r8) => format!("sbc a, {}", reg8(r8)),
+            Opcode::And(r8) => format!("and a, {}", reg8(r8)),
+            Opcode::Xor(r8) => format!("xor a, {}", reg8(r8)),
+            Opcode::Or(r8) => format!("or a, {}", reg8(r8)),
+            Opcode::Cp(r8) => format!("cp a, {}", reg8(r8)),
+            Opcode::Add8(imm) => format!("add a, ${:02X}", imm),
+            Opcode::Adc8(imm) => format!("adc a, ${:02X}", imm),
+            Opcode::Sub8(imm) => format!("sub a, ${:02X}", imm),
+            Opcode::Sbc8(imm) => format!("sbc a, ${:02X}", imm),
+            Opcode::And8(imm) => format!("and a, ${:02X}", imm),
+            Opcode::Xor8(imm) => format!("xor a, ${:02X}", imm),
+            Opcode::Or8(imm) => format!("or a, ${:02X}", imm),
+            Opcode::Cp8(imm) => format!("cp a, ${:02X}", imm),
+            Opcode::AddSp8(imm) => format!("add sp, {}", imm as i8),
+            Opcode::Daa => "daa".to_string(),
+            Opcode::Scf => "scf".to_string(),
+            Opcode::Cpl => "cpl".to_string(),
+            Opcode::Ccf => "ccf".to_string(),
+            Opcode::Rlca => "rlca".to_string(),
+            Opcode::Rla => "rla".to_string(),
+            Opcode::Rrca => "rrca".to_string(),
+            Opcode::Rra => "rra".to_string(),
+            Opcode::StoreImm16AddrSp(addr) => format!("ld [{}], sp", self.address(bank, addr)),
+            Opcode::AddHl(r16) => format!("add hl, {}", reg16(r16)),
+            Opcode::Ret(flag) => match flag {
+                Some(flag) => format!("ret {}", self::flag(flag)),
+                None => "ret".to_string(),
+            },
+            Opcode::Reti => "reti".to_string(),
+            Opcode::Di => "di".to_string(),
+            Opcode::Ei => "ei".to_string(),
+            Opcode::Call(flag, target) => with_flag("call", flag, &self.address(bank, target)),
+            Opcode::JpHl => "jp hl".to_string(),
+            Opcode::Rst(n) => format!("rst ${:02X}", n * 8),
+            Opcode::LdHlSp8(offset) => format!("ld hl, sp{:+}", offset),
+            Opcode::LdSpHl => "ld sp, hl".to_string(),
+            Opcode::StoreHA(offset) => format!("ldh [{}], a", self.address(bank, high(offset))),
+            Opcode::LoadHA(offset) => format!("ldh a, [{}]", self.address(bank, high(offset))),
+            Opcode::StoreCA => "ldh [c], a".to_string(),
+            Opcode::LoadCA => "ldh a, [c]".to_string(),
+            Opcode::StoreAAtAddress(addr) => format!("ld [{}], a", self.address(bank, addr)),
+            Opcode::LoadAFromAddress(addr) => format!("ld a, [{}]", self.address(bank, addr)),
+            Opcode::Rlc(r8) => format!("rlc {}", reg8(r8)),
+            Opcode::Rrc(r8) => format!("rrc {}", reg8(r8)),
+            Opcode::Rr(r8) => format!("rr {}", reg8(r8)),
+            Opcode::Rl(r8) => format!("rl {}", reg8(r8)),
+            Opcode::Sla(r8) => format!("sla {}", reg8(r8)),
+            Opcode::Sra(r8) => format!("sra {}", reg8(r8)),
+            Opcode::Swap(r8) => format!("swap {}", reg8(r8)),
+            Opcode::Srl(r8) => format!("srl {}", reg8(r8)),
+            Opcode::Bit(bit, r8) => format!("bit {}, {}", bit, reg8(r8)),
+            Opcode::Res(bit, r8) => format!("res {}, {}", bit, reg8(r8)),
+            Opcode::Set(bit, r8) => format!("set {}, {}", bit, reg8(r8)),
+        }
+    }
+
+    /// Render an absolute address referenced from `bank`, symbolizing it if
+    /// possible.
+    fn address(&self, bank: u16, address: u16) -> String {
+        if let Some(label) = self.symbols.lookup(bank, address) {
+            return label.to_string();
+        }
+        if self.symbolize_hardware_registers {
+            if let Some(reg) = lookup_hardware_register(address) {
+                return reg.name.to_string();
+            }
+        }
+        format!("${:04X}", address)
+    }
+}
+
+fn with_flag(mnemonic: &str, flag: Option<Flag>, operand: &str) -> String {
+    match flag {
+        Some(f) => format!("{} {}, {}", mnemonic, self::flag(f), operand),
+        None => format!("{} {}", mnemonic, operand),
+    }
+}
+
+/// The full address accessed by the `ldh` instructions.
+fn high(offset: u8) -> u16 {
+    0xFF00 | offset as u16
+}
+
+fn inc_dec(inc: bool) -> char {
+    if inc {
+        '+'
+    } else {
+        '-'
+    }
+}
+
+fn reg8(r8: Register8) -> &'static str {
+    match r8 {
+        Register8::A => "a",
+        Register8::B => "b",
+        Register8::C => "c",
+        Register8::D => "d",
+        Register8::E => "e",
+        Register8::H => "h",
+        Register8::L => "l",
+        Register8::DerefHL => "[hl]",
+    }
+}
+
+fn reg16(r16: Register16) -> &'static str {
+    match r16 {
+        Register16::BC => "bc",
+        Register16::DE => "de",
+        Register16::HL => "hl",
+        Register16::AF => "af",
+        Register16::SP => "sp",
+    }
+}
+
+fn flag(flag: Flag) -> &'static str {
+    match flag {
+        Flag::C => "c",
+        Flag::Z => "z",
+        Flag::NC => "nc",
+        Flag::NZ => "nz",
+    }
+}
+
+/// A decoded instruction and where it was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub location: BankedAddress,
+    pub opcode: Opcode,
+    /// The number of bytes the instruction takes up in the ROM.
+    pub size: u8,
+    /// The ROM bank believed to be mapped into 0x4000-0x7FFF when this
+    /// instruction runs.
+    pub rom_bank: u16,
+}
+
+/// How a location was referenced.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum XRefKind {
+    /// The location is the target of a `call` or `rst`.
+    Call,
+    /// The location is the target of a `jp` or `jr`.
+    Jump,
+    /// The location is an entry in a jump table dispatched from the
+    /// referencing instruction.
+    Table,
+}
+
+/// A reference to a location from an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XRef {
+    /// The location of the referencing instruction.
+    pub from: BankedAddress,
+    pub kind: XRefKind,
+}
+
+/// The result of running the `Disassembler`.
+#[derive(Debug, Clone, Default)]
+pub struct Disassembly {
+    /// Every instruction found, keyed by its location.
+    pub instructions: BTreeMap<BankedAddress, Instruction>,
+    /// Cross references keyed by the referenced location.
+    pub xrefs: BTreeMap<BankedAddress, Vec<XRef>>,
+    /// Detected jump tables keyed by their location, each entry is a
+    /// location that the table dispatches to.
+    pub jump_tables: BTreeMap<BankedAddress, Vec<BankedAddress>>,
+    /// The locations the disassembler started from.
+    pub entry_points: Vec<BankedAddress>,
+}
+
+/// Something that takes up space in a listing.
+enum ListingItem<'a> {
+    Code(&'a Instruction),
+    JumpTable(&'a [BankedAddress]),
+}
+
+impl Disassembly {
+    /// Generate labels for every referenced location.
+    ///
+    /// Locations which are called get a `Call_BB_AAAA` label, jump tables get
+    /// a `JumpTable_BB_AAAA` label and the rest get a `Jump_BB_AAAA` label.
+    pub fn auto_symbols(&self) -> SymbolTable {
+        let mut table = SymbolTable::new();
+        for (location, xrefs) in &self.xrefs {
+            let prefix = if xrefs.iter().any(|xref| xref.kind == XRefKind::Call) {
+                "Call"
+            } else {
+                "Jump"
+            };
+            table.insert(*location, auto_label(prefix, *location));
+        }
+        for location in self.jump_tables.keys() {
+            table.insert(*location, auto_label("JumpTable", *location));
+        }
+        table
+    }
+
+    /// Split the instructions into functions, keyed by where each one starts.
+    ///
+    /// Functions start at the entry points and at every call target, and are
+    /// made up of the instructions reachable from there by jumps and falling
+    /// through, stopping at the start of another function. Code reachable
+    /// from more than one function belongs to the first one by address, so
+    /// every instruction is in exactly one function.
+    pub fn functions(&self) -> BTreeMap<BankedAddress, Vec<BankedAddress>> {
+        let mut starts: BTreeSet<BankedAddress> = self
+            .entry_points
+            .iter()
+            .copied()
+            .filter(|location| self.instructions.contains_key(location))
+            .collect();
+        let mut jumps: BTreeMap<BankedAddress, Vec<BankedAddress>> = BTreeMap::new();
+        for (target, xrefs) in &self.xrefs {
+            for xref in xrefs {
+                if xref.kind == XRefKind::Call {
+                    starts.insert(*target);
+                } else {
+                    jumps.entry(xref.from).or_default().push(*target);
+                }
+            }
+        }
+        starts.retain(|location| self.instructions.contains_key(location));
+
+        let mut claimed = BTreeSet::new();
+        let mut functions = BTreeMap::new();
+        for start in &starts {
+            let mut body = vec![];
+            let mut to_visit = vec![*start];
+            while let Some(location) = to_visit.pop() {
+                let instruction = match self.instructions.get(&location) {
+                    Some(instruction) => instruction,
+                    None => continue,
+                };
+                if (location != *start && starts.contains(&location)) || !claimed.insert(location) {
+                    continue;
+                }
+                body.push(location);
+                to_visit.extend(jumps.get(&location).into_iter().flatten());
+                let next = location.address.wrapping_add(instruction.size as u16);
+                let table_follows = self
+                    .jump_tables
+                    .contains_key(&BankedAddress::resolve(instruction.rom_bank, next));
+                if control_flow(instruction).1 && !table_follows && next < 0x8000 {
+                    to_visit.push(BankedAddress::resolve(instruction.rom_bank, next));
+                }
+            }
+            body.sort();
+            functions.insert(*start, body);
+        }
+        functions
+    }
+
+    /// Render all found instructions and jump tables as an assembly listing.
+    ///
+    /// Labels from the formatter's symbol table are emitted before the
+    /// instructions they name, along with where they're referenced from.
+    pub fn listing(&self, formatter: &DisassemblyFormatter) -> String {
+        let mut items: BTreeMap<BankedAddress, ListingItem> = self
+            .instructions
+            .iter()
+            .map(|(location, instruction)| (*location, ListingItem::Code(instruction)))
+            .collect();
+        for (location, entries) in &self.jump_tables {
+            items.insert(*location, ListingItem::JumpTable(entries));
+        }
+
+        let mut out = String::new();
+        let mut next_location = None;
+        for (location, item) in items {
+            if next_location.is_some() && next_location != Some(location) {
+                out.push('\n');
+            }
+            if let Some(label) = formatter.symbols.get(location) {
+                out.push_str(label);
+                out.push(':');
+                if let Some(xrefs) = self.xrefs.get(&location) {
+                    out.push_str(" ; xrefs:");
+                    for xref in xrefs {
+                        out.push(' ');
+                        match formatter.symbols.get(xref.from) {
+                            Some(from) => out.push_str(from),
+                            None => out.push_str(&xref.from.to_string()),
+                        }
+                    }
+                }
+                out.push('\n');
+            }
+            let size = match item {
+                ListingItem::Code(instruction) => {
+                    out.push_str(&format!(
+                        "    {:<32}; {}\n",
+                        formatter.format_with_rom_bank(
+                            location.address,
+                            instruction.rom_bank,
+                            &instruction.opcode
+                        ),
+                        location
+                    ));
+                    instruction.size as u16
+                }
+                ListingItem::JumpTable(entries) => {
+                    for (idx, entry) in entries.iter().enumerate() {
+                        let entry_text = match formatter.symbols.get(*entry) {
+                            Some(label) => label.to_string(),
+                            None => format!("${:04X}", entry.address),
+                        };
+                        out.push_str(&format!(
+                            "    dw {:<29}; {}\n",
+                            entry_text,
+                            BankedAddress::new(
+                                location.bank,
+                                location.address.wrapping_add(idx as u16 * 2)
+                            )
+                        ));
+                    }
+                    entries.len() as u16 * 2
+                }
+            };
+            next_location = Some(BankedAddress::new(
+                location.bank,
+                location.address.wrapping_add(size),
+            ));
+        }
+        out
+    }
+}
+
+fn auto_label(prefix: &str, location: BankedAddress) -> String {
+    format!("{}_{:02X}_{:04X}", prefix, location.bank, location.address)
+}
+
+/// The most entries a single detected jump table may have.
+const MAX_JUMP_TABLE_ENTRIES: usize = 256;
+
+/// Finds code in a ROM by following control flow from a set of entry points.
+pub struct Disassembler<'rom> {
+    rom_data: &'rom [u8],
+    entry_points: Vec<BankedAddress>,
+    detect_jump_tables: bool,
+}
+
+impl<'rom> Disassembler<'rom> {
+    /// Create a disassembler starting from the cartridge entry point at 0x100
+    /// and the interrupt vectors.
+    pub fn new(rom_data: &'rom [u8]) -> Self {
+        Self {
+            rom_data,
+            entry_points: [0x100, 0x40, 0x48, 0x50, 0x58, 0x60]
+                .iter()
+                .map(|address| BankedAddress::new(0, *address))
+                .collect(),
+            detect_jump_tables: true,
+        }
+    }
+
+    /// Also start disassembling from the given location.
+    pub fn add_entry_point(&mut self, location: BankedAddress) {
+        self.entry_points.push(location);
+    }
+
+    /// Enable or disable the jump table heuristics, they're enabled by
+    /// default.
+    ///
+    /// Two idioms are recognized:
+    /// - `rst` into a routine that pops the return address into HL and ends in
+    ///   `jp hl`, the pointer table is stored inline after the `rst`.
+    /// - `jp hl` preceded by `ld hl, table` and a load of the pointer from
+    ///   `[hl]`.
+    pub fn set_jump_table_detection(&mut self, enabled: bool) {
+        self.detect_jump_tables = enabled;
+    }
+
+    /// Follow control flow from the entry points, decoding every reachable
+    /// instruction.
+    ///
+    /// Writes of known values to the MBC's ROM bank register are tracked so
+    /// that jumps into the switchable ROM bank resolve to the selected bank.
+    /// Callees are assumed to leave the selected bank unchanged.
+    ///
+    /// A detected jump table is only kept if it has at least one entry:
+    ///
+    /// ```
+    /// use gameboy_rom::GameBoyRom;
+    ///
+    /// let mut rom = vec![0; 0x8000];
+    /// // rst $00 is a dispatcher: pop hl, jp hl
+    /// rom[0x00..0x02].copy_from_slice(&[0xE1, 0xE9]);
+    /// // rst $00 followed by a pointer out of ROM
+    /// rom[0x100..0x103].copy_from_slice(&[0xC7, 0xFF, 0xFF]);
+    /// let disassembly = GameBoyRom::new(&rom).disassembler().run();
+    /// assert!(disassembly.jump_tables.is_empty());
+    /// ```
+    pub fn run(&self) -> Disassembly {
+        let mut disassembly = Disassembly {
+            entry_points: self.entry_points.clone(),
+            ..Disassembly::default()
+        };
+        let mut to_inspect: VecDeque<(BankedAddress, u16)> = self
+            .entry_points
+            .iter()
+            .map(|location| (*location, location.bank.max(1)))
+            .collect();
+        let mut dispatch_rsts = [false; 8];
+        if self.detect_jump_tables {
+            for (n, is_dispatcher) in dispatch_rsts.iter_mut().enumerate() {
+                *is_dispatcher = self.is_table_dispatcher(n as u16 * 8);
+            }
+        }
+
+        while let Some((mut location, mut rom_bank)) = to_inspect.pop_front() {
+            // the straight-line run of instructions leading up to `location`
+            let mut block: Vec<Instruction> = vec![];
+            let mut known = KnownValues::default();
+            while !disassembly.instructions.contains_key(&location) {
+                let instruction = match self.decode(location, rom_bank) {
+                    Some(instruction) => instruction,
+                    None => break,
+                };
+                disassembly.instructions.insert(location, instruction);
+
+                let (targets, mut falls_through) = control_flow(&instruction);
+                for (target, kind) in targets {
+                    if target >= 0x8000 {
+                        continue;
+                    }
+                    let target = BankedAddress::resolve(rom_bank, target);
+                    disassembly.xrefs.entry(target).or_default().push(XRef {
+                        from: location,
+                        kind,
+                    });
+                    to_inspect.push_back((target, rom_bank));
+                }
+
+                let table = match instruction.opcode {
+                    Opcode::Rst(n) if dispatch_rsts[n as usize] => {
+                        falls_through = false;
+                        Some(location.address.wrapping_add(instruction.size as u16))
+                    }
+                    Opcode::JpHl if self.detect_jump_tables => indexed_jump_table(&block),
+                    _ => None,
+                };
+                if let Some(table) = table.filter(|table| *table < 0x8000) {
+                    let table = BankedAddress::resolve(rom_bank, table);
+                    let entries = self.read_jump_table(table, rom_bank, &disassembly);
+                    for entry in &entries {
+                        disassembly.xrefs.entry(*entry).or_default().push(XRef {
+                            from: location,
+                            kind: XRefKind::Table,
+                        });
+                        to_inspect.push_back((*entry, rom_bank));
+                    }
+                    if !entries.is_empty() {
+                        disassembly.jump_tables.insert(table, entries);
+                    }
+                }
+
+                if let Some((address, value)) = known.memory_write(&instruction.opcode) {
+                    if let Some(bank) = self.selected_rom_bank(address, value) {
+                        // code running from the switchable bank can't switch
+                        // itself out without also jumping elsewhere
+                        if location.address < 0x4000 {
+                            rom_bank = bank;
+                        }
+                    }
+                }
+                known.update(&instruction.opcode);
+
+                block.push(instruction);
+                let next = location.address.wrapping_add(instruction.size as u16);
+                if !falls_through || next >= 0x8000 {
+                    break;
+                }
+                location = BankedAddress::resolve(rom_bank, next);
+            }
+        }
+
+        disassembly
+    }
+
+    fn decode(&self, location: BankedAddress, rom_bank: u16) -> Option<Instruction> {
+        let offset = location.rom_offset()?;
+        let input = self.rom_data.get(offset..)?;
+        let (rest, opcode) = parser::parse_instruction(input).ok()?;
+        Some(Instruction {
+            location,
+            opcode,
+            size: (input.len() - rest.len()) as u8,
+            rom_bank,
+        })
+    }
+
+    /// The ROM bank selected by writing `value` to `address`, if that write
+    /// changes the ROM bank for this ROM's MBC.
+    fn selected_rom_bank(&self, address: u16, value: u8) -> Option<u16> {
+        let rom_type = RomType::from(*self.rom_data.get(0x147)?);
+        let bank = match rom_type {
+            RomType::Mbc1 | RomType::Mbc1Ram | RomType::Mbc1RamBattery
+                if (0x2000..0x4000).contains(&address) =>
+            {
+                (value & 0x1F).max(1) as u16
+            }
+            RomType::Mbc2 | RomType::Mbc2Battery if address < 0x4000 && address & 0x0100 != 0 => {
+                (value & 0x0F).max(1) as u16
+            }
+            RomType::Mbc3TimerBattery
+            | RomType::Mbc3TimerRamBattery
+            | RomType::Mbc3
+            | RomType::Mbc3Ram
+            | RomType::Mbc3RamBattery
+            | RomType::Huc3
+                if (0x2000..0x4000).contains(&address) =>
+            {
+                (value & 0x7F).max(1) as u16
+            }
+            RomType::Huc1 if (0x2000..0x4000).contains(&address) => (value & 0x3F).max(1) as u16,
+            // bank 0 can be mapped in to the switchable bank on the MBC5 but
+            // that can't be expressed as a `BankedAddress`
+            RomType::Mbc5
+            | RomType::Mbc5Ram
+            | RomType::Mbc5RamBattery
+            | RomType::Mbc5Rumble
+            | RomType::Mbc5RumbleSram
+            | RomType::Mbc5RumbleSramBattery
+                if (0x2000..0x3000).contains(&address) && value != 0 =>
+            {
+                value as u16
+            }
+            _ => return None,
+        };
+        let rom_banks = self
+            .rom_data
+            .get(0x148)
+            .and_then(|byte| translate_rom_size(*byte))
+            .unwrap_or(2);
+        Some(bank % rom_banks).filter(|bank| *bank != 0)
+    }
+
+    /// Whether the `rst` vector at `address` looks like a routine that
+    /// dispatches through a table stored inline after the `rst`.
+    fn is_table_dispatcher(&self, address: u16) -> bool {
+        let mut location = BankedAddress::new(0, address);
+        let mut pops_return_address = false;
+        // the handlers are usually tiny, anything longer is not a dispatcher
+        for _ in 0..32 {
+            let instruction = match self.decode(location, 1) {
+                Some(instruction) => instruction,
+                None => return false,
+            };
+            let next = match instruction.opcode {
+                Opcode::Pop(Register16::HL) => {
+                    pops_return_address = true;
+                    location.address.wrapping_add(instruction.size as u16)
+                }
+                Opcode::JpHl => return pops_return_address,
+                Opcode::Jp(None, target) => target,
+                Opcode::Jr(None, offset) => location
+                    .address
+                    .wrapping_add(2)
+                    .wrapping_add(offset as i8 as u16),
+                Opcode::Ret(_) | Opcode::Reti | Opcode::Rst(_) => return false,
+                _ => location.address.wrapping_add(instruction.size as u16),
+            };
+            if next >= 0x4000 {
+                return false;
+            }
+            location = BankedAddress::new(0, next);
+        }
+        false
+    }
+
+    /// Read pointers from a jump table until they stop looking like code
+    /// addresses or the table runs into known code. The pointers are resolved
+    /// with `rom_bank` mapped in, as when the table is dispatched.
+    fn read_jump_table(
+        &self,
+        table: BankedAddress,
+        rom_bank: u16,
+        disassembly: &Disassembly,
+    ) -> Vec<BankedAddress> {
+        let mut entries = vec![];
+        let mut cursor = table;
+        // inline tables are usually directly followed by the code of one of
+        // their entries
+        let mut end = None;
+        while entries.len() < MAX_JUMP_TABLE_ENTRIES {
+            if end.map(|end| cursor.address >= end).unwrap_or(false)
+                || cursor.address >= 0x8000
+                || disassembly.instructions.contains_key(&cursor)
+            {
+                break;
+            }
+            let pointer = match cursor
+                .rom_offset()
+                .and_then(|offset| self.rom_data.get(offset..offset + 2))
+            {
+                Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+                None => break,
+            };
+            // reject pointers into the cartridge header and out of ROM
+            if pointer >= 0x8000 || (0x0104..0x0150).contains(&pointer) {
+                break;
+            }
+            if pointer > table.address && (pointer >= 0x4000) == (table.address >= 0x4000) {
+                end = Some(end.map_or(pointer, |end: u16| end.min(pointer)));
+            }
+            entries.push(BankedAddress::resolve(rom_bank, pointer));
+            cursor = BankedAddress::resolve(cursor.bank, cursor.address.wrapping_add(2));
+        }
+        entries
+    }
+}
+
+/// Register values known at a point in a straight-line run of instructions.
+#[derive(Debug, Clone, Copy, Default)]
+struct KnownValues {
+    a: Option<u8>,
+    hl: Option<u16>,
+}
+
+impl KnownValues {
+    /// The address and value of a memory write performed by `opcode`, if both
+    /// are known.
+    fn memory_write(&self, opcode: &Opcode) -> Option<(u16, u8)> {
+        match *opcode {
+            Opcode::StoreAAtAddress(address) => Some((address, self.a?)),
+            Opcode::StoreAToHlAddr(_) | Opcode::Mov8(Register8::DerefHL, Register8::A) => {
+                Some((self.hl?, self.a?))
+            }
+            Opcode::StoreImm8(Register8::DerefHL, value) => Some((self.hl?, value)),
+            _ => None,
+        }
+    }
+
+    /// Update the known values to reflect the effects of `opcode`.
+    fn update(&mut self, opcode: &Opcode) {
+        match *opcode {
+            Opcode::StoreImm8(Register8::A, value) => self.a = Some(value),
+            Opcode::Xor(Register8::A) | Opcode::Sub(Register8::A) => self.a = Some(0),
+            Opcode::Inc8(Register8::A) => self.a = self.a.map(|a| a.wrapping_add(1)),
+            Opcode::Dec8(Register8::A) => self.a = self.a.map(|a| a.wrapping_sub(1)),
+            Opcode::StoreImm16(Register16::HL, value) => self.hl = Some(value),
+            Opcode::Inc16(Register16::HL) => self.hl = self.hl.map(|hl| hl.wrapping_add(1)),
+            Opcode::Dec16(Register16::HL) => self.hl = self.hl.map(|hl| hl.wrapping_sub(1)),
+            Opcode::StoreAToHlAddr(inc) => {
+                self.hl = self.hl.map(|hl| {
+                    if inc {
+                        hl.wrapping_add(1)
+                    } else {
+                        hl.wrapping_sub(1)
+                    }
+                })
+            }
+            Opcode::LoadAFromHlAddr(inc) => {
+                self.a = None;
+                self.hl = self.hl.map(|hl| {
+                    if inc {
+                        hl.wrapping_add(1)
+                    } else {
+                        hl.wrapping_sub(1)
+                    }
+                })
+            }
+            // the callee may do anything
+            Opcode::Call(_, _) | Opcode::Rst(_) => *self = Self::default(),
+            Opcode::Pop(Register16::HL) | Opcode::AddHl(_) | Opcode::LdHlSp8(_) => self.hl = None,
+            Opcode::Pop(Register16::AF)
+            | Opcode::Add8(_)
+            | Opcode::Adc8(_)
+            | Opcode::Sub8(_)
+            | Opcode::Sbc8(_)
+            | Opcode::And8(_)
+            | Opcode::Xor8(_)
+            | Opcode::Or8(_)
+            | Opcode::Daa
+            | Opcode::Cpl
+            | Opcode::Rlca
+            | Opcode::Rla
+            | Opcode::Rrca
+            | Opcode::Rra
+            | Opcode::LoadHA(_)
+            | Opcode::LoadCA
+            | Opcode::LoadAFromAddress(_)
+            | Opcode::LoadAFromReg16Addr(_)
+            | Opcode::Add(_)
+            | Opcode::Adc(_)
+            | Opcode::Sub(_)
+            | Opcode::Sbc(_)
+            | Opcode::And(_)
+            | Opcode::Xor(_)
+            | Opcode::Or(_) => self.a = None,
+            _ => {
+                if let Some(register) = written_register8(opcode) {
+                    match register {
+                        Register8::A => self.a = None,
+                        Register8::H | Register8::L => self.hl = None,
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The 8 bit register explicitly written to by `opcode`.
+fn written_register8(opcode: &Opcode) -> Option<Register8> {
+    match *opcode {
+        Opcode::Mov8(register, _)
+        | Opcode::StoreImm8(register, _)
+        | Opcode::Inc8(register)
+        | Opcode::Dec8(register)
+        | Opcode::Rlc(register)
+        | Opcode::Rrc(register)
+        | Opcode::Rl(register)
+        | Opcode::Rr(register)
+        | Opcode::Sla(register)
+        | Opcode::Sra(register)
+        | Opcode::Swap(register)
+        | Opcode::Srl(register)
+        | Opcode::Res(_, register)
+        | Opcode::Set(_, register) => Some(register),
+        _ => None,
+    }
+}
+
+/// Find the table that a `jp hl` at the end of `block` dispatches through.
+fn indexed_jump_table(block: &[Instruction]) -> Option<u16> {
+    let mut loads_pointer = false;
+    for instruction in block.iter().rev() {
+        match instruction.opcode {
+            Opcode::LoadAFromHlAddr(_)
+            | Opcode::Mov8(Register8::H, Register8::DerefHL)
+            | Opcode::Mov8(Register8::L, Register8::DerefHL) => loads_pointer = true,
+            Opcode::StoreImm16(Register16::HL, table) if loads_pointer => return Some(table),
+            Opcode::StoreImm16(Register16::HL, _)
+            | Opcode::Pop(Register16::HL)
+            | Opcode::LdHlSp8(_) => return None,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The statically known targets of an instruction and whether execution may
+/// continue with the next instruction.
+fn control_flow(instruction: &Instruction) -> (Vec<(u16, XRefKind)>, bool) {
+    let address = instruction.location.address;
+    match instruction.opcode {
+        Opcode::Jr(flag, offset) => {
+            let target = address.wrapping_add(2).wrapping_add(offset as i8 as u16);
+            (vec![(target, XRefKind::Jump)], flag.is_some())
+        }
+        Opcode::Jp(flag, target) => (vec![(target, XRefKind::Jump)], flag.is_some()),
+        Opcode::Call(_, target) => (vec![(target, XRefKind::Call)], true),
+        Opcode::Rst(n) => (vec![(n as u16 * 8, XRefKind::Call)], true),
+        Opcode::Ret(flag) => (vec![], flag.is_some()),
+        Opcode::Reti | Opcode::JpHl => (vec![], false),
+        _ => (vec![], true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM of `0x8000 << size_code` bytes of `ret` for the cartridge type,
+    /// with the entry point jumping to 0x150 and `code` at the given offsets.
+    fn rom(cartridge_type: u8, size_code: u8, code: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut rom = vec![0xC9; 0x8000 << size_code];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x147] = cartridge_type;
+        rom[0x148] = size_code;
+        for (offset, bytes) in code {
+            rom[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        rom
+    }
+
+    fn at(bank: u16, address: u16) -> BankedAddress {
+        BankedAddress::new(bank, address)
+    }
+
+    fn has_xref(
+        disassembly: &Disassembly,
+        to: BankedAddress,
+        from: BankedAddress,
+        kind: XRefKind,
+    ) -> bool {
+        disassembly
+            .xrefs
+            .get(&to)
+            .is_some_and(|xrefs| xrefs.contains(&XRef { from, kind }))
+    }
+
+    #[test]
+    fn rst_jump_table() {
+        let rom = rom(
+            0x00,
+            0,
+            &[
+                // pop hl, jp hl
+                (0x0000, &[0xE1, 0xE9]),
+                // rst $00, dw $0200, $0210
+                (0x0150, &[0xC7, 0x00, 0x02, 0x10, 0x02]),
+                // ld a, 1
+                (0x0200, &[0x3E, 0x01]),
+                // xor a
+                (0x0210, &[0xAF]),
+            ],
+        );
+        let disassembly = Disassembler::new(&rom).run();
+
+        let entries = vec![at(0, 0x0200), at(0, 0x0210)];
+        assert_eq!(
+            disassembly.jump_tables,
+            vec![(at(0, 0x0151), entries.clone())].into_iter().collect()
+        );
+        assert_eq!(
+            disassembly.instructions[&entries[0]].opcode,
+            Opcode::StoreImm8(Register8::A, 1)
+        );
+        assert_eq!(
+            disassembly.instructions[&entries[1]].opcode,
+            Opcode::Xor(Register8::A)
+        );
+        for entry in &entries {
+            assert!(has_xref(
+                &disassembly,
+                *entry,
+                at(0, 0x0150),
+                XRefKind::Table
+            ));
+        }
+        // the table isn't code
+        assert!(!disassembly.instructions.contains_key(&at(0, 0x0151)));
+        assert!(!disassembly.instructions.contains_key(&at(0, 0x0153)));
+    }
+
+    #[test]
+    fn jp_hl_jump_table() {
+        let rom = rom(
+            0x00,
+            0,
+            &[
+                (
+                    0x0150,
+                    &[
+                        0x21, 0x60, 0x01, // ld hl, $0160
+                        0x87, // add a, a
+                        0x5F, // ld e, a
+                        0x16, 0x00, // ld d, 0
+                        0x19, // add hl, de
+                        0x2A, // ld a, [hl+]
+                        0x66, // ld h, [hl]
+                        0x6F, // ld l, a
+                        0xE9, // jp hl
+                    ],
+                ),
+                // dw $4000, $4008
+                (0x0160, &[0x00, 0x40, 0x08, 0x40]),
+                (0x4000, &[0x3E, 0x05]),
+                (0x4008, &[0x3E, 0x06]),
+            ],
+        );
+        let disassembly = Disassembler::new(&rom).run();
+
+        let entries = vec![at(1, 0x4000), at(1, 0x4008)];
+        assert_eq!(
+            disassembly.jump_tables,
+            vec![(at(0, 0x0160), entries.clone())].into_iter().collect()
+        );
+        assert_eq!(
+            disassembly.instructions[&entries[0]].opcode,
+            Opcode::StoreImm8(Register8::A, 5)
+        );
+        assert_eq!(
+            disassembly.instructions[&entries[1]].opcode,
+            Opcode::StoreImm8(Register8::A, 6)
+        );
+        for entry in &entries {
+            assert!(has_xref(
+                &disassembly,
+                *entry,
+                at(0, 0x015B),
+                XRefKind::Table
+            ));
+        }
+    }
+
+    #[test]
+    fn mbc1_bank_switch() {
+        let rom = rom(
+            0x01,
+            1,
+            &[(
+                0x0150,
+                &[
+                    0x3E, 0x03, // ld a, 3
+                    0xEA, 0x00, 0x20, // ld [$2000], a
+                    0xCD, 0x00, 0x40, // call $4000
+                ],
+            )],
+        );
+        let disassembly = Disassembler::new(&rom).run();
+        assert!(has_xref(
+            &disassembly,
+            at(3, 0x4000),
+            at(0, 0x0155),
+            XRefKind::Call
+        ));
+        assert_eq!(disassembly.instructions[&at(3, 0x4000)].rom_bank, 3);
+        assert!(!disassembly.instructions.contains_key(&at(1, 0x4000)));
+    }
+
+    #[test]
+    fn mbc5_bank_switch_through_hl() {
+        let rom = rom(
+            0x19,
+            2,
+            &[(
+                0x0150,
+                &[
+                    0x21, 0x00, 0x21, // ld hl, $2100
+                    0x36, 0x05, // ld [hl], 5
+                    0xCD, 0x00, 0x40, // call $4000
+                    0xAF, // xor a
+                    0x22, // ld [hl+], a
+                    0xCD, 0x10, 0x40, // call $4010
+                ],
+            )],
+        );
+        let disassembly = Disassembler::new(&rom).run();
+        assert!(has_xref(
+            &disassembly,
+            at(5, 0x4000),
+            at(0, 0x0155),
+            XRefKind::Call
+        ));
+        // bank 0 can't be a `BankedAddress` in 0x4000-0x7FFF, so bank 5 stays
+        assert!(has_xref(
+            &disassembly,
+            at(5, 0x4010),
+            at(0, 0x015A),
+            XRefKind::Call
+        ));
+    }
+
+    #[test]
+    fn unknown_bank_keeps_bank_1() {
+        let rom = rom(
+            0x01,
+            1,
+            &[(
+                0x0150,
+                &[
+                    0xFA, 0x00, 0xC0, // ld a, [$C000]
+                    0xEA, 0x00, 0x20, // ld [$2000], a
+                    0xCD, 0x00, 0x40, // call $4000
+                ],
+            )],
+        );
+        let disassembly = Disassembler::new(&rom).run();
+        assert!(has_xref(
+            &disassembly,
+            at(1, 0x4000),
+            at(0, 0x0156),
+            XRefKind::Call
+        ));
+    }
+
+    #[test]
+    fn switchable_bank_code_keeps_its_bank() {
+        let rom = rom(
+            0x01,
+            1,
+            &[
+                (
+                    0x0150,
+                    &[
+                        0x3E, 0x02, // ld a, 2
+                        0xEA, 0x00, 0x20, // ld [$2000], a
+                        0xC3, 0x00, 0x40, // jp $4000
+                    ],
+                ),
+                (
+                    0x8000,
+                    &[
+                        0x3E, 0x03, // ld a, 3
+                        0xEA, 0x00, 0x20, // ld [$2000], a
+                        0xCD, 0x00, 0x41, // call $4100
+                    ],
+                ),
+            ],
+        );
+        let disassembly = Disassembler::new(&rom).run();
+        assert!(has_xref(
+            &disassembly,
+            at(2, 0x4000),
+            at(0, 0x0155),
+            XRefKind::Jump
+        ));
+        assert!(has_xref(
+            &disassembly,
+            at(2, 0x4100),
+            at(2, 0x4005),
+            XRefKind::Call
+        ));
+    }
+
+    #[test]
+    fn jump_tables_in_bank_0_use_the_selected_bank() {
+        let rom = rom(
+            0x01,
+            1,
+            &[
+                // pop hl, jp hl
+                (0x0000, &[0xE1, 0xE9]),
+                (
+                    0x0150,
+                    &[
+                        0x3E, 0x02, // ld a, 2
+                        0xEA, 0x00, 0x20, // ld [$2000], a
+                        0xC7, // rst $00
+                        0x00, 0x40, // dw $4000
+                    ],
+                ),
+                (
+                    0x0200,
+                    &[
+                        0x3E, 0x03, // ld a, 3
+                        0xEA, 0x00, 0x20, // ld [$2000], a
+                        0x21, 0x20, 0x02, // ld hl, $0220
+                        0x2A, // ld a, [hl+]
+                        0x66, // ld h, [hl]
+                        0x6F, // ld l, a
+                        0xE9, // jp hl
+                    ],
+                ),
+                // dw $4010
+                (0x0220, &[0x10, 0x40]),
+            ],
+        );
+        let mut disassembler = Disassembler::new(&rom);
+        disassembler.add_entry_point(at(0, 0x0200));
+        let disassembly = disassembler.run();
+        assert_eq!(disassembly.jump_tables[&at(0, 0x0156)], vec![at(2, 0x4000)]);
+        assert_eq!(disassembly.instructions[&at(2, 0x4000)].rom_bank, 2);
+        assert_eq!(disassembly.jump_tables[&at(0, 0x0220)], vec![at(3, 0x4010)]);
+        assert_eq!(disassembly.instructions[&at(3, 0x4010)].rom_bank, 3);
+    }
+}
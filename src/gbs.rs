@@ -0,0 +1,371 @@
+//! Game Boy Sound (GBS) files, music ripped from games.
+//!
+//! A GBS file is a 0x70 byte header followed by the code and data to load at
+//! the header's load address. The init routine is called once with the song
+//! number in A, then the play routine is called at the rate of VBlank or of
+//! the timer interrupt configured by the header.
+//!
+//! With the `emulation` feature a [`GbsPlayer`] runs the routines on the
+//! crate's CPU and APU to render the music.
+//!
+//! The format is described in the [GBS specification].
+//!
+//! [GBS specification]: https://ocremix.org/info/GBS_Format_Specification
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "emulation")]
+use crate::apu::{Apu, CPU_CLOCK};
+#[cfg(feature = "emulation")]
+use crate::cartridge::ROM_BANK_SIZE;
+#[cfg(feature = "emulation")]
+use crate::cpu::{Bus, Cpu, Registers};
+use crate::parser;
+
+/// The size of the GBS header in bytes.
+pub const GBS_HEADER_SIZE: usize = 0x70;
+
+/// T-cycles between VBlanks.
+const VBLANK_PERIOD: u32 = 70224;
+
+/// The GBS header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GbsHeader {
+    /// Always 1
+    pub version: u8,
+    pub song_count: u8,
+    /// The song to play by default, starting from 1
+    pub first_song: u8,
+    /// Where the data following the header is loaded
+    pub load_address: u16,
+    /// Called once with the song number (starting from 0) in A
+    pub init_address: u16,
+    /// Called at the rate set by the timer fields
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    /// Written to TMA
+    pub timer_modulo: u8,
+    /// Written to TAC, if bit 2 is clear the play routine is called on
+    /// VBlank instead of the timer interrupt. Bit 7 selects CGB double speed.
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    /// The number of T-cycles between calls to the play routine.
+    pub fn play_period(&self) -> u32 {
+        if self.timer_control & 0x04 == 0 {
+            return VBLANK_PERIOD;
+        }
+        let divider = match self.timer_control & 0x03 {
+            0 => 1024,
+            1 => 16,
+            2 => 64,
+            _ => 256,
+        };
+        let period = divider * (256 - self.timer_modulo as u32);
+        if self.double_speed() {
+            period / 2
+        } else {
+            period
+        }
+    }
+
+    /// Whether the music expects a CGB running in double speed mode.
+    pub fn double_speed(&self) -> bool {
+        self.timer_control & 0x80 != 0
+    }
+}
+
+/// A parsed GBS file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GbsFile<'a> {
+    pub header: GbsHeader,
+    /// The code and data loaded at `header.load_address`.
+    pub data: &'a [u8],
+}
+
+impl<'a> GbsFile<'a> {
+    /// Parse a GBS file.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, String> {
+        let (data, header) =
+            parser::parse_gbs_header(bytes).map_err(|e| format!("Failed to parse GBS: {:?}", e))?;
+        if header.version != 1 {
+            return Err(format!("Unsupported GBS version {}", header.version));
+        }
+        Ok(Self { header, data })
+    }
+}
+
+/// Where the init and play routines return to. Nothing can run from here, so
+/// the player knows a routine is done when PC reaches it.
+#[cfg(feature = "emulation")]
+const RETURN_ADDRESS: u16 = 0xFEA0;
+
+/// Errors that may occur while starting a `GbsPlayer`.
+#[cfg(feature = "emulation")]
+#[derive(Debug, PartialEq, Eq)]
+pub enum GbsError {
+    /// The song number (starting from 0) is past the end of the file.
+    NoSuchSong(u8),
+}
+
+/// The memory map seen by GBS code: banked ROM with a simple MBC, RAM from
+/// 0x8000 to 0xDFFF and the sound registers.
+#[cfg(feature = "emulation")]
+struct GbsBus {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    hram: [u8; 0x7F],
+    apu: Apu,
+    /// Registers without any behavior, kept so that reads return what was
+    /// written.
+    io: [u8; 0x100],
+}
+
+#[cfg(feature = "emulation")]
+impl Bus for GbsBus {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.rom[address as usize],
+            0x4000..=0x7FFF => {
+                let banks = self.rom.len() / ROM_BANK_SIZE;
+                self.rom[(self.rom_bank % banks) * ROM_BANK_SIZE + (address as usize - 0x4000)]
+            }
+            0x8000..=0xDFFF => self.ram[address as usize - 0x8000],
+            0xE000..=0xFDFF => self.ram[address as usize - 0xA000],
+            0xFF10..=0xFF3F => self.apu.read(address),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80],
+            0xFF00..=0xFFFF => self.io[address as usize - 0xFF00],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x2000..=0x3FFF => self.rom_bank = (value as usize).max(1),
+            0x8000..=0xDFFF => self.ram[address as usize - 0x8000] = value,
+            0xE000..=0xFDFF => self.ram[address as usize - 0xA000] = value,
+            0xFF10..=0xFF3F => self.apu.write(address, value),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80] = value,
+            0xFF00..=0xFFFF => self.io[address as usize - 0xFF00] = value,
+            _ => (),
+        }
+    }
+}
+
+/// Plays a song from a GBS file.
+///
+/// ```no_run
+/// # fn example(bytes: &[u8]) -> Result<(), String> {
+/// use gameboy_rom::gbs::{GbsFile, GbsPlayer};
+///
+/// let gbs = GbsFile::parse(bytes)?;
+/// let mut player = GbsPlayer::new(&gbs, 0, 48_000).map_err(|e| format!("{:?}", e))?;
+/// // 10 seconds of interleaved stereo samples
+/// let samples = player.render_seconds(10);
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "emulation")]
+pub struct GbsPlayer {
+    cpu: Cpu,
+    bus: GbsBus,
+    play_address: u16,
+    stack_pointer: u16,
+    play_period: u32,
+    double_speed: bool,
+    /// T-cycles until the play routine is next called.
+    until_play: u32,
+    /// Whether the CPU is still running the init or play routine.
+    in_routine: bool,
+}
+
+#[cfg(feature = "emulation")]
+impl GbsPlayer {
+    /// Load the GBS file and call its init routine for `song`, which starts
+    /// from 0.
+    pub fn new(gbs: &GbsFile, song: u8, sample_rate: u32) -> Result<Self, GbsError> {
+        let header = &gbs.header;
+        if song >= header.song_count {
+            return Err(GbsError::NoSuchSong(song));
+        }
+
+        let load_address = header.load_address as usize;
+        let size = (load_address + gbs.data.len()).max(2 * ROM_BANK_SIZE);
+        let mut rom = vec![0; size.div_ceil(ROM_BANK_SIZE) * ROM_BANK_SIZE];
+        // the RST vectors are relocated to the load address
+        for vector in (0..0x40).step_by(8) {
+            if vector + 3 <= load_address {
+                let [lo, hi] = ((load_address + vector) as u16).to_le_bytes();
+                rom[vector..vector + 3].copy_from_slice(&[0xC3, lo, hi]);
+            }
+        }
+        rom[load_address..load_address + gbs.data.len()].copy_from_slice(gbs.data);
+
+        let mut bus = GbsBus {
+            rom,
+            rom_bank: 1,
+            ram: vec![0; 0x6000],
+            hram: [0; 0x7F],
+            apu: Apu::new(sample_rate),
+            io: [0; 0x100],
+        };
+        bus.write(0xFF26, 0x80);
+        bus.write(0xFF24, 0x77);
+        bus.write(0xFF25, 0xFF);
+        bus.write(0xFF06, header.timer_modulo);
+        bus.write(0xFF07, header.timer_control);
+
+        let mut player = Self {
+            cpu: Cpu::new(Registers {
+                a: song,
+                sp: header.stack_pointer,
+                ..Registers::default()
+            }),
+            bus,
+            play_address: header.play_address,
+            stack_pointer: header.stack_pointer,
+            play_period: header.play_period(),
+            double_speed: header.double_speed(),
+            until_play: 0,
+            in_routine: false,
+        };
+        player.call(header.init_address);
+        Ok(player)
+    }
+
+    fn call(&mut self, address: u16) {
+        self.cpu.push(&mut self.bus, RETURN_ADDRESS);
+        self.cpu.registers.pc = address;
+        self.in_routine = true;
+    }
+
+    /// Run for the given number of T-cycles and return the samples rendered,
+    /// interleaved left then right.
+    pub fn render(&mut self, cycles: u64) -> Vec<i16> {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            if self.until_play == 0 {
+                if self.in_routine && (self.cpu.halted || self.cpu.locked) {
+                    // the routine is waiting for an interrupt that will never
+                    // come, give up on it
+                    self.cpu = Cpu::new(Registers {
+                        sp: self.stack_pointer,
+                        ..self.cpu.registers
+                    });
+                    self.in_routine = false;
+                }
+                // a routine that's still running carries on instead
+                if !self.in_routine {
+                    self.call(self.play_address);
+                }
+                self.until_play = self.play_period;
+            }
+
+            let elapsed = if self.in_routine && !self.cpu.halted && !self.cpu.locked {
+                let cycles = self.cpu.step(&mut self.bus) as u32;
+                if self.cpu.registers.pc == RETURN_ADDRESS {
+                    self.in_routine = false;
+                }
+                if self.double_speed {
+                    cycles / 2
+                } else {
+                    cycles
+                }
+            } else {
+                self.until_play.min(remaining.min(u32::MAX as u64) as u32)
+            };
+            self.bus.apu.step(elapsed);
+            self.until_play = self.until_play.saturating_sub(elapsed);
+            remaining = remaining.saturating_sub(elapsed as u64);
+        }
+        self.bus.apu.take_samples()
+    }
+
+    /// Render the given number of seconds of audio.
+    pub fn render_seconds(&mut self, seconds: u32) -> Vec<i16> {
+        self.render(seconds as u64 * CPU_CLOCK as u64)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.bus.apu.sample_rate()
+    }
+}
+
+#[cfg(all(test, feature = "emulation"))]
+mod tests {
+    use super::*;
+
+    /// A GBS file with one song, loading `init` at 0x400 followed by `play`
+    /// and calling `play` on VBlank.
+    fn gbs(init: &[u8], play: &[u8]) -> Vec<u8> {
+        let play_address = 0x400 + init.len() as u16;
+        let mut bytes = b"GBS\x01\x01\x01".to_vec();
+        for address in [0x400, 0x400, play_address, 0xFFFE].iter() {
+            bytes.extend_from_slice(&u16::to_le_bytes(*address));
+        }
+        bytes.extend_from_slice(&[0x00, 0x00]);
+        for field in [&b"Test"[..], b"Author", b"2024"].iter() {
+            let mut string = [0; 32];
+            string[..field.len()].copy_from_slice(field);
+            bytes.extend_from_slice(&string);
+        }
+        assert_eq!(bytes.len(), GBS_HEADER_SIZE);
+        bytes.extend_from_slice(init);
+        bytes.extend_from_slice(play);
+        bytes
+    }
+
+    /// Sets up a 50% duty square wave on channel 2 at full volume.
+    const INIT: &[u8] = &[
+        0x3E, 0x80, // ld a, $80
+        0xE0, 0x16, // ldh (NR21), a
+        0x3E, 0xF0, // ld a, $F0
+        0xE0, 0x17, // ldh (NR22), a
+        0xC9, // ret
+    ];
+
+    #[test]
+    fn play() {
+        let bytes = gbs(
+            INIT,
+            &[
+                0x3E, 0x87, // ld a, $87
+                0xE0, 0x19, // ldh (NR24), a
+                0xC9, // ret
+            ],
+        );
+        let gbs = GbsFile::parse(&bytes).unwrap();
+        assert_eq!(gbs.header.title, "Test");
+        let mut player = GbsPlayer::new(&gbs, 0, 48_000).unwrap();
+        let samples = player.render_seconds(2);
+        assert_eq!(samples.len(), 2 * 48_000 * 2);
+        assert!(samples.iter().any(|sample| *sample != 0));
+        assert_eq!(
+            GbsPlayer::new(&gbs, 1, 48_000).err(),
+            Some(GbsError::NoSuchSong(1))
+        );
+    }
+
+    #[test]
+    fn halted_routine_is_abandoned() {
+        let bytes = gbs(
+            INIT,
+            &[
+                0x21, 0x00, 0xC0, // ld hl, $C000
+                0x34, // inc (hl)
+                0x76, // halt
+            ],
+        );
+        let gbs = GbsFile::parse(&bytes).unwrap();
+        let mut player = GbsPlayer::new(&gbs, 0, 0).unwrap();
+        player.render_seconds(1);
+        // init runs in the first of the 60 VBlank periods, the play routine
+        // is called again on each of the others instead of waiting forever
+        assert_eq!(player.bus.ram[0xC000 - 0x8000], 59);
+    }
+}
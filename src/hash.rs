@@ -0,0 +1,163 @@
+//! Hashes identifying a ROM dump, as listed in DAT files.
+//!
+//! ```
+//! use gameboy_rom::hash::to_hex;
+//! use gameboy_rom::GameBoyRom;
+//!
+//! let rom = vec![0; 0x8000];
+//! let hashes = GameBoyRom::new(&rom).hashes();
+//! assert_eq!(format!("{:08x}", hashes.crc32), "011ffca6");
+//! assert_eq!(
+//!     to_hex(&hashes.sha1),
+//!     "5188431849b4613152fd7bdba6a3ff0a4fd6424b"
+//! );
+//! ```
+
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// How much of the ROM is fed to every hasher before moving on, so the ROM
+/// is only read from memory once.
+const CHUNK_SIZE: usize = 0x4000;
+
+/// CRC32, MD5, SHA-1 and SHA-256 of a ROM. Serialized as lower case hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RomHashes {
+    #[serde(with = "crc_hex")]
+    pub crc32: u32,
+    #[serde(with = "hex")]
+    pub md5: [u8; 16],
+    #[serde(with = "hex")]
+    pub sha1: [u8; 20],
+    #[serde(with = "hex")]
+    pub sha256: [u8; 32],
+}
+
+impl RomHashes {
+    /// Hash `bytes` with every algorithm in a single pass.
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut crc32 = crc32fast::Hasher::new();
+        let mut md5 = Md5::new();
+        let mut sha1 = Sha1::new();
+        let mut sha256 = Sha256::new();
+        for chunk in bytes.chunks(CHUNK_SIZE) {
+            crc32.update(chunk);
+            md5.update(chunk);
+            sha1.update(chunk);
+            sha256.update(chunk);
+        }
+        Self {
+            crc32: crc32.finalize(),
+            md5: md5.finalize().into(),
+            sha1: sha1.finalize().into(),
+            sha256: sha256.finalize().into(),
+        }
+    }
+}
+
+/// Format bytes as lower case hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parse hex of either case into exactly `N` bytes.
+pub fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (idx, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+mod hex {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        super::from_hex(&hex).ok_or_else(|| D::Error::custom(format!("invalid hash {:?}", hex)))
+    }
+}
+
+mod crc_hex {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(crc: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:08x}", crc))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        super::from_hex::<4>(&hex)
+            .map(u32::from_be_bytes)
+            .ok_or_else(|| D::Error::custom(format!("invalid CRC32 {:?}", hex)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_hashes() {
+        let hashes = RomHashes::new(b"abc");
+        assert_eq!(hashes.crc32, 0x3524_41C2);
+        assert_eq!(to_hex(&hashes.md5), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            to_hex(&hashes.sha1),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            to_hex(&hashes.sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn chunked() {
+        let rom = (0..CHUNK_SIZE * 3 + 5)
+            .map(|idx| idx as u8)
+            .collect::<Vec<_>>();
+        let hashes = RomHashes::new(&rom);
+        assert_eq!(hashes.crc32, crc32fast::hash(&rom));
+        assert_eq!(hashes.sha256, <[u8; 32]>::from(Sha256::digest(&rom)));
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(from_hex::<2>("0aFf"), Some([0x0A, 0xFF]));
+        assert_eq!(from_hex::<2>("0aF"), None);
+        assert_eq!(from_hex::<2>("0aFf00"), None);
+        assert_eq!(from_hex::<2>("0aFg"), None);
+        assert_eq!(from_hex::<1>("é"), None);
+        assert_eq!(to_hex(&[0x0A, 0xFF]), "0aff");
+    }
+
+    #[test]
+    fn serde_hex() {
+        let hashes = RomHashes::new(b"abc");
+        let json = serde_json::to_value(hashes).unwrap();
+        assert_eq!(json["crc32"], "352441c2");
+        assert_eq!(json["md5"], "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(serde_json::from_value::<RomHashes>(json).unwrap(), hashes);
+
+        let mut json = serde_json::to_value(hashes).unwrap();
+        json["sha1"] = "abc".into();
+        assert!(serde_json::from_value::<RomHashes>(json).is_err());
+    }
+}
@@ -16,10 +16,7 @@ pub enum GameboyColorCompatibility {
 impl GameboyColorCompatibility {
     /// Whether or not the ROM declares it uses GameBoy Color features
     pub const fn supports_color(self) -> bool {
-        match self {
-            GameboyColorCompatibility::Monochrome => false,
-            _ => true,
-        }
+        !matches!(self, GameboyColorCompatibility::Monochrome)
     }
 }
 
@@ -0,0 +1,256 @@
+//! The IE and IF registers and interrupt priorities.
+//!
+//! Components request interrupts by returning IF bits, which are collected in
+//! an [`InterruptController`]. The CPU dispatches them through
+//! `Cpu::handle_interrupts`, reading IE and IF over its bus like the real
+//! hardware does.
+//!
+//! ```
+//! use gameboy_rom::cpu::{Bus, Cpu, Registers};
+//! use gameboy_rom::interrupts::{InterruptController, INTERRUPT_TIMER};
+//!
+//! struct Memory(Vec<u8>, InterruptController);
+//!
+//! impl Bus for Memory {
+//!     fn read(&mut self, address: u16) -> u8 {
+//!         match address {
+//!             0xFF0F | 0xFFFF => self.1.read(address),
+//!             _ => self.0[address as usize],
+//!         }
+//!     }
+//!     fn write(&mut self, address: u16, value: u8) {
+//!         match address {
+//!             0xFF0F | 0xFFFF => self.1.write(address, value),
+//!             _ => self.0[address as usize] = value,
+//!         }
+//!     }
+//! }
+//!
+//! let mut memory = Memory(vec![0; 0x10000], InterruptController::new());
+//! let mut cpu = Cpu::new(Registers::dmg_post_boot());
+//! cpu.ime = true;
+//! memory.1.enable = INTERRUPT_TIMER;
+//! memory.1.request(INTERRUPT_TIMER);
+//! assert_eq!(cpu.handle_interrupts(&mut memory), Some(20));
+//! assert_eq!(cpu.registers.pc, 0x50);
+//! assert_eq!(memory.1.flag, 0);
+//! ```
+
+use crate::cpu::Bus;
+
+use serde::{Deserialize, Serialize};
+
+/// The VBlank bit of the IF and IE registers.
+pub const INTERRUPT_VBLANK: u8 = 0x01;
+/// The LCD STAT bit of the IF and IE registers.
+pub const INTERRUPT_STAT: u8 = 0x02;
+/// The timer bit of the IF and IE registers.
+pub const INTERRUPT_TIMER: u8 = 0x04;
+/// The serial bit of the IF and IE registers.
+pub const INTERRUPT_SERIAL: u8 = 0x08;
+/// The joypad bit of the IF and IE registers.
+pub const INTERRUPT_JOYPAD: u8 = 0x10;
+
+/// The five interrupt sources, in priority order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// The highest priority interrupt set in `bits`.
+    pub fn highest_priority(bits: u8) -> Option<Self> {
+        Some(match (bits & 0x1F).trailing_zeros() {
+            0 => Interrupt::VBlank,
+            1 => Interrupt::Stat,
+            2 => Interrupt::Timer,
+            3 => Interrupt::Serial,
+            4 => Interrupt::Joypad,
+            _ => return None,
+        })
+    }
+
+    /// The interrupt's bit in IE and IF.
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// The address of the interrupt handler.
+    pub fn vector(self) -> u16 {
+        0x40 + self as u16 * 8
+    }
+}
+
+/// The IE and IF registers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterruptController {
+    /// IE, the interrupts that may be dispatched.
+    pub enable: u8,
+    /// IF, the interrupts that have been requested.
+    pub flag: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request the interrupts set in `bits`.
+    pub fn request(&mut self, bits: u8) {
+        self.flag |= bits & 0x1F;
+    }
+
+    /// The requested interrupts that are enabled.
+    pub fn pending(&self) -> u8 {
+        self.enable & self.flag & 0x1F
+    }
+}
+
+impl Bus for InterruptController {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0xFF0F => 0xE0 | self.flag,
+            0xFFFF => self.enable,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF0F => self.flag = value & 0x1F,
+            0xFFFF => self.enable = value,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Cpu, Registers};
+
+    struct Memory(Vec<u8>, InterruptController);
+
+    impl Bus for Memory {
+        fn read(&mut self, address: u16) -> u8 {
+            match address {
+                0xFF0F | 0xFFFF => self.1.read(address),
+                _ => self.0[address as usize],
+            }
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            match address {
+                0xFF0F | 0xFFFF => self.1.write(address, value),
+                _ => self.0[address as usize] = value,
+            }
+        }
+    }
+
+    /// A CPU about to run `code` at 0x100, with the timer interrupt enabled
+    /// and requested.
+    fn timer_pending(code: &[u8]) -> (Cpu, Memory) {
+        let mut memory = Memory(vec![0; 0x10000], InterruptController::new());
+        memory.0[0x100..0x100 + code.len()].copy_from_slice(code);
+        memory.1.enable = INTERRUPT_TIMER;
+        memory.1.request(INTERRUPT_TIMER);
+        (Cpu::new(Registers::dmg_post_boot()), memory)
+    }
+
+    /// Handle an interrupt or execute an instruction, as `Machine::step`
+    /// does.
+    fn step(cpu: &mut Cpu, memory: &mut Memory) {
+        if cpu.handle_interrupts(memory).is_none() {
+            cpu.step(memory);
+        }
+    }
+
+    fn return_address(cpu: &Cpu, memory: &Memory) -> u16 {
+        let sp = cpu.registers.sp as usize;
+        u16::from_le_bytes([memory.0[sp], memory.0[sp + 1]])
+    }
+
+    #[test]
+    fn priority() {
+        assert_eq!(Interrupt::highest_priority(0), None);
+        assert_eq!(Interrupt::highest_priority(0xE0), None);
+        assert_eq!(Interrupt::highest_priority(0x1F), Some(Interrupt::VBlank));
+        assert_eq!(Interrupt::highest_priority(0x1C), Some(Interrupt::Timer));
+        assert_eq!(Interrupt::highest_priority(0x10), Some(Interrupt::Joypad));
+        assert_eq!(Interrupt::Serial.bit(), INTERRUPT_SERIAL);
+        assert_eq!(Interrupt::Stat.vector(), 0x48);
+        assert_eq!(Interrupt::Joypad.vector(), 0x60);
+
+        // only the dispatched interrupt is acknowledged
+        let (mut cpu, mut memory) = timer_pending(&[]);
+        cpu.ime = true;
+        memory.1.enable = 0x1F;
+        memory.1.request(INTERRUPT_SERIAL | INTERRUPT_STAT);
+        assert_eq!(cpu.handle_interrupts(&mut memory), Some(20));
+        assert_eq!(cpu.registers.pc, 0x48);
+        assert_eq!(memory.1.flag, INTERRUPT_TIMER | INTERRUPT_SERIAL);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn ei_delay() {
+        // ei, nop, nop
+        let (mut cpu, mut memory) = timer_pending(&[0xFB, 0x00, 0x00]);
+        step(&mut cpu, &mut memory);
+        assert!(!cpu.ime);
+        // the nop after ei runs before the interrupt is dispatched
+        step(&mut cpu, &mut memory);
+        assert_eq!(cpu.registers.pc, 0x102);
+        step(&mut cpu, &mut memory);
+        assert_eq!(cpu.registers.pc, 0x50);
+        assert_eq!(return_address(&cpu, &memory), 0x102);
+    }
+
+    #[test]
+    fn halt_bug() {
+        // halt, inc a, nop: inc a is executed twice
+        let (mut cpu, mut memory) = timer_pending(&[0x76, 0x3C, 0x00]);
+        let a = cpu.registers.a;
+        step(&mut cpu, &mut memory);
+        assert!(!cpu.halted);
+        step(&mut cpu, &mut memory);
+        assert_eq!(cpu.registers.pc, 0x101);
+        step(&mut cpu, &mut memory);
+        assert_eq!(cpu.registers.pc, 0x102);
+        assert_eq!(cpu.registers.a, a.wrapping_add(2));
+    }
+
+    #[test]
+    fn ei_halt_returns_to_halt() {
+        // ei, halt: the interrupt returns to the halt
+        let (mut cpu, mut memory) = timer_pending(&[0xFB, 0x76, 0x00]);
+        step(&mut cpu, &mut memory);
+        step(&mut cpu, &mut memory);
+        assert!(!cpu.halted);
+        step(&mut cpu, &mut memory);
+        assert_eq!(cpu.registers.pc, 0x50);
+        assert_eq!(return_address(&cpu, &memory), 0x101);
+        assert!(!cpu.halt_bug);
+    }
+
+    #[test]
+    fn halt_wakes_without_ime() {
+        let (mut cpu, mut memory) = timer_pending(&[0x76, 0x00]);
+        memory.1.flag = 0;
+        step(&mut cpu, &mut memory);
+        assert!(cpu.halted);
+        step(&mut cpu, &mut memory);
+        assert_eq!(cpu.registers.pc, 0x101);
+
+        // an interrupt ends the halt but isn't dispatched with IME off
+        memory.1.request(INTERRUPT_TIMER);
+        assert_eq!(cpu.handle_interrupts(&mut memory), None);
+        assert!(!cpu.halted);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.registers.pc, 0x102);
+    }
+}
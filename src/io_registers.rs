@@ -0,0 +1,148 @@
+//! The memory mapped hardware registers living in the 0xFF00-0xFFFF range.
+//!
+//! Names follow the conventions of [hardware.inc] so that symbolized
+//! disassembly can be fed back into RGBDS.
+//!
+//! [hardware.inc]: https://github.com/gbdev/hardware.inc
+
+/// A memory mapped I/O register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardwareRegister {
+    /// The full address of the register, between 0xFF00 and 0xFFFF.
+    pub address: u16,
+    /// The name of the register as used by hardware.inc, e.g. `rLCDC`.
+    pub name: &'static str,
+    /// A short human readable description of the register.
+    pub description: &'static str,
+    /// Whether the register only exists on the Gameboy Color.
+    pub cgb_only: bool,
+}
+
+impl HardwareRegister {
+    const fn new(address: u16, name: &'static str, description: &'static str) -> Self {
+        Self {
+            address,
+            name,
+            description,
+            cgb_only: false,
+        }
+    }
+
+    const fn cgb(address: u16, name: &'static str, description: &'static str) -> Self {
+        Self {
+            address,
+            name,
+            description,
+            cgb_only: true,
+        }
+    }
+}
+
+/// All known hardware registers, sorted by address.
+pub const HARDWARE_REGISTERS: &[HardwareRegister] = &[
+    HardwareRegister::new(0xFF00, "rP1", "Joypad"),
+    HardwareRegister::new(0xFF01, "rSB", "Serial transfer data"),
+    HardwareRegister::new(0xFF02, "rSC", "Serial transfer control"),
+    HardwareRegister::new(0xFF04, "rDIV", "Divider register"),
+    HardwareRegister::new(0xFF05, "rTIMA", "Timer counter"),
+    HardwareRegister::new(0xFF06, "rTMA", "Timer modulo"),
+    HardwareRegister::new(0xFF07, "rTAC", "Timer control"),
+    HardwareRegister::new(0xFF0F, "rIF", "Interrupt flag"),
+    HardwareRegister::new(0xFF10, "rNR10", "Channel 1 sweep"),
+    HardwareRegister::new(0xFF11, "rNR11", "Channel 1 length timer and duty cycle"),
+    HardwareRegister::new(0xFF12, "rNR12", "Channel 1 volume and envelope"),
+    HardwareRegister::new(0xFF13, "rNR13", "Channel 1 period low"),
+    HardwareRegister::new(0xFF14, "rNR14", "Channel 1 period high and control"),
+    HardwareRegister::new(0xFF16, "rNR21", "Channel 2 length timer and duty cycle"),
+    HardwareRegister::new(0xFF17, "rNR22", "Channel 2 volume and envelope"),
+    HardwareRegister::new(0xFF18, "rNR23", "Channel 2 period low"),
+    HardwareRegister::new(0xFF19, "rNR24", "Channel 2 period high and control"),
+    HardwareRegister::new(0xFF1A, "rNR30", "Channel 3 DAC enable"),
+    HardwareRegister::new(0xFF1B, "rNR31", "Channel 3 length timer"),
+    HardwareRegister::new(0xFF1C, "rNR32", "Channel 3 output level"),
+    HardwareRegister::new(0xFF1D, "rNR33", "Channel 3 period low"),
+    HardwareRegister::new(0xFF1E, "rNR34", "Channel 3 period high and control"),
+    HardwareRegister::new(0xFF20, "rNR41", "Channel 4 length timer"),
+    HardwareRegister::new(0xFF21, "rNR42", "Channel 4 volume and envelope"),
+    HardwareRegister::new(0xFF22, "rNR43", "Channel 4 frequency and randomness"),
+    HardwareRegister::new(0xFF23, "rNR44", "Channel 4 control"),
+    HardwareRegister::new(0xFF24, "rNR50", "Master volume and VIN panning"),
+    HardwareRegister::new(0xFF25, "rNR51", "Sound panning"),
+    HardwareRegister::new(0xFF26, "rNR52", "Sound on/off"),
+    HardwareRegister::new(0xFF30, "rWAVE_0", "Wave pattern RAM byte 0"),
+    HardwareRegister::new(0xFF31, "rWAVE_1", "Wave pattern RAM byte 1"),
+    HardwareRegister::new(0xFF32, "rWAVE_2", "Wave pattern RAM byte 2"),
+    HardwareRegister::new(0xFF33, "rWAVE_3", "Wave pattern RAM byte 3"),
+    HardwareRegister::new(0xFF34, "rWAVE_4", "Wave pattern RAM byte 4"),
+    HardwareRegister::new(0xFF35, "rWAVE_5", "Wave pattern RAM byte 5"),
+    HardwareRegister::new(0xFF36, "rWAVE_6", "Wave pattern RAM byte 6"),
+    HardwareRegister::new(0xFF37, "rWAVE_7", "Wave pattern RAM byte 7"),
+    HardwareRegister::new(0xFF38, "rWAVE_8", "Wave pattern RAM byte 8"),
+    HardwareRegister::new(0xFF39, "rWAVE_9", "Wave pattern RAM byte 9"),
+    HardwareRegister::new(0xFF3A, "rWAVE_A", "Wave pattern RAM byte 10"),
+    HardwareRegister::new(0xFF3B, "rWAVE_B", "Wave pattern RAM byte 11"),
+    HardwareRegister::new(0xFF3C, "rWAVE_C", "Wave pattern RAM byte 12"),
+    HardwareRegister::new(0xFF3D, "rWAVE_D", "Wave pattern RAM byte 13"),
+    HardwareRegister::new(0xFF3E, "rWAVE_E", "Wave pattern RAM byte 14"),
+    HardwareRegister::new(0xFF3F, "rWAVE_F", "Wave pattern RAM byte 15"),
+    HardwareRegister::new(0xFF40, "rLCDC", "LCD control"),
+    HardwareRegister::new(0xFF41, "rSTAT", "LCD status"),
+    HardwareRegister::new(0xFF42, "rSCY", "Background viewport Y position"),
+    HardwareRegister::new(0xFF43, "rSCX", "Background viewport X position"),
+    HardwareRegister::new(0xFF44, "rLY", "LCD Y coordinate"),
+    HardwareRegister::new(0xFF45, "rLYC", "LY compare"),
+    HardwareRegister::new(0xFF46, "rDMA", "OAM DMA source address and start"),
+    HardwareRegister::new(0xFF47, "rBGP", "Background palette data"),
+    HardwareRegister::new(0xFF48, "rOBP0", "Object palette 0 data"),
+    HardwareRegister::new(0xFF49, "rOBP1", "Object palette 1 data"),
+    HardwareRegister::new(0xFF4A, "rWY", "Window Y position"),
+    HardwareRegister::new(0xFF4B, "rWX", "Window X position plus 7"),
+    HardwareRegister::cgb(0xFF4D, "rKEY1", "Prepare speed switch"),
+    HardwareRegister::cgb(0xFF4F, "rVBK", "VRAM bank"),
+    HardwareRegister::new(0xFF50, "rBOOT", "Boot ROM disable"),
+    HardwareRegister::cgb(0xFF51, "rHDMA1", "VRAM DMA source high"),
+    HardwareRegister::cgb(0xFF52, "rHDMA2", "VRAM DMA source low"),
+    HardwareRegister::cgb(0xFF53, "rHDMA3", "VRAM DMA destination high"),
+    HardwareRegister::cgb(0xFF54, "rHDMA4", "VRAM DMA destination low"),
+    HardwareRegister::cgb(0xFF55, "rHDMA5", "VRAM DMA length, mode and start"),
+    HardwareRegister::cgb(0xFF56, "rRP", "Infrared communications port"),
+    HardwareRegister::cgb(0xFF68, "rBCPS", "Background color palette specification"),
+    HardwareRegister::cgb(0xFF69, "rBCPD", "Background color palette data"),
+    HardwareRegister::cgb(0xFF6A, "rOCPS", "Object color palette specification"),
+    HardwareRegister::cgb(0xFF6B, "rOCPD", "Object color palette data"),
+    HardwareRegister::cgb(0xFF6C, "rOPRI", "Object priority mode"),
+    HardwareRegister::cgb(0xFF70, "rSVBK", "WRAM bank"),
+    HardwareRegister::cgb(0xFF76, "rPCM12", "Audio digital outputs 1 and 2"),
+    HardwareRegister::cgb(0xFF77, "rPCM34", "Audio digital outputs 3 and 4"),
+    HardwareRegister::new(0xFFFF, "rIE", "Interrupt enable"),
+];
+
+/// Look up the hardware register at the given address.
+///
+/// Returns `None` for addresses that aren't mapped to a known register,
+/// including HRAM.
+pub fn lookup_hardware_register(address: u16) -> Option<&'static HardwareRegister> {
+    HARDWARE_REGISTERS
+        .binary_search_by_key(&address, |reg| reg.address)
+        .ok()
+        .map(|idx| &HARDWARE_REGISTERS[idx])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_by_address() {
+        for pair in HARDWARE_REGISTERS.windows(2) {
+            assert!(
+                pair[0].address < pair[1].address,
+                "{} at {:04X} must come before {} at {:04X}",
+                pair[1].name,
+                pair[1].address,
+                pair[0].name,
+                pair[0].address
+            );
+        }
+    }
+}
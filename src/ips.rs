@@ -0,0 +1,600 @@
+//! IPS patches, the most common format for ROM hacks and translations.
+//!
+//! An IPS patch is `PATCH` followed by records of a 24 bit big endian offset
+//! and a 16 bit size followed by that many bytes. A size of 0 marks an RLE
+//! record, a 16 bit count and a byte to repeat. The patch ends with `EOF`,
+//! optionally followed by a 24 bit size to truncate the ROM to when that's
+//! all that follows.
+//!
+//! After patching, a [`PatchReport`] shows what changed in the header and
+//! whether the checksums still hold.
+//!
+//! ```
+//! use gameboy_rom::ips::IpsPatch;
+//!
+//! let original = vec![0; 0x200];
+//! let mut modified = original.clone();
+//! modified[0x134..0x138].copy_from_slice(b"TEST");
+//! let patch = IpsPatch::create(&original, &modified).unwrap();
+//!
+//! let mut rom = original.clone();
+//! IpsPatch::parse(&patch.to_bytes()).unwrap().apply(&mut rom);
+//! assert_eq!(rom, modified);
+//! ```
+
+use crate::GameBoyRom;
+
+use std::fmt;
+
+const MAGIC: &[u8] = b"PATCH";
+const EOF: &[u8] = b"EOF";
+/// A record at this offset would be read as the end of the patch.
+const EOF_OFFSET: u32 = 0x454F46;
+/// The largest offset a record can have.
+const MAX_OFFSET: usize = 0xFF_FFFF;
+/// Records are split when they'd be longer than this.
+const MAX_RECORD_SIZE: usize = 0xFFFF;
+/// Runs of the same byte at least this long are written as RLE records.
+const MIN_RLE_LENGTH: usize = 9;
+/// Unchanged bytes between two changes shorter than this are included in a
+/// single record rather than starting a new one, which costs 5 bytes.
+const MAX_MERGED_GAP: usize = 5;
+
+/// Errors that may occur while reading or creating an IPS patch.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IpsError {
+    /// The patch doesn't start with `PATCH`.
+    MissingHeader,
+    /// The patch ends part way through the record starting at this offset
+    /// into the patch, or without `EOF`.
+    UnexpectedEnd(usize),
+    /// The ROM is too big for IPS, changes must be within the first 16MB.
+    TooLarge(usize),
+}
+
+/// A single change made by a patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpsRecord {
+    /// Write `data` at `offset`.
+    Data { offset: u32, data: Vec<u8> },
+    /// Write `value` `count` times starting at `offset`.
+    Rle { offset: u32, count: u16, value: u8 },
+}
+
+impl IpsRecord {
+    pub fn offset(&self) -> u32 {
+        match self {
+            IpsRecord::Data { offset, .. } | IpsRecord::Rle { offset, .. } => *offset,
+        }
+    }
+
+    /// The number of bytes written.
+    pub fn len(&self) -> usize {
+        match self {
+            IpsRecord::Data { data, .. } => data.len(),
+            IpsRecord::Rle { count, .. } => *count as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A parsed IPS patch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpsPatch {
+    pub records: Vec<IpsRecord>,
+    /// The size to truncate the ROM to after applying the records.
+    pub truncate_to: Option<u32>,
+}
+
+impl IpsPatch {
+    /// Parse an IPS patch.
+    pub fn parse(bytes: &[u8]) -> Result<Self, IpsError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(IpsError::MissingHeader);
+        }
+        let mut patch = Self::default();
+        let mut position = MAGIC.len();
+        loop {
+            let start = position;
+            let mut take = |len: usize| {
+                let taken = bytes
+                    .get(position..position + len)
+                    .ok_or(IpsError::UnexpectedEnd(start))?;
+                position += len;
+                Ok(taken)
+            };
+            let offset = take(3)?;
+            if offset == EOF {
+                break;
+            }
+            let offset = read_u24(offset);
+            let size = read_u16(take(2)?);
+            let record = if size == 0 {
+                let count = read_u16(take(2)?);
+                let value = take(1)?[0];
+                IpsRecord::Rle {
+                    offset,
+                    count,
+                    value,
+                }
+            } else {
+                IpsRecord::Data {
+                    offset,
+                    data: take(size as usize)?.to_vec(),
+                }
+            };
+            patch.records.push(record);
+        }
+        // the truncation extension is exactly 3 bytes, anything else after
+        // EOF, such as the name of the tool that made the patch, is ignored
+        if bytes.len() - position == 3 {
+            patch.truncate_to = Some(read_u24(&bytes[position..]));
+        }
+        Ok(patch)
+    }
+
+    /// Encode the patch.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        for record in &self.records {
+            out.extend_from_slice(&record.offset().to_be_bytes()[1..]);
+            match record {
+                IpsRecord::Data { data, .. } => {
+                    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                    out.extend_from_slice(data);
+                }
+                IpsRecord::Rle { count, value, .. } => {
+                    out.extend_from_slice(&[0, 0]);
+                    out.extend_from_slice(&count.to_be_bytes());
+                    out.push(*value);
+                }
+            }
+        }
+        out.extend_from_slice(EOF);
+        if let Some(size) = self.truncate_to {
+            out.extend_from_slice(&size.to_be_bytes()[1..]);
+        }
+        out
+    }
+
+    /// Apply the patch to a ROM, growing it when records are past the end.
+    pub fn apply(&self, rom: &mut Vec<u8>) {
+        for record in &self.records {
+            let offset = record.offset() as usize;
+            let end = offset + record.len();
+            if rom.len() < end {
+                rom.resize(end, 0);
+            }
+            match record {
+                IpsRecord::Data { data, .. } => rom[offset..end].copy_from_slice(data),
+                IpsRecord::Rle { value, .. } => {
+                    rom[offset..end].iter_mut().for_each(|byte| *byte = *value)
+                }
+            }
+        }
+        if let Some(size) = self.truncate_to {
+            rom.truncate(size as usize);
+        }
+    }
+
+    /// Create a patch turning `original` into `modified`.
+    pub fn create(original: &[u8], modified: &[u8]) -> Result<Self, IpsError> {
+        // bytes past the end of the original always have to be written
+        let changed = |idx: usize| original.get(idx) != Some(&modified[idx]);
+        let mut patch = Self::default();
+        if modified.len() < original.len() {
+            if modified.len() > MAX_OFFSET {
+                return Err(IpsError::TooLarge(modified.len()));
+            }
+            patch.truncate_to = Some(modified.len() as u32);
+        }
+
+        let mut idx = 0;
+        while idx < modified.len() {
+            if !changed(idx) {
+                idx += 1;
+                continue;
+            }
+            let mut start = idx;
+            let mut end = idx + 1;
+            // extend the change over short gaps of unchanged bytes
+            while end < modified.len() && end - start < MAX_RECORD_SIZE - 1 {
+                match (end..modified.len().min(end + MAX_MERGED_GAP)).find(|&i| changed(i)) {
+                    Some(next) if next - start < MAX_RECORD_SIZE - 1 => end = next + 1,
+                    _ => break,
+                }
+            }
+            if start as u32 == EOF_OFFSET {
+                start -= 1;
+            }
+            if start > MAX_OFFSET {
+                return Err(IpsError::TooLarge(modified.len()));
+            }
+            patch.push_change(start, &modified[start..end]);
+            idx = end;
+        }
+        Ok(patch)
+    }
+
+    /// Add records writing `data` at `offset`, using RLE for long runs of the
+    /// same byte.
+    fn push_change(&mut self, offset: usize, data: &[u8]) {
+        let mut pending = 0;
+        let mut idx = 0;
+        while idx < data.len() {
+            let run = data[idx..].iter().take_while(|b| **b == data[idx]).count();
+            // an RLE record can't start at EOF_OFFSET either
+            if run >= MIN_RLE_LENGTH && (offset + idx) as u32 != EOF_OFFSET {
+                self.push_data(offset, data, pending, idx);
+                self.records.push(IpsRecord::Rle {
+                    offset: (offset + idx) as u32,
+                    count: run as u16,
+                    value: data[idx],
+                });
+                pending = idx + run;
+            }
+            idx += run;
+        }
+        self.push_data(offset, data, pending, data.len());
+    }
+
+    /// Add a record writing `data[start..end]`, where `data` is written at
+    /// `offset`.
+    fn push_data(&mut self, offset: usize, data: &[u8], mut start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        // rewrite the byte before instead, it's the end of an RLE record
+        if (offset + start) as u32 == EOF_OFFSET && start > 0 {
+            start -= 1;
+        }
+        self.records.push(IpsRecord::Data {
+            offset: (offset + start) as u32,
+            data: data[start..end].to_vec(),
+        });
+    }
+}
+
+/// Apply an IPS patch to a copy of `rom`, returning the patched ROM and what
+/// changed.
+pub fn patch_rom(rom: &[u8], patch: &[u8]) -> Result<(Vec<u8>, PatchReport), IpsError> {
+    let mut patched = rom.to_vec();
+    IpsPatch::parse(patch)?.apply(&mut patched);
+    let report = PatchReport::new(rom, &patched);
+    Ok((patched, report))
+}
+
+fn read_u24(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/// A header field that a patch changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderChange {
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+/// Whether a ROM's stored checksums match its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumStatus {
+    pub header_checksum_valid: bool,
+    pub global_checksum_valid: bool,
+}
+
+impl ChecksumStatus {
+    /// Check the checksums, `None` if the header can't be parsed.
+    pub fn new(rom: &[u8]) -> Option<Self> {
+        let rom = GameBoyRom::new(rom);
+        let header = rom.parse_header().ok()?;
+        Some(Self {
+            header_checksum_valid: rom.compute_header_checksum() == Some(header.complement),
+            global_checksum_valid: rom.compute_global_checksum() == header.checksum,
+        })
+    }
+}
+
+/// How a patch changed a ROM, found by parsing the header before and after.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchReport {
+    pub size_before: usize,
+    pub size_after: usize,
+    /// Empty if either header couldn't be parsed.
+    pub header_changes: Vec<HeaderChange>,
+    /// `None` if the original header couldn't be parsed.
+    pub checksums_before: Option<ChecksumStatus>,
+    /// `None` if the patched header couldn't be parsed.
+    pub checksums_after: Option<ChecksumStatus>,
+}
+
+impl PatchReport {
+    /// Compare a ROM with its patched version.
+    pub fn new(original: &[u8], patched: &[u8]) -> Self {
+        let before = GameBoyRom::new(original).parse_header().ok();
+        let after = GameBoyRom::new(patched).parse_header().ok();
+        let header_changes = match (before, after) {
+            (Some(before), Some(after)) => header_fields(&before)
+                .into_iter()
+                .zip(header_fields(&after))
+                .filter(|((_, before), (_, after))| before != after)
+                .map(|((field, before), (_, after))| HeaderChange {
+                    field,
+                    before,
+                    after,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        Self {
+            size_before: original.len(),
+            size_after: patched.len(),
+            header_changes,
+            checksums_before: ChecksumStatus::new(original),
+            checksums_after: ChecksumStatus::new(patched),
+        }
+    }
+
+    /// Whether the patched ROM's checksums are wrong when the original's
+    /// weren't.
+    pub fn broke_checksums(&self) -> bool {
+        match (self.checksums_before, self.checksums_after) {
+            (Some(before), Some(after)) => {
+                (before.header_checksum_valid && !after.header_checksum_valid)
+                    || (before.global_checksum_valid && !after.global_checksum_valid)
+            }
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for PatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.size_before != self.size_after {
+            writeln!(f, "size: {} -> {}", self.size_before, self.size_after)?;
+        }
+        for change in &self.header_changes {
+            writeln!(f, "{}: {} -> {}", change.field, change.before, change.after)?;
+        }
+        let describe = |status: Option<ChecksumStatus>| match status {
+            Some(status) => format!(
+                "header checksum {}, global checksum {}",
+                if status.header_checksum_valid {
+                    "ok"
+                } else {
+                    "bad"
+                },
+                if status.global_checksum_valid {
+                    "ok"
+                } else {
+                    "bad"
+                }
+            ),
+            None => "unparsable header".to_string(),
+        };
+        writeln!(f, "before: {}", describe(self.checksums_before))?;
+        writeln!(f, "after: {}", describe(self.checksums_after))
+    }
+}
+
+/// Every header field with its value formatted for display.
+fn header_fields(header: &crate::RomHeader) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "entry point",
+            format!("{:02X?}", header.begin_code_execution_point),
+        ),
+        ("logo", format!("{:02X?}", header.scrolling_graphic)),
+        (
+            "title",
+            format!("{:?}", header.game_title.trim_end_matches('\0')),
+        ),
+        ("gameboy color", format!("{:?}", header.gameboy_color)),
+        (
+            "new licensee code",
+            format!("{:?}", header.licensee_code_new),
+        ),
+        ("super gameboy", header.super_gameboy.to_string()),
+        ("rom type", format!("{:?}", header.rom_type)),
+        ("rom banks", header.rom_size.to_string()),
+        ("ram banks", header.ram_banks.to_string()),
+        ("ram bank size", header.ram_bank_size.to_string()),
+        ("japanese", header.japanese.to_string()),
+        ("licensee code", format!("${:02X}", header.licensee_code)),
+        ("mask rom version", header.mask_rom_version.to_string()),
+        ("header checksum", format!("${:02X}", header.complement)),
+        ("global checksum", format!("${:04X}", header.checksum)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 32KB ROM with valid checksums.
+    fn rom(title: &[u8], mask_rom_version: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14C] = mask_rom_version;
+        rom[0x14D] = GameBoyRom::new(&rom).compute_header_checksum().unwrap();
+        let checksum = GameBoyRom::new(&rom).compute_global_checksum();
+        rom[0x14E..0x150].copy_from_slice(&checksum.to_be_bytes());
+        rom
+    }
+
+    #[test]
+    fn rle_records() {
+        let patch = IpsPatch::parse(b"PATCH\x00\x00\x10\x00\x00\x00\x04\xABEOF").unwrap();
+        assert_eq!(
+            patch.records,
+            vec![IpsRecord::Rle {
+                offset: 0x10,
+                count: 4,
+                value: 0xAB
+            }]
+        );
+        // the ROM grows to fit
+        let mut rom = vec![0; 0x12];
+        patch.apply(&mut rom);
+        assert_eq!(rom.len(), 0x14);
+        assert_eq!(rom[0x0F..], [0, 0xAB, 0xAB, 0xAB, 0xAB]);
+
+        // long runs are written as RLE records
+        let original = vec![0; 0x100];
+        let mut modified = original.clone();
+        modified[0x20] = 1;
+        modified[0x21..0x41]
+            .iter_mut()
+            .for_each(|byte| *byte = 0x55);
+        let patch = IpsPatch::create(&original, &modified).unwrap();
+        assert_eq!(
+            patch.records,
+            vec![
+                IpsRecord::Data {
+                    offset: 0x20,
+                    data: vec![1]
+                },
+                IpsRecord::Rle {
+                    offset: 0x21,
+                    count: 0x20,
+                    value: 0x55
+                },
+            ]
+        );
+        let mut rom = original.clone();
+        IpsPatch::parse(&patch.to_bytes()).unwrap().apply(&mut rom);
+        assert_eq!(rom, modified);
+    }
+
+    #[test]
+    fn truncation() {
+        let patch = IpsPatch::parse(b"PATCHEOF\x00\x01\x00").unwrap();
+        assert_eq!(patch.truncate_to, Some(0x100));
+        let mut rom = vec![0; 0x200];
+        patch.apply(&mut rom);
+        assert_eq!(rom.len(), 0x100);
+
+        // anything but exactly 3 bytes after EOF isn't a size
+        let junk = IpsPatch::parse(b"PATCHEOF\0\0\x20 created by tool").unwrap();
+        assert_eq!(junk.truncate_to, None);
+        let mut rom = vec![0; 0x200];
+        junk.apply(&mut rom);
+        assert_eq!(rom.len(), 0x200);
+        let short = IpsPatch::parse(b"PATCHEOF\x00\x01").unwrap();
+        assert_eq!(short.truncate_to, None);
+
+        let original = vec![1; 0x200];
+        let patch = IpsPatch::create(&original, &original[..0x180]).unwrap();
+        assert_eq!(patch.to_bytes(), b"PATCHEOF\x00\x01\x80");
+    }
+
+    #[test]
+    fn change_at_eof_offset() {
+        let offset = EOF_OFFSET as usize;
+        let original = vec![0; offset + 0x10];
+        let mut modified = original.clone();
+        modified[offset] = 1;
+        modified[offset + 1] = 2;
+        let patch = IpsPatch::create(&original, &modified).unwrap();
+        assert!(patch
+            .records
+            .iter()
+            .all(|record| record.offset() != EOF_OFFSET));
+
+        let parsed = IpsPatch::parse(&patch.to_bytes()).unwrap();
+        assert_eq!(parsed, patch);
+        let mut rom = original.clone();
+        parsed.apply(&mut rom);
+        assert_eq!(rom, modified);
+
+        // and a run of the same byte starting there
+        modified[offset..offset + 0x10]
+            .iter_mut()
+            .for_each(|byte| *byte = 7);
+        let mut rom = original.clone();
+        let bytes = IpsPatch::create(&original, &modified).unwrap().to_bytes();
+        IpsPatch::parse(&bytes).unwrap().apply(&mut rom);
+        assert_eq!(rom, modified);
+    }
+
+    #[test]
+    fn unexpected_end() {
+        assert_eq!(IpsPatch::parse(b"PATCX"), Err(IpsError::MissingHeader));
+        assert_eq!(IpsPatch::parse(b"PATCH"), Err(IpsError::UnexpectedEnd(5)));
+        assert_eq!(
+            IpsPatch::parse(b"PATCH\x00\x00\x10\x00\x04\x01\x02"),
+            Err(IpsError::UnexpectedEnd(5))
+        );
+        // the record's data runs into EOF, leaving a truncated record after
+        assert_eq!(
+            IpsPatch::parse(b"PATCH\x00\x00\x10\x00\x04\x01\x02EOF"),
+            Err(IpsError::UnexpectedEnd(14))
+        );
+    }
+
+    #[test]
+    fn report_header_changes() {
+        let original = rom(b"OLD", 0);
+        let modified = rom(b"NEW", 1);
+        let patch = IpsPatch::create(&original, &modified).unwrap().to_bytes();
+        let (patched, report) = patch_rom(&original, &patch).unwrap();
+        assert_eq!(patched, modified);
+
+        let change = |field, before: &str, after: &str| HeaderChange {
+            field,
+            before: before.to_string(),
+            after: after.to_string(),
+        };
+        assert_eq!(
+            report.header_changes,
+            vec![
+                change("title", "\"OLD\"", "\"NEW\""),
+                change("mask rom version", "0", "1"),
+                change(
+                    "header checksum",
+                    &format!("${:02X}", original[0x14D]),
+                    &format!("${:02X}", modified[0x14D])
+                ),
+                change(
+                    "global checksum",
+                    &format!(
+                        "${:04X}",
+                        u16::from_be_bytes([original[0x14E], original[0x14F]])
+                    ),
+                    &format!(
+                        "${:04X}",
+                        u16::from_be_bytes([modified[0x14E], modified[0x14F]])
+                    )
+                ),
+            ]
+        );
+        assert_eq!(report.size_before, report.size_after);
+        assert!(!report.broke_checksums());
+
+        // a patch that leaves the checksums alone breaks them
+        let mut broken = original.clone();
+        broken[0x134..0x137].copy_from_slice(b"NEW");
+        let patch = IpsPatch::create(&original, &broken).unwrap().to_bytes();
+        let (_, report) = patch_rom(&original, &patch).unwrap();
+        assert_eq!(
+            report.header_changes,
+            vec![change("title", "\"OLD\"", "\"NEW\"")]
+        );
+        assert_eq!(
+            report.checksums_after,
+            Some(ChecksumStatus {
+                header_checksum_valid: false,
+                global_checksum_valid: false,
+            })
+        );
+        assert!(report.broke_checksums());
+    }
+}
@@ -0,0 +1,203 @@
+//! The joypad and the P1 register it's read through.
+//!
+//! P1 bits 4 and 5 select the d-pad and the buttons respectively when written
+//! as 0, the low 4 bits then read as 0 for each selected key that's held.
+//! When one of those bits goes from 1 to 0 the joypad interrupt is requested.
+//!
+//! With the `emulation` feature a [`Joypad`] implements P1 for the `machine`
+//! module.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "emulation")]
+use crate::cpu::Bus;
+#[cfg(feature = "emulation")]
+use crate::interrupts::INTERRUPT_JOYPAD;
+
+/// A set of held keys, the d-pad in the low 4 bits in the order of P1 and the
+/// buttons in the high 4 bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Buttons(pub u8);
+
+impl Buttons {
+    pub const RIGHT: Buttons = Buttons(0x01);
+    pub const LEFT: Buttons = Buttons(0x02);
+    pub const UP: Buttons = Buttons(0x04);
+    pub const DOWN: Buttons = Buttons(0x08);
+    pub const A: Buttons = Buttons(0x10);
+    pub const B: Buttons = Buttons(0x20);
+    pub const SELECT: Buttons = Buttons(0x40);
+    pub const START: Buttons = Buttons(0x80);
+
+    /// Every key with the character it's written as, in the order used by
+    /// BizHawk's input logs.
+    pub const MNEMONICS: [(Buttons, char); 8] = [
+        (Buttons::UP, 'U'),
+        (Buttons::DOWN, 'D'),
+        (Buttons::LEFT, 'L'),
+        (Buttons::RIGHT, 'R'),
+        (Buttons::START, 'S'),
+        (Buttons::SELECT, 's'),
+        (Buttons::B, 'B'),
+        (Buttons::A, 'A'),
+    ];
+
+    pub fn contains(self, other: Buttons) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for Buttons {
+    type Output = Buttons;
+
+    fn bitor(self, other: Buttons) -> Buttons {
+        Buttons(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Buttons {
+    fn bitor_assign(&mut self, other: Buttons) {
+        self.0 |= other.0;
+    }
+}
+
+/// Writes the keys as 8 characters, e.g. `U......A` for up and A, which
+/// `FromStr` reads back.
+impl fmt::Display for Buttons {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (button, mnemonic) in Buttons::MNEMONICS.iter() {
+            let c = if self.contains(*button) {
+                *mnemonic
+            } else {
+                '.'
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Buttons {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.chars().count() != Buttons::MNEMONICS.len() {
+            return Err(format!(
+                "Expected {} keys, found {:?}",
+                Buttons::MNEMONICS.len(),
+                s
+            ));
+        }
+        let mut buttons = Buttons::default();
+        for (c, (button, mnemonic)) in s.chars().zip(Buttons::MNEMONICS.iter()) {
+            match c {
+                '.' => (),
+                c if c == *mnemonic => buttons |= *button,
+                c => return Err(format!("Unexpected {:?} in {:?}", c, s)),
+            }
+        }
+        Ok(buttons)
+    }
+}
+
+/// The P1 register.
+///
+/// ```
+/// use gameboy_rom::cpu::Bus;
+/// use gameboy_rom::interrupts::INTERRUPT_JOYPAD;
+/// use gameboy_rom::joypad::{Buttons, Joypad};
+///
+/// let mut joypad = Joypad::new();
+/// // select the buttons
+/// joypad.write(0xFF00, 0x10);
+/// joypad.set_buttons(Buttons::START | Buttons::UP);
+/// assert_eq!(joypad.read(0xFF00), 0xD7);
+/// assert_eq!(joypad.take_interrupts(), INTERRUPT_JOYPAD);
+/// ```
+#[cfg(feature = "emulation")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Joypad {
+    /// Bits 4 and 5 of P1.
+    select: u8,
+    buttons: Buttons,
+    /// IF bits requested since the last `take_interrupts`.
+    interrupts: u8,
+}
+
+#[cfg(feature = "emulation")]
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "emulation")]
+impl Joypad {
+    /// Create a joypad with nothing held and nothing selected.
+    pub fn new() -> Self {
+        Self {
+            select: 0x30,
+            buttons: Buttons::default(),
+            interrupts: 0,
+        }
+    }
+
+    /// The keys currently held.
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// Change the keys held.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        let before = self.lines();
+        self.buttons = buttons;
+        self.update(before);
+    }
+
+    /// The interrupts requested since the last call as IF bits.
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupts)
+    }
+
+    /// The low 4 bits of P1, 0 for each selected key that's held.
+    fn lines(&self) -> u8 {
+        let mut held = 0;
+        if self.select & 0x10 == 0 {
+            held |= self.buttons.0 & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            held |= self.buttons.0 >> 4;
+        }
+        !held & 0x0F
+    }
+
+    fn update(&mut self, before: u8) {
+        if before & !self.lines() != 0 {
+            self.interrupts |= INTERRUPT_JOYPAD;
+        }
+    }
+}
+
+#[cfg(feature = "emulation")]
+impl Bus for Joypad {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0xFF00 => 0xC0 | self.select | self.lines(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address == 0xFF00 {
+            let before = self.lines();
+            self.select = value & 0x30;
+            self.update(before);
+        }
+    }
+}
@@ -5,6 +5,34 @@
 //!
 //! Basic validation is provided through the `validate` method on `RomHeader`.
 //!
+//! With the `emulation` feature the `cpu` module provides
+//! an interpreter that executes `Opcode`s and the `cartridge` module provides
+//! the memory bank controllers described by `RomType`. The `ppu` module
+//! renders frames in software, the `apu` module renders audio which can be
+//! saved with the `wav` module (music from GBS files is parsed and played by
+//! the `gbs` module), `timer` and `interrupts` provide the timer
+//! registers and interrupt controller, `serial` provides the link port and
+//! what can be plugged into it, `joypad` provides the P1 register, and the
+//! `machine` module puts everything together to run ROMs headlessly.
+//!
+//! Input for a run can be recorded and replayed with the `movie` module, and
+//! a machine can be snapshotted and restored with the `savestate` module.
+//!
+//! Code can be found and rendered as assembly with the `disasm` module, using
+//! labels loaded from `.sym` files via the `symbols` module. With the
+//! `opcode-serde` feature `Opcode`s can be serialized, see the `opcodes`
+//! module.
+//!
+//! Battery saves sized from the header can be created and checked with the
+//! `save` module, and IPS, BPS and UPS patches can be applied and created with
+//! the `ips`, `bps` and `ups` modules.
+//!
+//! With the `hash` feature a ROM's CRC32, MD5, SHA-1 and SHA-256 are computed
+//! by `GameBoyRom::hashes`, and with the `dat` feature they can be looked up
+//! in No-Intro DAT files with the `dat` module. With the `archive` feature
+//! ROMs can be read from disk, including from zip and gzip archives, with the
+//! `loader` module.
+//!
 //! Header logic based on info from the [GB CPU Manual].
 //!
 //! Opcode parsing logic was created with this [opcode table] as a reference.
@@ -14,6 +42,11 @@
 //! [GB CPU Manual]: http://marc.rawer.de/Gameboy/Docs/GBCPUman.pdf
 //! [opcode table]: https://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
 //!
+//! Header parsing and opcode decoding (the `header` and `opcodes` modules,
+//! `GameBoyRom` and `OpcodeStreamer`) work without the standard library.
+//! Disabling the default `std` feature builds the crate as `no_std`, needing
+//! only `alloc`, for use in firmware or on WASM. Everything else needs `std`.
+//!
 //! Basic usage:
 //! ```
 //! # fn example() -> Result<(), String> {
@@ -24,14 +57,76 @@
 //! # }
 //! ```
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "emulation")]
+pub mod apu;
+#[cfg(feature = "std")]
+pub mod bps;
+#[cfg(feature = "emulation")]
+pub mod cartridge;
+#[cfg(feature = "emulation")]
+pub mod cpu;
+#[cfg(feature = "dat")]
+pub mod dat;
+#[cfg(feature = "std")]
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod gbs;
+#[cfg(feature = "hash")]
+pub mod hash;
 pub mod header;
+#[cfg(feature = "emulation")]
+pub mod interrupts;
+#[cfg(feature = "std")]
+pub mod io_registers;
+#[cfg(feature = "std")]
+pub mod ips;
+#[cfg(feature = "std")]
+pub mod joypad;
+#[cfg(feature = "archive")]
+pub mod loader;
+#[cfg(feature = "emulation")]
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod movie;
+#[cfg(feature = "opcode-serde")]
+mod opcode_serde;
 pub mod opcodes;
 mod parser;
+#[cfg(feature = "emulation")]
+pub mod ppu;
+#[cfg(feature = "emulation")]
+pub mod rtc;
+#[cfg(feature = "std")]
+pub mod save;
+#[cfg(feature = "emulation")]
+pub mod savestate;
+#[cfg(feature = "emulation")]
+pub mod serial;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(feature = "emulation")]
+pub mod timer;
+#[cfg(feature = "std")]
+pub mod ups;
 pub mod util;
+#[cfg(feature = "std")]
+pub mod wav;
+
+use alloc::format;
+use alloc::string::String;
 
 pub use crate::header::*;
 pub use crate::opcodes::*;
 
+/// The largest ROM that will be decompressed or built by a patch, so a
+/// corrupt or malicious file can't exhaust memory. The biggest cartridges are
+/// 8MB.
+pub const MAX_ROM_SIZE: u64 = 0x100_0000;
+
 /// Top level type for dealing with GameBoy ROMs.
 #[derive(Debug)]
 pub struct GameBoyRom<'rom> {
@@ -47,14 +142,47 @@ impl<'rom> GameBoyRom<'rom> {
     }
 
     /// Parse the ROM header and return a high level type containing its data.
-    pub fn parse_header(&self) -> Result<RomHeader, String> {
+    pub fn parse_header(&self) -> Result<RomHeader<'rom>, String> {
         parser::parse_rom_header(self.rom_data)
             .map_err(|e| format!("Failed to parse ROM: {:?}", e))
             .map(|(_, rh)| rh)
     }
 
+    /// Compute the header checksum stored at 0x14D (`RomHeader::complement`)
+    /// from bytes 0x134-0x14C, `None` if the ROM is too short.
+    pub fn compute_header_checksum(&self) -> Option<u8> {
+        let bytes = self.rom_data.get(0x134..=0x14C)?;
+        Some(
+            bytes
+                .iter()
+                .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1)),
+        )
+    }
+
+    /// Compute the global checksum stored at 0x14E (`RomHeader::checksum`),
+    /// the sum of every byte in the ROM except the checksum itself.
+    pub fn compute_global_checksum(&self) -> u16 {
+        self.rom_data
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| *idx != 0x14E && *idx != 0x14F)
+            .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16))
+    }
+
+    /// Compute the hashes DAT files identify ROMs by, in a single pass.
+    #[cfg(feature = "hash")]
+    pub fn hashes(&self) -> hash::RomHashes {
+        hash::RomHashes::new(self.rom_data)
+    }
+
+    /// Get a `Disassembler` for finding the code in this ROM.
+    #[cfg(feature = "std")]
+    pub fn disassembler(&self) -> disasm::Disassembler<'rom> {
+        disasm::Disassembler::new(self.rom_data)
+    }
+
     /// Get an iterator over the instructions starting at the given address.
-    pub fn get_instructions_at(&self, address: usize) -> OpcodeStreamer {
+    pub fn get_instructions_at(&self, address: usize) -> OpcodeStreamer<'rom> {
         OpcodeStreamer::new(self.rom_data, address)
     }
 }
@@ -82,7 +210,7 @@ impl<'rom> Iterator for OpcodeStreamer<'rom> {
             Ok((i, op)) => {
                 // Compare the pointers to find out how many bytes we read
                 let offset =
-                    i.as_ptr() as usize - (&self.rom_data[self.current_index..]).as_ptr() as usize;
+                    i.as_ptr() as usize - self.rom_data[self.current_index..].as_ptr() as usize;
                 self.current_index += offset;
 
                 Some(op)
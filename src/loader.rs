@@ -0,0 +1,253 @@
+//! Loading ROMs from disk, looking inside zip and gzip archives.
+//!
+//! The archive type is found from the first bytes of the file rather than its
+//! extension. Every `.gb`, `.gbc` and `.sgb` file in a zip archive is loaded,
+//! a gzip file is assumed to hold a single ROM and anything else is loaded
+//! as is.
+//!
+//! ```no_run
+//! use gameboy_rom::{loader, GameBoyRom};
+//!
+//! # fn example() -> Result<(), loader::LoadError> {
+//! for rom in loader::load_path("roms/tetris.zip")? {
+//!     let header = GameBoyRom::new(&rom.bytes).parse_header();
+//!     println!("{}: {:?}", rom.name, header.map(|header| header.game_title));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::MAX_ROM_SIZE;
+
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+/// Extensions of the files loaded from zip archives, compared ignoring case.
+pub const ROM_EXTENSIONS: &[&str] = &["gb", "gbc", "sgb"];
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const EMPTY_ZIP_MAGIC: &[u8] = b"PK\x05\x06";
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+
+/// Errors that may occur while loading ROMs.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// The file couldn't be read or decompressed.
+    Io(String),
+    /// The zip archive couldn't be read.
+    Zip(String),
+    /// The zip archive has no ROMs in it.
+    NoRoms,
+    /// The decompressed ROM is bigger than `MAX_ROM_SIZE`.
+    TooLarge(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Zip(e) => write!(f, "invalid zip archive: {}", e),
+            LoadError::NoRoms => write!(f, "no ROMs found in the archive"),
+            LoadError::TooLarge(name) => {
+                write!(f, "{} is bigger than {} bytes", name, MAX_ROM_SIZE)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(error: std::io::Error) -> Self {
+        LoadError::Io(error.to_string())
+    }
+}
+
+impl From<zip::result::ZipError> for LoadError {
+    fn from(error: zip::result::ZipError) -> Self {
+        LoadError::Zip(error.to_string())
+    }
+}
+
+/// A ROM read from disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedRom {
+    /// The path the ROM was loaded from. For a ROM in an archive this is the
+    /// path of the archive followed by the path of the ROM inside it, which
+    /// for a gzip file is the archive's file name without `.gz`.
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Whether the path has one of the `ROM_EXTENSIONS`.
+pub fn is_rom_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom_extension| rom_extension.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// Load the ROMs in the file at `path`.
+pub fn load_path<P: AsRef<Path>>(path: P) -> Result<Vec<LoadedRom>, LoadError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    load_bytes(&path.to_string_lossy(), bytes)
+}
+
+/// Load the ROMs in a file that's already been read, `name` being where it
+/// was read from.
+pub fn load_bytes(name: &str, bytes: Vec<u8>) -> Result<Vec<LoadedRom>, LoadError> {
+    if bytes.starts_with(ZIP_MAGIC) || bytes.starts_with(EMPTY_ZIP_MAGIC) {
+        load_zip(name, &bytes)
+    } else if bytes.starts_with(GZIP_MAGIC) {
+        let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+        let inner_name = file_name
+            .strip_suffix(".gz")
+            .or_else(|| file_name.strip_suffix(".GZ"))
+            .unwrap_or(file_name);
+        let name = format!("{}/{}", name, inner_name);
+        let decoder = flate2::read::GzDecoder::new(bytes.as_slice());
+        Ok(vec![LoadedRom {
+            bytes: read_limited(decoder, &name)?,
+            name,
+        }])
+    } else {
+        Ok(vec![LoadedRom {
+            name: name.to_string(),
+            bytes,
+        }])
+    }
+}
+
+fn load_zip(name: &str, bytes: &[u8]) -> Result<Vec<LoadedRom>, LoadError> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+    let mut roms = vec![];
+    for idx in 0..archive.len() {
+        let file = archive.by_index(idx)?;
+        if !file.is_file() || !is_rom_path(Path::new(file.name())) {
+            continue;
+        }
+        let name = format!("{}/{}", name, file.name());
+        roms.push(LoadedRom {
+            bytes: read_limited(file, &name)?,
+            name,
+        });
+    }
+    if roms.is_empty() {
+        return Err(LoadError::NoRoms);
+    }
+    Ok(roms)
+}
+
+fn read_limited<R: Read>(reader: R, name: &str) -> Result<Vec<u8>, LoadError> {
+    let mut bytes = vec![];
+    reader.take(MAX_ROM_SIZE + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_ROM_SIZE {
+        return Err(LoadError::TooLarge(name.to_string()));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        for (name, bytes) in files {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn zip_rom_extensions() {
+        let archive = zip(&[
+            ("README.txt", b"readme"),
+            ("t.gb", b"dmg"),
+            ("dir/T.GBC", b"cgb"),
+            ("t.sgb.bak", b"backup"),
+        ]);
+        assert_eq!(
+            load_bytes("roms/t.zip", archive).unwrap(),
+            vec![
+                LoadedRom {
+                    name: "roms/t.zip/t.gb".to_string(),
+                    bytes: b"dmg".to_vec(),
+                },
+                LoadedRom {
+                    name: "roms/t.zip/dir/T.GBC".to_string(),
+                    bytes: b"cgb".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn no_roms() {
+        let archive = zip(&[("README.txt", b"readme")]);
+        assert_eq!(load_bytes("t.zip", archive), Err(LoadError::NoRoms));
+        assert_eq!(load_bytes("t.zip", zip(&[])), Err(LoadError::NoRoms));
+    }
+
+    #[test]
+    fn gzip_name() {
+        assert_eq!(
+            load_bytes("roms/t.gb.gz", gzip(b"dmg")).unwrap(),
+            vec![LoadedRom {
+                name: "roms/t.gb.gz/t.gb".to_string(),
+                bytes: b"dmg".to_vec(),
+            }]
+        );
+        assert_eq!(
+            load_bytes("T.GBZ", gzip(b"dmg")).unwrap()[0].name,
+            "T.GBZ/T.GBZ"
+        );
+    }
+
+    #[test]
+    fn too_large() {
+        let rom = vec![0; MAX_ROM_SIZE as usize + 1];
+        assert_eq!(
+            load_bytes("t.gb.gz", gzip(&rom)),
+            Err(LoadError::TooLarge("t.gb.gz/t.gb".to_string()))
+        );
+        assert_eq!(
+            load_bytes("t.zip", zip(&[("t.gb", &rom)])),
+            Err(LoadError::TooLarge("t.zip/t.gb".to_string()))
+        );
+
+        let rom = vec![0; MAX_ROM_SIZE as usize];
+        assert_eq!(load_bytes("t.gb.gz", gzip(&rom)).unwrap()[0].bytes, rom);
+    }
+
+    #[test]
+    fn passthrough() {
+        // only the magic bytes matter, not the extension
+        let bytes = b"\x1Fnot a gzip file".to_vec();
+        assert_eq!(
+            load_bytes("t.gb.gz", bytes.clone()).unwrap(),
+            vec![LoadedRom {
+                name: "t.gb.gz".to_string(),
+                bytes,
+            }]
+        );
+        assert!(matches!(
+            load_bytes("t.gb", b"PK\x03\x04".to_vec()),
+            Err(LoadError::Zip(_))
+        ));
+    }
+}
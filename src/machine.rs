@@ -0,0 +1,654 @@
+//! A complete Gameboy: CPU, memory map, cartridge, timer, interrupts, PPU and
+//! APU.
+//!
+//! The [`Machine`] runs headlessly, which makes it suitable for running test
+//! ROMs and checking their results through the serial port or registers.
+//!
+//! ```no_run
+//! # fn example(rom_bytes: &[u8]) -> Result<(), gameboy_rom::machine::MachineError> {
+//! use gameboy_rom::machine::Machine;
+//! use gameboy_rom::GameBoyRom;
+//!
+//! let mut machine = Machine::new(&GameBoyRom::new(rom_bytes))?;
+//! machine.run_frames(60);
+//! println!("{}", String::from_utf8_lossy(machine.bus.serial_output()));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::apu::Apu;
+use crate::cartridge::{self, Cartridge, CartridgeError, RomOnly};
+use crate::cpu::{Bus, Cpu, Registers};
+use crate::interrupts::{InterruptController, INTERRUPT_VBLANK};
+use crate::joypad::{Buttons, Joypad};
+use crate::movie::Movie;
+use crate::ppu::{Mode, Ppu, FRAME_CYCLES};
+use crate::rtc::{SystemTimeSource, TimeSource};
+use crate::serial::SerialController;
+use crate::timer::Timer;
+use crate::GameBoyRom;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+const WRAM_BANK_SIZE: usize = 0x1000;
+const HRAM_SIZE: usize = 0x7F;
+
+/// Errors that may occur while creating a `Machine`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MachineError {
+    /// The ROM header couldn't be parsed.
+    Header(String),
+    Cartridge(CartridgeError),
+}
+
+impl From<CartridgeError> for MachineError {
+    fn from(error: CartridgeError) -> Self {
+        MachineError::Cartridge(error)
+    }
+}
+
+/// Why `Machine::run_until` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested number of frames have been run.
+    FrameLimit,
+    /// PC reached one of the machine's breakpoints.
+    Breakpoint(u16),
+    /// The `ld b, b` at this address was executed.
+    SoftwareBreakpoint(u16),
+    /// The condition passed to `run_until` was met.
+    Condition,
+    /// The CPU hit an illegal instruction and hung.
+    Locked,
+}
+
+/// Everything the CPU can see on its memory bus.
+#[derive(Serialize, Deserialize)]
+pub struct SystemBus {
+    /// Save states store the cartridge's state separately.
+    #[serde(skip, default = "no_cartridge")]
+    pub cartridge: Box<dyn Cartridge>,
+    pub ppu: Ppu,
+    /// Doesn't render audio until given a sample rate.
+    pub apu: Apu,
+    cgb: bool,
+    /// 2 banks on DMG, 8 banks on CGB.
+    wram: Vec<u8>,
+    svbk: u8,
+    #[serde(with = "crate::savestate::byte_array")]
+    hram: [u8; HRAM_SIZE],
+    pub interrupts: InterruptController,
+    pub timer: Timer,
+    pub serial: SerialController,
+    pub joypad: Joypad,
+    key1: u8,
+    double_speed: bool,
+    hdma_source: u16,
+    hdma_destination: u16,
+    /// Blocks of 16 bytes left to copy in an HBlank DMA.
+    hdma_remaining: Option<u8>,
+    /// Registers without any behavior, kept so that reads return what was
+    /// written.
+    #[serde(with = "crate::savestate::byte_array")]
+    io: [u8; 0x80],
+}
+
+/// A placeholder until the real cartridge is put back.
+fn no_cartridge() -> Box<dyn Cartridge> {
+    Box::new(RomOnly::new(vec![], 0))
+}
+
+impl SystemBus {
+    fn new(cartridge: Box<dyn Cartridge>, cgb: bool) -> Self {
+        let wram_banks = if cgb { 8 } else { 2 };
+        Self {
+            cartridge,
+            ppu: Ppu::new(cgb),
+            apu: Apu::new(0),
+            cgb,
+            wram: vec![0; wram_banks * WRAM_BANK_SIZE],
+            svbk: 0,
+            hram: [0; HRAM_SIZE],
+            interrupts: InterruptController {
+                enable: 0,
+                flag: INTERRUPT_VBLANK,
+            },
+            timer: Timer::new(0xABCC),
+            serial: SerialController::new(cgb),
+            joypad: Joypad::new(),
+            key1: 0,
+            double_speed: false,
+            hdma_source: 0,
+            hdma_destination: 0,
+            hdma_remaining: None,
+            io: [0xFF; 0x80],
+        }
+    }
+
+    /// Every byte sent out of the serial port so far.
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    /// Whether a CGB is in double speed mode.
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    fn wram_offset(&self, address: u16) -> usize {
+        let offset = address as usize & 0x0FFF;
+        if address & 0x1000 == 0 {
+            offset
+        } else {
+            (self.svbk as usize).max(1) * WRAM_BANK_SIZE + offset
+        }
+    }
+
+    /// Advance everything but the CPU by `cycles` CPU T-cycles.
+    fn tick(&mut self, cycles: u32) {
+        let timer_interrupts = self.timer.step(cycles);
+        self.interrupts.request(timer_interrupts);
+        let serial_interrupts = self.serial.step(cycles);
+        self.interrupts.request(serial_interrupts);
+
+        let single_speed_cycles = if self.double_speed {
+            cycles / 2
+        } else {
+            cycles
+        };
+        self.apu.step(single_speed_cycles);
+        let mode = self.ppu.mode();
+        let ppu_interrupts = self.ppu.step(single_speed_cycles);
+        self.interrupts.request(ppu_interrupts);
+        if mode != Mode::HBlank && self.ppu.mode() == Mode::HBlank {
+            self.hblank_dma();
+        }
+    }
+
+    fn oam_dma(&mut self, page: u8) {
+        let source = (page as u16) << 8;
+        for offset in 0..0xA0 {
+            let value = self.read(source + offset);
+            self.ppu.write(0xFE00 + offset, value);
+        }
+    }
+
+    fn copy_hdma_block(&mut self) {
+        for _ in 0..0x10 {
+            let value = self.read(self.hdma_source);
+            self.ppu
+                .write(0x8000 | (self.hdma_destination & 0x1FFF), value);
+            self.hdma_source = self.hdma_source.wrapping_add(1);
+            self.hdma_destination = self.hdma_destination.wrapping_add(1);
+        }
+    }
+
+    fn hblank_dma(&mut self) {
+        if let Some(remaining) = self.hdma_remaining {
+            self.copy_hdma_block();
+            self.hdma_remaining = remaining.checked_sub(1);
+        }
+    }
+
+    fn write_hdma5(&mut self, value: u8) {
+        let blocks = value & 0x7F;
+        if value & 0x80 != 0 {
+            self.hdma_remaining = Some(blocks);
+        } else if self.hdma_remaining.is_some() {
+            // writing with bit 7 clear stops an HBlank DMA
+            self.hdma_remaining = None;
+        } else {
+            for _ in 0..=blocks {
+                self.copy_hdma_block();
+            }
+        }
+    }
+
+    /// Handle a `stop` executed with a speed switch prepared, returning
+    /// whether one happened.
+    fn switch_speed(&mut self) -> bool {
+        if !self.cgb || self.key1 & 0x01 == 0 {
+            return false;
+        }
+        self.key1 = 0;
+        self.double_speed = !self.double_speed;
+        true
+    }
+}
+
+impl Bus for SystemBus {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.read(address),
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => self.ppu.read(address),
+            0xC000..=0xFDFF => self.wram[self.wram_offset(address)],
+            0xFEA0..=0xFEFF => 0xFF,
+            0xFF00 => self.joypad.read(address),
+            0xFF01 | 0xFF02 => self.serial.read(address),
+            0xFF04..=0xFF07 => self.timer.read(address),
+            0xFF0F | 0xFFFF => self.interrupts.read(address),
+            0xFF10..=0xFF3F => self.apu.read(address),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B => self.ppu.read(address),
+            0xFF4D if self.cgb => 0x7E | self.key1 | if self.double_speed { 0x80 } else { 0 },
+            0xFF55 if self.cgb => self.hdma_remaining.unwrap_or(0xFF),
+            0xFF70 if self.cgb => 0xF8 | self.svbk,
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80],
+            _ => self.io[address as usize & 0x7F],
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.write(address, value),
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => self.ppu.write(address, value),
+            0xC000..=0xFDFF => {
+                let offset = self.wram_offset(address);
+                self.wram[offset] = value;
+            }
+            0xFEA0..=0xFEFF => (),
+            0xFF00 => {
+                self.joypad.write(address, value);
+                let joypad_interrupts = self.joypad.take_interrupts();
+                self.interrupts.request(joypad_interrupts);
+            }
+            0xFF01 | 0xFF02 => self.serial.write(address, value),
+            0xFF04..=0xFF07 => self.timer.write(address, value),
+            0xFF0F | 0xFFFF => self.interrupts.write(address, value),
+            0xFF10..=0xFF3F => self.apu.write(address, value),
+            0xFF46 => self.oam_dma(value),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B => {
+                self.ppu.write(address, value)
+            }
+            0xFF4D if self.cgb => self.key1 = value & 0x01,
+            0xFF51 if self.cgb => {
+                self.hdma_source = (self.hdma_source & 0x00FF) | ((value as u16) << 8)
+            }
+            0xFF52 if self.cgb => {
+                self.hdma_source = (self.hdma_source & 0xFF00) | (value & 0xF0) as u16
+            }
+            0xFF53 if self.cgb => {
+                self.hdma_destination =
+                    (self.hdma_destination & 0x00FF) | (((value & 0x1F) as u16) << 8)
+            }
+            0xFF54 if self.cgb => {
+                self.hdma_destination = (self.hdma_destination & 0xFF00) | (value & 0xF0) as u16
+            }
+            0xFF55 if self.cgb => self.write_hdma5(value),
+            0xFF70 if self.cgb => self.svbk = value & 0x07,
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80] = value,
+            _ => self.io[address as usize & 0x7F] = value,
+        }
+    }
+}
+
+/// A Gameboy with a cartridge inserted, starting from the state the boot ROM
+/// leaves behind.
+///
+/// The debugging settings aren't part of save states.
+#[derive(Serialize, Deserialize)]
+pub struct Machine {
+    pub cpu: Cpu,
+    pub bus: SystemBus,
+    /// Addresses to stop at before executing the instruction there.
+    #[serde(skip)]
+    pub breakpoints: BTreeSet<u16>,
+    /// Stop after executing `ld b, b`, which Mooneye's test ROMs use to
+    /// signal that they're done.
+    #[serde(skip)]
+    pub break_on_ld_b_b: bool,
+    /// The CRC32 of the ROM, save states can only be loaded into a machine
+    /// running the same ROM.
+    #[serde(skip)]
+    pub(crate) rom_crc32: u32,
+    /// Time elapsed in single speed T-cycles.
+    cycles: u64,
+}
+
+impl Machine {
+    /// Create a machine running the ROM, as a CGB if the ROM supports it. A
+    /// cartridge's real time clock follows the host's wall clock.
+    pub fn new(rom: &GameBoyRom) -> Result<Self, MachineError> {
+        Self::with_time_source(rom, Rc::new(SystemTimeSource))
+    }
+
+    /// Create a machine running the ROM with a cartridge's real time clock
+    /// driven by `time_source`, e.g. a `ManualTimeSource` so that runs can be
+    /// reproduced.
+    pub fn with_time_source(
+        rom: &GameBoyRom,
+        time_source: Rc<dyn TimeSource>,
+    ) -> Result<Self, MachineError> {
+        let header = rom.parse_header().map_err(MachineError::Header)?;
+        let cgb = header.gameboy_color.supports_color();
+        let cartridge = cartridge::from_rom_with_time_source(rom, &header, time_source)?;
+
+        let registers = if cgb {
+            Registers::cgb_post_boot()
+        } else {
+            Registers::dmg_post_boot()
+        };
+        let mut bus = SystemBus::new(cartridge, cgb);
+        bus.write(0xFF47, 0xFC);
+        bus.write(0xFF40, 0x91);
+
+        Ok(Self {
+            cpu: Cpu::new(registers),
+            bus,
+            breakpoints: BTreeSet::new(),
+            break_on_ld_b_b: false,
+            rom_crc32: crc32fast::hash(rom.rom_data),
+            cycles: 0,
+        })
+    }
+
+    /// Time elapsed in single speed T-cycles.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The CRC32 of the ROM the machine is running.
+    pub fn rom_crc32(&self) -> u32 {
+        self.rom_crc32
+    }
+
+    /// Handle a pending interrupt or execute one instruction, returning the
+    /// number of T-cycles taken.
+    pub fn step(&mut self) -> u32 {
+        if self.cpu.stopped && self.bus.switch_speed() {
+            self.cpu.stopped = false;
+        }
+        let cycles = match self.cpu.handle_interrupts(&mut self.bus) {
+            Some(cycles) => cycles,
+            None => self.cpu.step(&mut self.bus),
+        } as u32;
+
+        self.bus.tick(cycles);
+        // DIV is reset on entering STOP and doesn't count until it's left
+        if self.cpu.stopped {
+            self.bus.write(0xFF04, 0);
+        }
+        self.cycles += if self.bus.double_speed {
+            cycles as u64 / 2
+        } else {
+            cycles as u64
+        };
+        cycles
+    }
+
+    /// Change the keys held on the joypad. A selected input line going low
+    /// also wakes the CPU from STOP.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.bus.joypad.set_buttons(buttons);
+        let interrupts = self.bus.joypad.take_interrupts();
+        if interrupts != 0 {
+            self.cpu.stopped = false;
+        }
+        self.bus.interrupts.request(interrupts);
+    }
+
+    /// Play back a movie from the current state, holding the keys of each of
+    /// its frames for a frame's worth of time. Stops early for the same
+    /// reasons as `run_until`.
+    ///
+    /// A movie is recorded by pushing the keys held before running each
+    /// frame, replaying it from the same starting state gives the same
+    /// result. Both machines need a `ManualTimeSource` set to the same time
+    /// for cartridges with a real time clock.
+    ///
+    /// ```no_run
+    /// # fn example(rom_bytes: &[u8]) -> Result<(), gameboy_rom::machine::MachineError> {
+    /// use gameboy_rom::joypad::Buttons;
+    /// use gameboy_rom::machine::Machine;
+    /// use gameboy_rom::movie::Movie;
+    /// use gameboy_rom::rtc::ManualTimeSource;
+    /// use gameboy_rom::GameBoyRom;
+    /// use std::rc::Rc;
+    ///
+    /// let rom = GameBoyRom::new(rom_bytes);
+    /// let start = 1_600_000_000;
+    /// let mut machine = Machine::with_time_source(&rom, Rc::new(ManualTimeSource::new(start)))?;
+    /// let mut movie = Movie::new();
+    /// for frame in 0..600 {
+    ///     let buttons = if frame % 30 == 0 { Buttons::START } else { Buttons::default() };
+    ///     movie.push(buttons);
+    ///     machine.set_buttons(buttons);
+    ///     machine.run_frames(1);
+    /// }
+    ///
+    /// let mut replay = Machine::with_time_source(&rom, Rc::new(ManualTimeSource::new(start)))?;
+    /// replay.play_movie(&movie);
+    /// assert_eq!(replay.bus.ppu.framebuffer(), machine.bus.ppu.framebuffer());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn play_movie(&mut self, movie: &Movie) -> StopReason {
+        for &buttons in &movie.frames {
+            self.set_buttons(buttons);
+            match self.run_frames(1) {
+                StopReason::FrameLimit => (),
+                reason => return reason,
+            }
+        }
+        StopReason::FrameLimit
+    }
+
+    /// Run for the given number of frames' worth of time.
+    pub fn run_frames(&mut self, frames: u64) -> StopReason {
+        self.run_until(frames, |_| false)
+    }
+
+    /// Run until `condition` returns true after an instruction, a breakpoint is
+    /// hit or `max_frames` frames' worth of time have passed.
+    pub fn run_until<F>(&mut self, max_frames: u64, mut condition: F) -> StopReason
+    where
+        F: FnMut(&Machine) -> bool,
+    {
+        let end = self.cycles + max_frames * FRAME_CYCLES as u64;
+        while self.cycles < end {
+            let pc = self.cpu.registers.pc;
+            let ld_b_b = self.break_on_ld_b_b
+                && !self.cpu.halted
+                && !self.cpu.locked
+                && self.bus.read(pc) == 0x40;
+            self.step();
+
+            if self.cpu.locked {
+                return StopReason::Locked;
+            }
+            if ld_b_b && self.cpu.registers.pc == pc.wrapping_add(1) {
+                return StopReason::SoftwareBreakpoint(pc);
+            }
+            if self.breakpoints.contains(&self.cpu.registers.pc) {
+                return StopReason::Breakpoint(self.cpu.registers.pc);
+            }
+            if condition(self) {
+                return StopReason::Condition;
+            }
+        }
+        StopReason::FrameLimit
+    }
+}
+
+/// Run two machines side by side for the given number of frames, keeping
+/// them in step so that they can talk over a link cable made by
+/// `serial::LinkEnd::pair`.
+///
+/// ```no_run
+/// # fn example(first: &mut gameboy_rom::machine::Machine, second: &mut gameboy_rom::machine::Machine) {
+/// use gameboy_rom::machine;
+/// use gameboy_rom::serial::LinkEnd;
+///
+/// let (first_end, second_end) = LinkEnd::pair();
+/// first.bus.serial.connect(Box::new(first_end));
+/// second.bus.serial.connect(Box::new(second_end));
+/// machine::run_linked(first, second, 60);
+/// # }
+/// ```
+pub fn run_linked(first: &mut Machine, second: &mut Machine, frames: u64) {
+    let duration = frames * FRAME_CYCLES as u64;
+    let (first_start, second_start) = (first.cycles, second.cycles);
+    loop {
+        let first_elapsed = first.cycles - first_start;
+        let second_elapsed = second.cycles - second_start;
+        if first_elapsed >= duration && second_elapsed >= duration {
+            break;
+        }
+        // step whichever is behind
+        if first_elapsed <= second_elapsed {
+            first.step();
+        } else {
+            second.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtc::ManualTimeSource;
+    use crate::savestate;
+
+    /// A 32 KiB ROM without an MBC that jumps to `code` at 0x150.
+    fn rom(code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x150..0x150 + code.len()].copy_from_slice(code);
+        rom
+    }
+
+    fn machine(rom: &[u8]) -> Machine {
+        Machine::new(&GameBoyRom::new(rom)).unwrap()
+    }
+
+    #[test]
+    fn serial_passed() {
+        let rom = rom(&[
+            0x21, 0x67, 0x01, // ld hl, text
+            0x2A, // loop: ld a, (hl+)
+            0xA7, // and a
+            0x28, 0x0E, // jr z, done
+            0xE0, 0x01, // ldh (SB), a
+            0x3E, 0x81, // ld a, $81
+            0xE0, 0x02, // ldh (SC), a
+            0xF0, 0x02, // wait: ldh a, (SC)
+            0xCB, 0x7F, // bit 7, a
+            0x20, 0xFA, // jr nz, wait
+            0x18, 0xEE, // jr loop
+            0x18, 0xFE, // done: jr done
+            b'P', b'a', b's', b's', b'e', b'd', b'\n', 0,
+        ]);
+        let mut machine = machine(&rom);
+        let reason = machine.run_until(10, |machine| {
+            machine.bus.serial_output().ends_with(b"Passed\n")
+        });
+        assert_eq!(reason, StopReason::Condition);
+        assert_eq!(machine.bus.serial_output(), b"Passed\n");
+    }
+
+    #[test]
+    fn ld_b_b_fibonacci() {
+        let rom = rom(&[
+            0x06, 3, // ld b, 3
+            0x0E, 5, // ld c, 5
+            0x16, 8, // ld d, 8
+            0x1E, 13, // ld e, 13
+            0x26, 21, // ld h, 21
+            0x2E, 34,   // ld l, 34
+            0x40, // ld b, b
+            0x18, 0xFE, // jr @
+        ]);
+        let mut machine = machine(&rom);
+        machine.break_on_ld_b_b = true;
+        assert_eq!(
+            machine.run_frames(10),
+            StopReason::SoftwareBreakpoint(0x15C)
+        );
+        let r = &machine.cpu.registers;
+        assert_eq!([r.b, r.c, r.d, r.e, r.h, r.l], [3, 5, 8, 13, 21, 34]);
+
+        // it's an ordinary instruction otherwise
+        let mut machine = self::machine(&rom);
+        assert_eq!(machine.run_frames(1), StopReason::FrameLimit);
+    }
+
+    #[test]
+    fn locked() {
+        let mut machine = machine(&rom(&[0x00, 0xD3]));
+        assert_eq!(machine.run_frames(10), StopReason::Locked);
+        assert!(machine.cpu.locked);
+        // PC stays on the illegal opcode
+        assert_eq!(machine.cpu.registers.pc, 0x151);
+    }
+
+    #[test]
+    fn joypad_wakes_from_stop() {
+        let rom = rom(&[
+            0xAF, // xor a
+            0xE0, 0x00, // ldh (P1), a
+            0x10, 0x00, // stop
+            0x06, 0x2A, // ld b, $2A
+            0x18, 0xFE, // jr @
+        ]);
+        let mut machine = machine(&rom);
+        machine.run_frames(1);
+        assert!(machine.cpu.stopped);
+        assert_eq!(machine.cpu.registers.pc, 0x154);
+        // DIV is held at 0
+        assert_eq!(machine.bus.read(0xFF04), 0);
+
+        machine.set_buttons(Buttons::A);
+        assert!(!machine.cpu.stopped);
+        machine.run_frames(1);
+        assert_eq!(machine.cpu.registers.b, 0x2A);
+        assert_ne!(machine.bus.read(0xFF04), 0);
+    }
+
+    #[test]
+    fn replay_movie() {
+        let mut rom = rom(&[
+            0xAF, // xor a
+            0xE0, 0x00, // ldh (P1), a
+            0x3E, 0x0A, // ld a, $0A
+            0xEA, 0x00, 0x00, // ld ($0000), a
+            0x3E, 0x08, // ld a, $08
+            0xEA, 0x00, 0x40, // ld ($4000), a
+            0xAF, // loop: xor a
+            0xEA, 0x00, 0x60, // ld ($6000), a
+            0x3C, // inc a
+            0xEA, 0x00, 0x60, // ld ($6000), a
+            0xFA, 0x00, 0xA0, // ld a, ($A000)
+            0xEA, 0x01, 0xC0, // ld ($C001), a
+            0xF0, 0x00, // ldh a, (P1)
+            0x21, 0x00, 0xC0, // ld hl, $C000
+            0x86, // add a, (hl)
+            0x77, // ld (hl), a
+            0x18, 0xE9, // jr loop
+        ]);
+        // MBC3 with a clock
+        rom[0x147] = 0x0F;
+        let rom = GameBoyRom::new(&rom);
+        let start = 1_600_000_000;
+
+        let mut recorded =
+            Machine::with_time_source(&rom, Rc::new(ManualTimeSource::new(start))).unwrap();
+        let mut movie = Movie::new();
+        for frame in 0..30 {
+            let buttons = Buttons(frame * 7);
+            movie.push(buttons);
+            recorded.set_buttons(buttons);
+            recorded.run_frames(1);
+        }
+
+        let replay = || {
+            let time = Rc::new(ManualTimeSource::new(start));
+            let mut machine = Machine::with_time_source(&rom, time).unwrap();
+            assert_eq!(machine.play_movie(&movie), StopReason::FrameLimit);
+            machine
+        };
+        let (first, second) = (replay(), replay());
+        assert_eq!(savestate::save(&first), savestate::save(&second));
+        assert_eq!(savestate::save(&first), savestate::save(&recorded));
+    }
+}
@@ -0,0 +1,195 @@
+//! Input movies, the keys held on each frame, for replaying a run
+//! deterministically.
+//!
+//! Movies are stored as text with one line of keys per frame in the format
+//! of [`Buttons`]' `Display` impl. Lines starting with `#` are comments.
+//! BizHawk's `.bk2` movies can be imported from the `Input Log.txt` they
+//! contain.
+//!
+//! ```
+//! use gameboy_rom::joypad::Buttons;
+//! use gameboy_rom::movie::Movie;
+//!
+//! let movie = Movie::parse("# title screen\n........\n...RS...\n").unwrap();
+//! assert_eq!(movie.buttons(1), Buttons::RIGHT | Buttons::START);
+//! assert_eq!(movie.to_movie_string(), "........\n...RS...\n");
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::joypad::Buttons;
+
+/// Errors that may occur while reading a movie.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MovieError {
+    /// The line (1-indexed) isn't a valid set of keys.
+    MalformedLine(usize),
+    /// A BizHawk input log has no `LogKey` line describing its columns.
+    MissingLogKey,
+}
+
+/// The keys held on each frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Movie {
+    pub frames: Vec<Buttons>,
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a movie in this crate's text format.
+    pub fn parse(input: &str) -> Result<Self, MovieError> {
+        let mut movie = Self::new();
+        for (idx, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let buttons = line
+                .parse()
+                .map_err(|_| MovieError::MalformedLine(idx + 1))?;
+            movie.frames.push(buttons);
+        }
+        Ok(movie)
+    }
+
+    /// Import the `Input Log.txt` from a BizHawk `.bk2` movie.
+    ///
+    /// The columns are found from the `LogKey` line, keys other than the
+    /// joypad's such as `Power` are ignored.
+    ///
+    /// ```
+    /// use gameboy_rom::joypad::Buttons;
+    /// use gameboy_rom::movie::Movie;
+    ///
+    /// let log = "[Input]\nLogKey:#Up|Down|Left|Right|Start|Select|B|A|Power|\n\
+    ///            |.........|\n|.......A.|\n[/Input]\n";
+    /// let movie = Movie::from_bk2_input_log(log).unwrap();
+    /// assert_eq!(movie.frames, vec![Buttons::default(), Buttons::A]);
+    /// ```
+    pub fn from_bk2_input_log(input: &str) -> Result<Self, MovieError> {
+        let mut columns: Option<Vec<Option<Buttons>>> = None;
+        let mut movie = Self::new();
+        for (idx, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if let Some(key) = line.strip_prefix("LogKey:") {
+                columns = Some(
+                    key.split(['|', '#'])
+                        .filter(|name| !name.is_empty())
+                        .map(bk2_button)
+                        .collect(),
+                );
+                continue;
+            }
+            if !line.starts_with('|') {
+                continue;
+            }
+            let columns = columns.as_ref().ok_or(MovieError::MissingLogKey)?;
+            let keys: Vec<char> = line.chars().filter(|&c| c != '|').collect();
+            if keys.len() != columns.len() {
+                return Err(MovieError::MalformedLine(idx + 1));
+            }
+            let mut buttons = Buttons::default();
+            for (key, column) in keys.into_iter().zip(columns) {
+                if let (Some(button), false) = (column, key == '.') {
+                    buttons |= *button;
+                }
+            }
+            movie.frames.push(buttons);
+        }
+        Ok(movie)
+    }
+
+    /// Render the movie in this crate's text format.
+    pub fn to_movie_string(&self) -> String {
+        let mut out = String::new();
+        for buttons in &self.frames {
+            out.push_str(&format!("{}\n", buttons));
+        }
+        out
+    }
+
+    /// Record the keys held on the next frame.
+    pub fn push(&mut self, buttons: Buttons) {
+        self.frames.push(buttons);
+    }
+
+    /// The keys held on `frame`, nothing is held after the movie ends.
+    pub fn buttons(&self, frame: usize) -> Buttons {
+        self.frames.get(frame).copied().unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// The joypad key for a BizHawk log column, e.g. `Up` or `P1 A`.
+fn bk2_button(name: &str) -> Option<Buttons> {
+    let name = name.trim();
+    let name = name.strip_prefix("P1 ").unwrap_or(name);
+    Some(match name {
+        "Up" => Buttons::UP,
+        "Down" => Buttons::DOWN,
+        "Left" => Buttons::LEFT,
+        "Right" => Buttons::RIGHT,
+        "Start" => Buttons::START,
+        "Select" => Buttons::SELECT,
+        "B" => Buttons::B,
+        "A" => Buttons::A,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_line() {
+        // blank lines and comments still count
+        let input = "# comment\n\n........\nU..RSsBA\nUp\n";
+        assert_eq!(Movie::parse(input), Err(MovieError::MalformedLine(5)));
+        assert_eq!(
+            Movie::parse("........\nA.......\n"),
+            Err(MovieError::MalformedLine(2))
+        );
+        assert_eq!(
+            Movie::parse("  U......A  \n"),
+            Ok(Movie {
+                frames: vec![Buttons::UP | Buttons::A]
+            })
+        );
+    }
+
+    #[test]
+    fn bk2_missing_log_key() {
+        let log = "[Input]\n|.......A.|\n[/Input]\n";
+        assert_eq!(
+            Movie::from_bk2_input_log(log),
+            Err(MovieError::MissingLogKey)
+        );
+        // a log without any frames doesn't need one
+        assert_eq!(
+            Movie::from_bk2_input_log("[Input]\n[/Input]\n"),
+            Ok(Movie::new())
+        );
+    }
+
+    #[test]
+    fn bk2_columns() {
+        let log = "LogKey:#P1 A|P1 B|Power|P1 Up|\n|A..U|\n|.BP.|\n|A.|\n";
+        assert_eq!(
+            Movie::from_bk2_input_log(log),
+            Err(MovieError::MalformedLine(4))
+        );
+        let movie = Movie::from_bk2_input_log(&log[..log.len() - 5]).unwrap();
+        assert_eq!(movie.frames, vec![Buttons::A | Buttons::UP, Buttons::B]);
+    }
+}
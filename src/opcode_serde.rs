@@ -0,0 +1,582 @@
+//! The serde representation of `Opcode`, documented in the `opcodes` module.
+
+use crate::opcodes::*;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An instruction as its mnemonic and operands, the fields used depending on
+/// the instruction the same way they would be written in assembly.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Repr {
+    op: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cond: Option<Flag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bit: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dst: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    src: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    imm: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    addr: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    offset: Option<i8>,
+}
+
+impl Serialize for Opcode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        to_repr(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Opcode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = Repr::deserialize(deserializer)?;
+        // only the exact representation of an opcode is accepted, which also
+        // rejects operands that are out of range or don't belong
+        from_repr(&repr)
+            .filter(|opcode| to_repr(opcode) == repr)
+            .ok_or_else(|| D::Error::custom(format!("invalid operands for {}", repr.op)))
+    }
+}
+
+fn reg8(r8: Register8) -> &'static str {
+    match r8 {
+        Register8::A => "a",
+        Register8::B => "b",
+        Register8::C => "c",
+        Register8::D => "d",
+        Register8::E => "e",
+        Register8::H => "h",
+        Register8::L => "l",
+        Register8::DerefHL => "[hl]",
+    }
+}
+
+fn reg16(r16: Register16) -> &'static str {
+    match r16 {
+        Register16::BC => "bc",
+        Register16::DE => "de",
+        Register16::HL => "hl",
+        Register16::AF => "af",
+        Register16::SP => "sp",
+    }
+}
+
+fn parse_reg8(name: &str) -> Option<Register8> {
+    use Register8::*;
+    [A, B, C, D, E, H, L, DerefHL]
+        .iter()
+        .copied()
+        .find(|r8| reg8(*r8) == name)
+}
+
+fn parse_reg16(name: &str) -> Option<Register16> {
+    use Register16::*;
+    [BC, DE, HL, AF, SP]
+        .iter()
+        .copied()
+        .find(|r16| reg16(*r16) == name)
+}
+
+/// `[bc]` or `[de]`, the registers `StoreATo16` and `LoadAFromReg16Addr`
+/// go through.
+fn deref16(r16: Register16) -> String {
+    format!("[{}]", reg16(r16))
+}
+
+fn parse_deref16(name: &str) -> Option<Register16> {
+    match name {
+        "[bc]" => Some(Register16::BC),
+        "[de]" => Some(Register16::DE),
+        _ => None,
+    }
+}
+
+fn hl_inc_dec(inc: bool) -> String {
+    if inc { "[hl+]" } else { "[hl-]" }.to_string()
+}
+
+fn to_repr(opcode: &Opcode) -> Repr {
+    let op = opcode.mnemonic().to_string();
+    let reg = |name: &str| Some(name.to_string());
+    let repr = Repr {
+        op,
+        ..Repr::default()
+    };
+    match *opcode {
+        Opcode::StoreImm16(r16, imm) => Repr {
+            dst: reg(reg16(r16)),
+            imm: Some(imm),
+            ..repr
+        },
+        Opcode::StoreImm8(r8, imm) => Repr {
+            dst: reg(reg8(r8)),
+            imm: Some(imm as u16),
+            ..repr
+        },
+        Opcode::StoreAToHlAddr(inc) => Repr {
+            dst: Some(hl_inc_dec(inc)),
+            src: reg("a"),
+            ..repr
+        },
+        Opcode::LoadAFromHlAddr(inc) => Repr {
+            dst: reg("a"),
+            src: Some(hl_inc_dec(inc)),
+            ..repr
+        },
+        Opcode::StoreATo16(r16) => Repr {
+            dst: Some(deref16(r16)),
+            src: reg("a"),
+            ..repr
+        },
+        Opcode::LoadAFromReg16Addr(r16) => Repr {
+            dst: reg("a"),
+            src: Some(deref16(r16)),
+            ..repr
+        },
+        Opcode::Mov8(dst, src) => Repr {
+            dst: reg(reg8(dst)),
+            src: reg(reg8(src)),
+            ..repr
+        },
+        Opcode::Jr(cond, offset) => Repr {
+            cond,
+            offset: Some(offset as i8),
+            ..repr
+        },
+        Opcode::Jp(cond, addr) | Opcode::Call(cond, addr) => Repr {
+            cond,
+            addr: Some(addr),
+            ..repr
+        },
+        Opcode::JpHl => Repr {
+            dst: reg("hl"),
+            ..repr
+        },
+        Opcode::Inc8(r8) | Opcode::Dec8(r8) => Repr {
+            dst: reg(reg8(r8)),
+            ..repr
+        },
+        Opcode::Inc16(r16) | Opcode::Dec16(r16) | Opcode::Pop(r16) => Repr {
+            dst: reg(reg16(r16)),
+            ..repr
+        },
+        Opcode::Push(r16) => Repr {
+            src: reg(reg16(r16)),
+            ..repr
+        },
+        Opcode::Add(r8)
+        | Opcode::Adc(r8)
+        | Opcode::Sub(r8)
+        | Opcode::Sbc(r8)
+        | Opcode::And(r8)
+        | Opcode::Xor(r8)
+        | Opcode::Or(r8)
+        | Opcode::Cp(r8) => Repr {
+            dst: reg("a"),
+            src: reg(reg8(r8)),
+            ..repr
+        },
+        Opcode::Add8(imm)
+        | Opcode::Adc8(imm)
+        | Opcode::Sub8(imm)
+        | Opcode::Sbc8(imm)
+        | Opcode::And8(imm)
+        | Opcode::Xor8(imm)
+        | Opcode::Or8(imm)
+        | Opcode::Cp8(imm) => Repr {
+            dst: reg("a"),
+            imm: Some(imm as u16),
+            ..repr
+        },
+        Opcode::AddSp8(offset) => Repr {
+            dst: reg("sp"),
+            offset: Some(offset as i8),
+            ..repr
+        },
+        Opcode::StoreImm16AddrSp(addr) => Repr {
+            src: reg("sp"),
+            addr: Some(addr),
+            ..repr
+        },
+        Opcode::AddHl(r16) => Repr {
+            dst: reg("hl"),
+            src: reg(reg16(r16)),
+            ..repr
+        },
+        Opcode::Ret(cond) => Repr { cond, ..repr },
+        Opcode::Rst(n) => Repr {
+            addr: Some(n as u16 * 8),
+            ..repr
+        },
+        Opcode::LdHlSp8(offset) => Repr {
+            dst: reg("hl"),
+            src: reg("sp"),
+            offset: Some(offset),
+            ..repr
+        },
+        Opcode::LdSpHl => Repr {
+            dst: reg("sp"),
+            src: reg("hl"),
+            ..repr
+        },
+        Opcode::StoreHA(offset) => Repr {
+            src: reg("a"),
+            addr: Some(0xFF00 | offset as u16),
+            ..repr
+        },
+        Opcode::LoadHA(offset) => Repr {
+            dst: reg("a"),
+            addr: Some(0xFF00 | offset as u16),
+            ..repr
+        },
+        Opcode::StoreCA => Repr {
+            dst: reg("[c]"),
+            src: reg("a"),
+            ..repr
+        },
+        Opcode::LoadCA => Repr {
+            dst: reg("a"),
+            src: reg("[c]"),
+            ..repr
+        },
+        Opcode::StoreAAtAddress(addr) => Repr {
+            src: reg("a"),
+            addr: Some(addr),
+            ..repr
+        },
+        Opcode::LoadAFromAddress(addr) => Repr {
+            dst: reg("a"),
+            addr: Some(addr),
+            ..repr
+        },
+        Opcode::Rlc(r8)
+        | Opcode::Rrc(r8)
+        | Opcode::Rr(r8)
+        | Opcode::Rl(r8)
+        | Opcode::Sla(r8)
+        | Opcode::Sra(r8)
+        | Opcode::Swap(r8)
+        | Opcode::Srl(r8) => Repr {
+            dst: reg(reg8(r8)),
+            ..repr
+        },
+        Opcode::Bit(bit, r8) => Repr {
+            bit: Some(bit),
+            src: reg(reg8(r8)),
+            ..repr
+        },
+        Opcode::Res(bit, r8) | Opcode::Set(bit, r8) => Repr {
+            bit: Some(bit),
+            dst: reg(reg8(r8)),
+            ..repr
+        },
+        Opcode::Nop
+        | Opcode::Stop
+        | Opcode::Halt
+        | Opcode::Daa
+        | Opcode::Scf
+        | Opcode::Cpl
+        | Opcode::Ccf
+        | Opcode::Rlca
+        | Opcode::Rla
+        | Opcode::Rrca
+        | Opcode::Rra
+        | Opcode::Reti
+        | Opcode::Di
+        | Opcode::Ei => repr,
+    }
+}
+
+/// The opcode a representation most likely means. The caller checks that
+/// it's exactly the representation of that opcode.
+fn from_repr(repr: &Repr) -> Option<Opcode> {
+    let dst = repr.dst.as_deref();
+    let src = repr.src.as_deref();
+    let dst8 = dst.and_then(parse_reg8);
+    let src8 = src.and_then(parse_reg8);
+    let dst16 = dst.and_then(parse_reg16);
+    let src16 = src.and_then(parse_reg16);
+    let imm8 = repr.imm.map(|imm| imm as u8);
+    let offset = repr.offset.map(|offset| offset as u8);
+    let high = repr.addr.map(|addr| addr as u8);
+    let bit = repr.bit.filter(|bit| *bit < 8);
+
+    Some(match repr.op.as_str() {
+        "nop" => Opcode::Nop,
+        "stop" => Opcode::Stop,
+        "halt" => Opcode::Halt,
+        "daa" => Opcode::Daa,
+        "scf" => Opcode::Scf,
+        "cpl" => Opcode::Cpl,
+        "ccf" => Opcode::Ccf,
+        "rlca" => Opcode::Rlca,
+        "rla" => Opcode::Rla,
+        "rrca" => Opcode::Rrca,
+        "rra" => Opcode::Rra,
+        "reti" => Opcode::Reti,
+        "di" => Opcode::Di,
+        "ei" => Opcode::Ei,
+        "ld" => match (dst, src) {
+            (Some("[hl+]"), _) => Opcode::StoreAToHlAddr(true),
+            (Some("[hl-]"), _) => Opcode::StoreAToHlAddr(false),
+            (_, Some("[hl+]")) => Opcode::LoadAFromHlAddr(true),
+            (_, Some("[hl-]")) => Opcode::LoadAFromHlAddr(false),
+            (Some("hl"), Some("sp")) => Opcode::LdHlSp8(repr.offset?),
+            (Some("sp"), Some("hl")) => Opcode::LdSpHl,
+            (None, Some("sp")) => Opcode::StoreImm16AddrSp(repr.addr?),
+            (None, _) => Opcode::StoreAAtAddress(repr.addr?),
+            (_, None) if repr.addr.is_some() => Opcode::LoadAFromAddress(repr.addr?),
+            (_, None) if dst16.is_some() => Opcode::StoreImm16(dst16?, repr.imm?),
+            (_, None) => Opcode::StoreImm8(dst8?, imm8?),
+            (Some(dst), _) if parse_deref16(dst).is_some() => {
+                Opcode::StoreATo16(parse_deref16(dst)?)
+            }
+            (_, Some(src)) if parse_deref16(src).is_some() => {
+                Opcode::LoadAFromReg16Addr(parse_deref16(src)?)
+            }
+            _ => Opcode::Mov8(dst8?, src8?),
+        },
+        "ldh" => match (dst, src) {
+            (Some("[c]"), _) => Opcode::StoreCA,
+            (_, Some("[c]")) => Opcode::LoadCA,
+            (None, _) => Opcode::StoreHA(high?),
+            _ => Opcode::LoadHA(high?),
+        },
+        "jr" => Opcode::Jr(repr.cond, offset?),
+        "jp" if dst.is_some() => Opcode::JpHl,
+        "jp" => Opcode::Jp(repr.cond, repr.addr?),
+        "call" => Opcode::Call(repr.cond, repr.addr?),
+        "ret" => Opcode::Ret(repr.cond),
+        "rst" if repr.addr? <= 0x38 => Opcode::Rst((repr.addr? / 8) as u8),
+        "inc" if dst16.is_some() => Opcode::Inc16(dst16?),
+        "inc" => Opcode::Inc8(dst8?),
+        "dec" if dst16.is_some() => Opcode::Dec16(dst16?),
+        "dec" => Opcode::Dec8(dst8?),
+        "push" => Opcode::Push(src16?),
+        "pop" => Opcode::Pop(dst16?),
+        "add" => match dst {
+            Some("sp") => Opcode::AddSp8(offset?),
+            Some("hl") => Opcode::AddHl(src16?),
+            _ if repr.imm.is_some() => Opcode::Add8(imm8?),
+            _ => Opcode::Add(src8?),
+        },
+        "adc" if repr.imm.is_some() => Opcode::Adc8(imm8?),
+        "adc" => Opcode::Adc(src8?),
+        "sub" if repr.imm.is_some() => Opcode::Sub8(imm8?),
+        "sub" => Opcode::Sub(src8?),
+        "sbc" if repr.imm.is_some() => Opcode::Sbc8(imm8?),
+        "sbc" => Opcode::Sbc(src8?),
+        "and" if repr.imm.is_some() => Opcode::And8(imm8?),
+        "and" => Opcode::And(src8?),
+        "xor" if repr.imm.is_some() => Opcode::Xor8(imm8?),
+        "xor" => Opcode::Xor(src8?),
+        "or" if repr.imm.is_some() => Opcode::Or8(imm8?),
+        "or" => Opcode::Or(src8?),
+        "cp" if repr.imm.is_some() => Opcode::Cp8(imm8?),
+        "cp" => Opcode::Cp(src8?),
+        "rlc" => Opcode::Rlc(dst8?),
+        "rrc" => Opcode::Rrc(dst8?),
+        "rr" => Opcode::Rr(dst8?),
+        "rl" => Opcode::Rl(dst8?),
+        "sla" => Opcode::Sla(dst8?),
+        "sra" => Opcode::Sra(dst8?),
+        "swap" => Opcode::Swap(dst8?),
+        "srl" => Opcode::Srl(dst8?),
+        "bit" => Opcode::Bit(bit?, src8?),
+        "res" => Opcode::Res(bit?, dst8?),
+        "set" => Opcode::Set(bit?, dst8?),
+        _ => return None,
+    })
+}
+
+#[cfg(all(test, feature = "opcode-serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots() {
+        let snapshots = [
+            (Opcode::Nop, r#"{"op":"nop"}"#),
+            (Opcode::Stop, r#"{"op":"stop"}"#),
+            (Opcode::Halt, r#"{"op":"halt"}"#),
+            (
+                Opcode::StoreImm16(Register16::HL, 0xC000),
+                r#"{"op":"ld","dst":"hl","imm":49152}"#,
+            ),
+            (
+                Opcode::StoreImm8(Register8::DerefHL, 0x12),
+                r#"{"op":"ld","dst":"[hl]","imm":18}"#,
+            ),
+            (
+                Opcode::StoreAToHlAddr(true),
+                r#"{"op":"ld","dst":"[hl+]","src":"a"}"#,
+            ),
+            (
+                Opcode::LoadAFromHlAddr(false),
+                r#"{"op":"ld","dst":"a","src":"[hl-]"}"#,
+            ),
+            (
+                Opcode::StoreATo16(Register16::BC),
+                r#"{"op":"ld","dst":"[bc]","src":"a"}"#,
+            ),
+            (
+                Opcode::LoadAFromReg16Addr(Register16::DE),
+                r#"{"op":"ld","dst":"a","src":"[de]"}"#,
+            ),
+            (
+                Opcode::Mov8(Register8::B, Register8::C),
+                r#"{"op":"ld","dst":"b","src":"c"}"#,
+            ),
+            (
+                Opcode::Mov8(Register8::DerefHL, Register8::A),
+                r#"{"op":"ld","dst":"[hl]","src":"a"}"#,
+            ),
+            (
+                Opcode::Jr(Some(Flag::NZ), 0xFE),
+                r#"{"op":"jr","cond":"nz","offset":-2}"#,
+            ),
+            (Opcode::Jp(None, 0x150), r#"{"op":"jp","addr":336}"#),
+            (Opcode::Inc8(Register8::A), r#"{"op":"inc","dst":"a"}"#),
+            (Opcode::Dec8(Register8::L), r#"{"op":"dec","dst":"l"}"#),
+            (Opcode::Inc16(Register16::SP), r#"{"op":"inc","dst":"sp"}"#),
+            (Opcode::Dec16(Register16::DE), r#"{"op":"dec","dst":"de"}"#),
+            (Opcode::Push(Register16::AF), r#"{"op":"push","src":"af"}"#),
+            (Opcode::Pop(Register16::HL), r#"{"op":"pop","dst":"hl"}"#),
+            (
+                Opcode::Add(Register8::B),
+                r#"{"op":"add","dst":"a","src":"b"}"#,
+            ),
+            (
+                Opcode::Adc(Register8::C),
+                r#"{"op":"adc","dst":"a","src":"c"}"#,
+            ),
+            (
+                Opcode::Sub(Register8::D),
+                r#"{"op":"sub","dst":"a","src":"d"}"#,
+            ),
+            (
+                Opcode::Sbc(Register8::E),
+                r#"{"op":"sbc","dst":"a","src":"e"}"#,
+            ),
+            (
+                Opcode::And(Register8::H),
+                r#"{"op":"and","dst":"a","src":"h"}"#,
+            ),
+            (
+                Opcode::Xor(Register8::A),
+                r#"{"op":"xor","dst":"a","src":"a"}"#,
+            ),
+            (
+                Opcode::Or(Register8::DerefHL),
+                r#"{"op":"or","dst":"a","src":"[hl]"}"#,
+            ),
+            (
+                Opcode::Cp(Register8::L),
+                r#"{"op":"cp","dst":"a","src":"l"}"#,
+            ),
+            (Opcode::Add8(1), r#"{"op":"add","dst":"a","imm":1}"#),
+            (Opcode::Adc8(2), r#"{"op":"adc","dst":"a","imm":2}"#),
+            (Opcode::Sub8(3), r#"{"op":"sub","dst":"a","imm":3}"#),
+            (Opcode::Sbc8(4), r#"{"op":"sbc","dst":"a","imm":4}"#),
+            (Opcode::And8(0x0F), r#"{"op":"and","dst":"a","imm":15}"#),
+            (Opcode::Xor8(0xFF), r#"{"op":"xor","dst":"a","imm":255}"#),
+            (Opcode::Or8(0x80), r#"{"op":"or","dst":"a","imm":128}"#),
+            (Opcode::Cp8(0x90), r#"{"op":"cp","dst":"a","imm":144}"#),
+            (
+                Opcode::AddSp8(0xF8),
+                r#"{"op":"add","dst":"sp","offset":-8}"#,
+            ),
+            (Opcode::Daa, r#"{"op":"daa"}"#),
+            (Opcode::Scf, r#"{"op":"scf"}"#),
+            (Opcode::Cpl, r#"{"op":"cpl"}"#),
+            (Opcode::Ccf, r#"{"op":"ccf"}"#),
+            (Opcode::Rlca, r#"{"op":"rlca"}"#),
+            (Opcode::Rla, r#"{"op":"rla"}"#),
+            (Opcode::Rrca, r#"{"op":"rrca"}"#),
+            (Opcode::Rra, r#"{"op":"rra"}"#),
+            (
+                Opcode::StoreImm16AddrSp(0xC100),
+                r#"{"op":"ld","src":"sp","addr":49408}"#,
+            ),
+            (
+                Opcode::AddHl(Register16::BC),
+                r#"{"op":"add","dst":"hl","src":"bc"}"#,
+            ),
+            (Opcode::Ret(Some(Flag::C)), r#"{"op":"ret","cond":"c"}"#),
+            (Opcode::Ret(None), r#"{"op":"ret"}"#),
+            (Opcode::Reti, r#"{"op":"reti"}"#),
+            (Opcode::Di, r#"{"op":"di"}"#),
+            (Opcode::Ei, r#"{"op":"ei"}"#),
+            (
+                Opcode::Call(Some(Flag::Z), 0x4000),
+                r#"{"op":"call","cond":"z","addr":16384}"#,
+            ),
+            (Opcode::JpHl, r#"{"op":"jp","dst":"hl"}"#),
+            (Opcode::Rst(7), r#"{"op":"rst","addr":56}"#),
+            (
+                Opcode::LdHlSp8(-1),
+                r#"{"op":"ld","dst":"hl","src":"sp","offset":-1}"#,
+            ),
+            (Opcode::LdSpHl, r#"{"op":"ld","dst":"sp","src":"hl"}"#),
+            (
+                Opcode::StoreHA(0x40),
+                r#"{"op":"ldh","src":"a","addr":65344}"#,
+            ),
+            (
+                Opcode::LoadHA(0x44),
+                r#"{"op":"ldh","dst":"a","addr":65348}"#,
+            ),
+            (Opcode::StoreCA, r#"{"op":"ldh","dst":"[c]","src":"a"}"#),
+            (Opcode::LoadCA, r#"{"op":"ldh","dst":"a","src":"[c]"}"#),
+            (
+                Opcode::StoreAAtAddress(0x2000),
+                r#"{"op":"ld","src":"a","addr":8192}"#,
+            ),
+            (
+                Opcode::LoadAFromAddress(0xFF80),
+                r#"{"op":"ld","dst":"a","addr":65408}"#,
+            ),
+            (Opcode::Rlc(Register8::B), r#"{"op":"rlc","dst":"b"}"#),
+            (Opcode::Rrc(Register8::C), r#"{"op":"rrc","dst":"c"}"#),
+            (Opcode::Rr(Register8::D), r#"{"op":"rr","dst":"d"}"#),
+            (Opcode::Rl(Register8::E), r#"{"op":"rl","dst":"e"}"#),
+            (Opcode::Sla(Register8::H), r#"{"op":"sla","dst":"h"}"#),
+            (Opcode::Sra(Register8::L), r#"{"op":"sra","dst":"l"}"#),
+            (Opcode::Swap(Register8::A), r#"{"op":"swap","dst":"a"}"#),
+            (
+                Opcode::Srl(Register8::DerefHL),
+                r#"{"op":"srl","dst":"[hl]"}"#,
+            ),
+            (
+                Opcode::Bit(7, Register8::H),
+                r#"{"op":"bit","bit":7,"src":"h"}"#,
+            ),
+            (
+                Opcode::Res(0, Register8::A),
+                r#"{"op":"res","bit":0,"dst":"a"}"#,
+            ),
+            (
+                Opcode::Set(3, Register8::DerefHL),
+                r#"{"op":"set","bit":3,"dst":"[hl]"}"#,
+            ),
+        ];
+        for (opcode, json) in snapshots.iter() {
+            assert_eq!(&serde_json::to_string(opcode).unwrap(), json);
+            assert_eq!(&serde_json::from_str::<Opcode>(json).unwrap(), opcode);
+        }
+        assert!(serde_json::from_str::<Opcode>(r#"{"op":"ld","dst":"a","imm":256}"#).is_err());
+        assert!(serde_json::from_str::<Opcode>(r#"{"op":"nop","dst":"a"}"#).is_err());
+        assert_eq!(
+            serde_json::to_string(&Register8::DerefHL).unwrap(),
+            r#""[hl]""#
+        );
+    }
+}
@@ -1,10 +1,35 @@
 //! Types related to the opcodes of the Gameboy parser.
+//!
+//! With the `opcode-serde` feature the types here implement serde's
+//! `Serialize` and `Deserialize`. Registers and flags are their lower case
+//! names (`"a"`, `"[hl]"`, `"bc"`, `"nz"`) and an `Opcode` is a map of its
+//! RGBDS mnemonic and operands, written as they would be in assembly:
+//!
+//! - `op`: the mnemonic, as given by `Opcode::mnemonic`.
+//! - `cond`: the flag a jump, call or return depends on.
+//! - `bit`: the bit tested, reset or set.
+//! - `dst` and `src`: registers, or the memory they point to such as
+//!   `"[bc]"`, `"[hl+]"` and `"[c]"`.
+//! - `imm`: an 8 or 16 bit immediate value.
+//! - `addr`: an absolute address, including `ldh`'s `$FF00` and the vector of
+//!   an `rst`.
+//! - `offset`: a signed offset, for `jr`, `add sp` and `ld hl, sp`.
+//!
+//! Fields an instruction doesn't have are left out, and only the exact
+//! representation of an opcode is accepted when deserializing. The
+//! representation of every instruction is covered by a snapshot test in
+//! `opcode_serde`, so changing it fails that test.
 
 /// An 8 bit register.
 ///
 /// Includes the `DerefHL` variant which represents a memory access at the value
 /// contained in the `HL` 16 bit register.
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "opcode-serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Register8 {
     A,
     B,
@@ -13,6 +38,7 @@ pub enum Register8 {
     E,
     H,
     L,
+    #[cfg_attr(feature = "opcode-serde", serde(rename = "[hl]"))]
     DerefHL,
 }
 
@@ -21,6 +47,11 @@ pub enum Register8 {
 /// Does not include `PC`, the program counter, used to indicate which
 /// instruction is being executed next.
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "opcode-serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Register16 {
     /// 16 bit register that's made up of 2 8 bit registers, `B` and `C`.
     BC,
@@ -43,6 +74,11 @@ pub enum Register16 {
 /// is a `Jp` (jump) instruction that will jump to location 0x1234, if the `NZ`
 /// (not zero) flag is set.
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "opcode-serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Flag {
     /// The Carry flag.
     C,
@@ -218,3 +254,66 @@ pub enum Opcode {
     /// u8 is number between 0 and 7 (inclusive)
     Set(u8, Register8),
 }
+
+impl Opcode {
+    /// The RGBDS mnemonic of the instruction, such as `ld` or `jr`.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Nop => "nop",
+            Opcode::Stop => "stop",
+            Opcode::Halt => "halt",
+            Opcode::StoreImm16(..)
+            | Opcode::StoreImm8(..)
+            | Opcode::StoreAToHlAddr(_)
+            | Opcode::LoadAFromHlAddr(_)
+            | Opcode::StoreATo16(_)
+            | Opcode::LoadAFromReg16Addr(_)
+            | Opcode::Mov8(..)
+            | Opcode::StoreImm16AddrSp(_)
+            | Opcode::LdHlSp8(_)
+            | Opcode::LdSpHl
+            | Opcode::StoreAAtAddress(_)
+            | Opcode::LoadAFromAddress(_) => "ld",
+            Opcode::StoreHA(_) | Opcode::LoadHA(_) | Opcode::StoreCA | Opcode::LoadCA => "ldh",
+            Opcode::Jr(..) => "jr",
+            Opcode::Jp(..) | Opcode::JpHl => "jp",
+            Opcode::Inc8(_) | Opcode::Inc16(_) => "inc",
+            Opcode::Dec8(_) | Opcode::Dec16(_) => "dec",
+            Opcode::Push(_) => "push",
+            Opcode::Pop(_) => "pop",
+            Opcode::Add(_) | Opcode::Add8(_) | Opcode::AddSp8(_) | Opcode::AddHl(_) => "add",
+            Opcode::Adc(_) | Opcode::Adc8(_) => "adc",
+            Opcode::Sub(_) | Opcode::Sub8(_) => "sub",
+            Opcode::Sbc(_) | Opcode::Sbc8(_) => "sbc",
+            Opcode::And(_) | Opcode::And8(_) => "and",
+            Opcode::Xor(_) | Opcode::Xor8(_) => "xor",
+            Opcode::Or(_) | Opcode::Or8(_) => "or",
+            Opcode::Cp(_) | Opcode::Cp8(_) => "cp",
+            Opcode::Daa => "daa",
+            Opcode::Scf => "scf",
+            Opcode::Cpl => "cpl",
+            Opcode::Ccf => "ccf",
+            Opcode::Rlca => "rlca",
+            Opcode::Rla => "rla",
+            Opcode::Rrca => "rrca",
+            Opcode::Rra => "rra",
+            Opcode::Ret(_) => "ret",
+            Opcode::Reti => "reti",
+            Opcode::Di => "di",
+            Opcode::Ei => "ei",
+            Opcode::Call(..) => "call",
+            Opcode::Rst(_) => "rst",
+            Opcode::Rlc(_) => "rlc",
+            Opcode::Rrc(_) => "rrc",
+            Opcode::Rr(_) => "rr",
+            Opcode::Rl(_) => "rl",
+            Opcode::Sla(_) => "sla",
+            Opcode::Sra(_) => "sra",
+            Opcode::Swap(_) => "swap",
+            Opcode::Srl(_) => "srl",
+            Opcode::Bit(..) => "bit",
+            Opcode::Res(..) => "res",
+            Opcode::Set(..) => "set",
+        }
+    }
+}
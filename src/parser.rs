@@ -1,9 +1,13 @@
 //! The logic to transform bytes into GameBoy ROM data types
 
+#[cfg(feature = "std")]
+use crate::gbs::GbsHeader;
 use crate::header::*;
 use crate::opcodes::*;
 use crate::util::*;
 
+#[cfg(feature = "std")]
+use nom::bytes::complete::tag;
 use nom::{
     bytes::complete::take,
     combinator::*,
@@ -13,22 +17,20 @@ use nom::{
     Err, IResult,
 };
 
-pub fn parse_scrolling_graphic<'a>(
-    input: &'a [u8],
-) -> IResult<&'a [u8], &'a [u8], VerboseError<&'a [u8]>> {
+pub fn parse_scrolling_graphic(input: &[u8]) -> IResult<&[u8], &[u8], VerboseError<&[u8]>> {
     context("scrolling graphic", take(0x30usize))(input)
 }
 
-pub fn parse_game_title<'a>(input: &'a [u8]) -> IResult<&'a [u8], &'a str, VerboseError<&'a [u8]>> {
+pub fn parse_game_title(input: &[u8]) -> IResult<&[u8], &str, VerboseError<&[u8]>> {
     context(
         "game title as ASCII",
-        map_res(take(0xFusize), std::str::from_utf8),
+        map_res(take(0xFusize), core::str::from_utf8),
     )(input)
 }
 
-pub fn parse_gbc_byte<'a>(
-    input: &'a [u8],
-) -> IResult<&'a [u8], GameboyColorCompatibility, VerboseError<&'a [u8]>> {
+pub fn parse_gbc_byte(
+    input: &[u8],
+) -> IResult<&[u8], GameboyColorCompatibility, VerboseError<&[u8]>> {
     let (i, byte) = take(1usize)(input)?;
 
     match byte[0] {
@@ -38,7 +40,7 @@ pub fn parse_gbc_byte<'a>(
     }
 }
 
-pub fn parse_rom_type<'a>(input: &'a [u8]) -> IResult<&'a [u8], RomType, VerboseError<&'a [u8]>> {
+pub fn parse_rom_type(input: &[u8]) -> IResult<&[u8], RomType, VerboseError<&[u8]>> {
     let (i, byte) = take(1usize)(input)?;
 
     Ok((i, byte[0].into()))
@@ -55,7 +57,7 @@ pub fn parse_new_licensee_code<'a>(
 
 /// 3 is SGB
 /// 0 is GB
-pub fn parse_sgb_byte<'a>(input: &'a [u8]) -> IResult<&'a [u8], bool, VerboseError<&'a [u8]>> {
+pub fn parse_sgb_byte(input: &[u8]) -> IResult<&[u8], bool, VerboseError<&[u8]>> {
     let (i, byte) = take(1usize)(input)?;
 
     Ok((i, byte[0] == 0x03))
@@ -75,7 +77,7 @@ pub fn parse_ram_size<'a>(input: &'a [u8]) -> IResult<&'a [u8], (u8, u16), Verbo
     )(input)
 }
 
-pub fn parse_jp_byte<'a>(input: &'a [u8]) -> IResult<&'a [u8], bool, VerboseError<&'a [u8]>> {
+pub fn parse_jp_byte(input: &[u8]) -> IResult<&[u8], bool, VerboseError<&[u8]>> {
     let (i, byte) = take(1usize)(input)?;
 
     Ok((i, byte[0] == 0))
@@ -144,6 +146,68 @@ pub fn parse_rom_header<'a>(
     )(input)
 }
 
+/// A NUL padded 32 byte string from a GBS header, these aren't always valid
+/// UTF-8 so invalid characters are replaced.
+#[cfg(feature = "std")]
+pub fn parse_gbs_string(input: &[u8]) -> IResult<&[u8], String, VerboseError<&[u8]>> {
+    context(
+        "GBS string",
+        map(take(32usize), |bytes: &[u8]| {
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).into_owned()
+        }),
+    )(input)
+}
+
+#[cfg(feature = "std")]
+pub fn parse_gbs_header(input: &[u8]) -> IResult<&[u8], GbsHeader, VerboseError<&[u8]>> {
+    map(
+        tuple((
+            context("GBS magic", tag("GBS")),
+            context("version", parse_byte),
+            context("song count", parse_byte),
+            context("first song", parse_byte),
+            context("load address", le_u16),
+            context("init address", le_u16),
+            context("play address", le_u16),
+            context("stack pointer", le_u16),
+            context("timer modulo", parse_byte),
+            context("timer control", parse_byte),
+            parse_gbs_string,
+            parse_gbs_string,
+            parse_gbs_string,
+        )),
+        |(
+            _,
+            version,
+            song_count,
+            first_song,
+            load_address,
+            init_address,
+            play_address,
+            stack_pointer,
+            timer_modulo,
+            timer_control,
+            title,
+            author,
+            copyright,
+        )| GbsHeader {
+            version,
+            song_count,
+            first_song,
+            load_address,
+            init_address,
+            play_address,
+            stack_pointer,
+            timer_modulo,
+            timer_control,
+            title,
+            author,
+            copyright,
+        },
+    )(input)
+}
+
 pub fn parse_instruction(input: &[u8]) -> IResult<&[u8], Opcode, VerboseError<&[u8]>> {
     let (i, byte) = take(1usize)(input)?;
     Ok(match byte[0] {
@@ -468,18 +532,18 @@ pub fn parse_cb(input: &[u8]) -> IResult<&[u8], Opcode, VerboseError<&[u8]>> {
     const CB_TABLE: [Opcode; 256] = [
         Opcode::Rlc(Register8::B),
         Opcode::Rlc(Register8::C),
-        Opcode::Rlc(Register8::E),
         Opcode::Rlc(Register8::D),
-        Opcode::Rlc(Register8::L),
+        Opcode::Rlc(Register8::E),
         Opcode::Rlc(Register8::H),
+        Opcode::Rlc(Register8::L),
         Opcode::Rlc(Register8::DerefHL),
         Opcode::Rlc(Register8::A),
         Opcode::Rrc(Register8::B),
         Opcode::Rrc(Register8::C),
-        Opcode::Rrc(Register8::E),
         Opcode::Rrc(Register8::D),
-        Opcode::Rrc(Register8::L),
+        Opcode::Rrc(Register8::E),
         Opcode::Rrc(Register8::H),
+        Opcode::Rrc(Register8::L),
         Opcode::Rrc(Register8::DerefHL),
         Opcode::Rrc(Register8::A),
         // 0x1X
@@ -742,3 +806,96 @@ pub fn parse_cb(input: &[u8]) -> IResult<&[u8], Opcode, VerboseError<&[u8]>> {
     let (i, byte) = take(1usize)(input)?;
     Ok((i, CB_TABLE[byte[0] as usize]))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Opcode {
+        let (rest, opcode) = parse_instruction(bytes).unwrap();
+        assert!(rest.is_empty());
+        opcode
+    }
+
+    #[test]
+    fn cb_rlc_rrc_registers() {
+        use Register8::*;
+        let expected = [
+            (0x02, Opcode::Rlc(D)),
+            (0x03, Opcode::Rlc(E)),
+            (0x04, Opcode::Rlc(H)),
+            (0x05, Opcode::Rlc(L)),
+            (0x0A, Opcode::Rrc(D)),
+            (0x0B, Opcode::Rrc(E)),
+            (0x0C, Opcode::Rrc(H)),
+            (0x0D, Opcode::Rrc(L)),
+        ];
+        for (byte, opcode) in expected.iter() {
+            assert_eq!(decode(&[0xCB, *byte]), *opcode, "CB {:02X}", byte);
+        }
+    }
+
+    #[test]
+    fn cb_operand_order() {
+        use Register8::*;
+        let registers = [B, C, D, E, H, L, DerefHL, A];
+        for byte in 0..=0xFFu8 {
+            let register = registers[byte as usize & 7];
+            let bit = (byte >> 3) & 7;
+            let expected = match byte >> 3 {
+                0 => Opcode::Rlc(register),
+                1 => Opcode::Rrc(register),
+                2 => Opcode::Rl(register),
+                3 => Opcode::Rr(register),
+                4 => Opcode::Sla(register),
+                5 => Opcode::Sra(register),
+                6 => Opcode::Swap(register),
+                7 => Opcode::Srl(register),
+                8..=15 => Opcode::Bit(bit, register),
+                16..=23 => Opcode::Res(bit, register),
+                _ => Opcode::Set(bit, register),
+            };
+            assert_eq!(decode(&[0xCB, byte]), expected, "CB {:02X}", byte);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn gbs_header() {
+        let mut bytes = b"GBS\x01\x03\x02\x70\x04\x80\x04\x90\x04\xFE\xFF\xC0\x04".to_vec();
+        let mut string = |s: &[u8]| {
+            bytes.extend_from_slice(s);
+            bytes.resize(bytes.len() + 32 - s.len(), 0);
+        };
+        string(b"Title");
+        // a full string has no NUL
+        string(&[b'A'; 32]);
+        string(b"\xFF 1998");
+        assert_eq!(bytes.len(), crate::gbs::GBS_HEADER_SIZE);
+        bytes.extend_from_slice(&[0xC9, 0xC9]);
+
+        let (rest, header) = parse_gbs_header(&bytes).unwrap();
+        assert_eq!(rest, &[0xC9, 0xC9]);
+        assert_eq!(
+            header,
+            GbsHeader {
+                version: 1,
+                song_count: 3,
+                first_song: 2,
+                load_address: 0x0470,
+                init_address: 0x0480,
+                play_address: 0x0490,
+                stack_pointer: 0xFFFE,
+                timer_modulo: 0xC0,
+                timer_control: 0x04,
+                title: "Title".to_string(),
+                author: "A".repeat(32),
+                copyright: "\u{FFFD} 1998".to_string(),
+            }
+        );
+
+        assert!(parse_gbs_header(&bytes[..0x6F]).is_err());
+        bytes[0] = b'X';
+        assert!(parse_gbs_header(&bytes).is_err());
+    }
+}
@@ -0,0 +1,538 @@
+//! A software pixel processing unit rendering to an RGBA framebuffer.
+//!
+//! Lines are rendered in one go at the end of mode 3 rather than pixel by
+//! pixel, which is accurate enough for nearly all games and for comparing
+//! screenshots in tests. The PPU is a [`Bus`] covering VRAM (0x8000-0x9FFF),
+//! OAM (0xFE00-0xFE9F) and the LCD registers; OAM DMA is left to whatever owns
+//! the rest of the memory map.
+//!
+//! ```
+//! use gameboy_rom::cpu::Bus;
+//! use gameboy_rom::ppu::{Ppu, DMG_GREYSCALE, FRAME_CYCLES};
+//!
+//! let mut ppu = Ppu::new(false);
+//! // the top row of tile 0 uses color 3, the rest color 0
+//! ppu.write(0x8000, 0xFF);
+//! ppu.write(0x8001, 0xFF);
+//! ppu.write(0xFF47, 0xE4);
+//! // LCD and background on, tile data at 0x8000
+//! ppu.write(0xFF40, 0x91);
+//! ppu.step(FRAME_CYCLES);
+//! assert_eq!(ppu.pixel(0, 0), DMG_GREYSCALE[3]);
+//! assert_eq!(ppu.pixel(0, 1), DMG_GREYSCALE[0]);
+//! ```
+//!
+//! Behavior is based on the [Pan Docs].
+//!
+//! [Pan Docs]: https://gbdev.io/pandocs/Graphics.html
+
+use crate::cpu::Bus;
+use crate::interrupts::{INTERRUPT_STAT, INTERRUPT_VBLANK};
+
+use serde::{Deserialize, Serialize};
+
+/// The width of the screen in pixels.
+pub const SCREEN_WIDTH: usize = 160;
+/// The height of the screen in pixels.
+pub const SCREEN_HEIGHT: usize = 144;
+/// The number of T-cycles in one frame.
+pub const FRAME_CYCLES: u32 = DOTS_PER_LINE * LINES_PER_FRAME as u32;
+
+/// The four DMG shades from lightest to darkest as RGBA.
+pub const DMG_GREYSCALE: [[u8; 4]; 4] = [
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF],
+];
+
+const DOTS_PER_LINE: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const SPRITES_PER_LINE: usize = 10;
+const VRAM_BANK_SIZE: usize = 0x2000;
+const OAM_SIZE: usize = 0xA0;
+
+const LCDC_BG_ENABLE: u8 = 0x01;
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_OBJ_SIZE: u8 = 0x04;
+const LCDC_BG_MAP: u8 = 0x08;
+const LCDC_TILE_DATA: u8 = 0x10;
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_LCD_ENABLE: u8 = 0x80;
+
+const STAT_HBLANK_INTERRUPT: u8 = 0x08;
+const STAT_VBLANK_INTERRUPT: u8 = 0x10;
+const STAT_OAM_INTERRUPT: u8 = 0x20;
+const STAT_LYC_INTERRUPT: u8 = 0x40;
+
+/// Tile attributes shared by CGB background maps and OAM.
+const ATTR_BANK: u8 = 0x08;
+const ATTR_X_FLIP: u8 = 0x20;
+const ATTR_Y_FLIP: u8 = 0x40;
+const ATTR_PRIORITY: u8 = 0x80;
+/// OAM only, selects OBP1 on DMG.
+const ATTR_DMG_PALETTE: u8 = 0x10;
+
+/// What the PPU is currently doing, as reported in the low bits of STAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// The pixel processing unit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ppu {
+    cgb: bool,
+    /// Both VRAM banks, bank 1 is only used on CGB.
+    vram: Vec<u8>,
+    oam: Vec<u8>,
+    lcdc: u8,
+    /// Only the interrupt select bits (3-6) are stored.
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    vbk: u8,
+    bcps: u8,
+    ocps: u8,
+    /// 8 CGB background palettes of 4 little endian BGR555 colors.
+    #[serde(with = "crate::savestate::byte_array")]
+    bg_palettes: [u8; 64],
+    /// 8 CGB sprite palettes of 4 little endian BGR555 colors.
+    #[serde(with = "crate::savestate::byte_array")]
+    obj_palettes: [u8; 64],
+    mode: Mode,
+    /// The dot within the current line.
+    dots: u32,
+    /// The line of the window to draw next, it only advances on lines where
+    /// the window is visible.
+    window_line: u8,
+    /// Whether LY has matched WY this frame.
+    window_triggered: bool,
+    /// The STAT interrupt fires on the rising edge of this.
+    stat_line: bool,
+    frames: u64,
+    framebuffer: Vec<u8>,
+    /// The colors used for the four DMG shades.
+    pub dmg_colors: [[u8; 4]; 4],
+}
+
+impl Ppu {
+    /// Create a PPU with the LCD off, `cgb` enables CGB palettes and VRAM
+    /// banking.
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            cgb,
+            vram: vec![0; VRAM_BANK_SIZE * 2],
+            oam: vec![0; OAM_SIZE],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0,
+            wx: 0,
+            vbk: 0,
+            bcps: 0,
+            ocps: 0,
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0xFF; 64],
+            mode: Mode::HBlank,
+            dots: 0,
+            window_line: 0,
+            window_triggered: false,
+            stat_line: false,
+            frames: 0,
+            framebuffer: vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
+            dmg_colors: DMG_GREYSCALE,
+        }
+    }
+
+    /// The most recently rendered frame, `SCREEN_WIDTH * SCREEN_HEIGHT` RGBA
+    /// pixels in rows from top to bottom.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    /// The RGBA color of a pixel in the framebuffer.
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.framebuffer[offset..offset + 4]);
+        pixel
+    }
+
+    /// The number of frames that have finished rendering.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_LCD_ENABLE != 0
+    }
+
+    /// Run the PPU for the given number of T-cycles, returning the interrupts
+    /// it requested as IF bits.
+    pub fn step(&mut self, cycles: u32) -> u8 {
+        if !self.lcd_enabled() {
+            return 0;
+        }
+        let mut interrupts = 0;
+        for _ in 0..cycles {
+            interrupts |= self.tick();
+        }
+        interrupts
+    }
+
+    fn tick(&mut self) -> u8 {
+        let mut interrupts = 0;
+        self.dots += 1;
+        if self.ly < SCREEN_HEIGHT as u8 {
+            if self.dots == OAM_SCAN_DOTS {
+                self.mode = Mode::Drawing;
+            } else if self.dots == OAM_SCAN_DOTS + DRAWING_DOTS {
+                self.render_line();
+                self.mode = Mode::HBlank;
+            }
+        }
+        if self.dots == DOTS_PER_LINE {
+            self.dots = 0;
+            self.ly += 1;
+            if self.ly == LINES_PER_FRAME {
+                self.ly = 0;
+                self.window_line = 0;
+                self.window_triggered = false;
+            }
+            if self.ly == SCREEN_HEIGHT as u8 {
+                self.mode = Mode::VBlank;
+                self.frames += 1;
+                interrupts |= INTERRUPT_VBLANK;
+            } else if self.ly < SCREEN_HEIGHT as u8 {
+                self.start_line();
+            }
+        }
+        if self.update_stat_line() {
+            interrupts |= INTERRUPT_STAT;
+        }
+        interrupts
+    }
+
+    fn start_line(&mut self) {
+        self.mode = Mode::OamScan;
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+    }
+
+    /// Recompute the STAT interrupt line, returning true on a rising edge.
+    fn update_stat_line(&mut self) -> bool {
+        let line = (self.stat & STAT_LYC_INTERRUPT != 0 && self.ly == self.lyc)
+            || match self.mode {
+                Mode::HBlank => self.stat & STAT_HBLANK_INTERRUPT != 0,
+                Mode::VBlank => self.stat & STAT_VBLANK_INTERRUPT != 0,
+                Mode::OamScan => self.stat & STAT_OAM_INTERRUPT != 0,
+                Mode::Drawing => false,
+            };
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+
+    fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = value;
+        if was_enabled && !self.lcd_enabled() {
+            self.ly = 0;
+            self.dots = 0;
+            self.mode = Mode::HBlank;
+            self.stat_line = false;
+        } else if !was_enabled && self.lcd_enabled() {
+            self.window_line = 0;
+            self.window_triggered = false;
+            self.start_line();
+        }
+    }
+
+    /// The color number (0-3) of a pixel of the tile at `offset` into VRAM.
+    fn tile_pixel(&self, offset: usize, row: usize, column: u8) -> u8 {
+        let low = self.vram[offset + row * 2];
+        let high = self.vram[offset + row * 2 + 1];
+        let bit = 7 - column;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+
+    /// The color number and CGB attributes of a pixel in the 256x256 tile map
+    /// selected by `high_map`.
+    fn map_pixel(&self, high_map: bool, x: u8, y: u8) -> (u8, u8) {
+        let map_offset = if high_map { 0x1C00 } else { 0x1800 };
+        let index = map_offset + (y as usize / 8) * 32 + x as usize / 8;
+        let tile = self.vram[index];
+        let attributes = if self.cgb {
+            self.vram[VRAM_BANK_SIZE + index]
+        } else {
+            0
+        };
+
+        let mut row = y as usize % 8;
+        let mut column = x % 8;
+        if attributes & ATTR_Y_FLIP != 0 {
+            row = 7 - row;
+        }
+        if attributes & ATTR_X_FLIP != 0 {
+            column = 7 - column;
+        }
+        let bank_offset = if attributes & ATTR_BANK != 0 {
+            VRAM_BANK_SIZE
+        } else {
+            0
+        };
+        let tile_offset = if self.lcdc & LCDC_TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + tile as i8 as isize * 16) as usize
+        };
+        (
+            self.tile_pixel(bank_offset + tile_offset, row, column),
+            attributes,
+        )
+    }
+
+    fn render_line(&mut self) {
+        let y = self.ly as usize;
+        // color number and CGB attributes of each background/window pixel
+        let mut background = [(0u8, 0u8); SCREEN_WIDTH];
+        // on DMG bit 0 of LCDC turns off the background and window, on CGB it
+        // takes away their priority over sprites instead
+        let bg_enabled = self.cgb || self.lcdc & LCDC_BG_ENABLE != 0;
+
+        if bg_enabled {
+            let map_y = self.ly.wrapping_add(self.scy);
+            for (x, pixel) in background.iter_mut().enumerate() {
+                let map_x = (x as u8).wrapping_add(self.scx);
+                *pixel = self.map_pixel(self.lcdc & LCDC_BG_MAP != 0, map_x, map_y);
+            }
+
+            let window_x = self.wx as isize - 7;
+            if self.lcdc & LCDC_WINDOW_ENABLE != 0
+                && self.window_triggered
+                && window_x < SCREEN_WIDTH as isize
+            {
+                let start = window_x.max(0) as usize;
+                for (x, pixel) in background.iter_mut().enumerate().skip(start) {
+                    *pixel = self.map_pixel(
+                        self.lcdc & LCDC_WINDOW_MAP != 0,
+                        (x as isize - window_x) as u8,
+                        self.window_line,
+                    );
+                }
+                self.window_line += 1;
+            }
+        }
+
+        for (x, (color, attributes)) in background.iter().enumerate() {
+            let rgba = if !bg_enabled {
+                self.dmg_colors[0]
+            } else if self.cgb {
+                cgb_color(&self.bg_palettes, attributes & 0x07, *color)
+            } else {
+                self.dmg_colors[((self.bgp >> (color * 2)) & 0x03) as usize]
+            };
+            self.set_pixel(x, y, rgba);
+        }
+
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.render_sprites(&background);
+        }
+    }
+
+    fn render_sprites(&mut self, background: &[(u8, u8); SCREEN_WIDTH]) {
+        let height = if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        };
+        let line = self.ly as isize + 16;
+        let mut sprites = self
+            .oam
+            .chunks(4)
+            .filter(|sprite| {
+                let top = sprite[0] as isize;
+                line >= top && line < top + height
+            })
+            .take(SPRITES_PER_LINE)
+            .map(|sprite| [sprite[0], sprite[1], sprite[2], sprite[3]])
+            .collect::<Vec<_>>();
+        // on DMG the sprite furthest left wins, ties and all CGB sprites go by
+        // OAM order
+        if !self.cgb {
+            sprites.sort_by_key(|sprite| sprite[1]);
+        }
+
+        let mut claimed = [false; SCREEN_WIDTH];
+        for [top, left, mut tile, attributes] in sprites {
+            let mut row = (line - top as isize) as usize;
+            if attributes & ATTR_Y_FLIP != 0 {
+                row = height as usize - 1 - row;
+            }
+            if height == 16 {
+                tile &= 0xFE;
+            }
+            let bank_offset = if self.cgb && attributes & ATTR_BANK != 0 {
+                VRAM_BANK_SIZE
+            } else {
+                0
+            };
+
+            for column in 0..8 {
+                let x = left as isize - 8 + column as isize;
+                if x < 0 || x >= SCREEN_WIDTH as isize || claimed[x as usize] {
+                    continue;
+                }
+                let x = x as usize;
+                let tile_column = if attributes & ATTR_X_FLIP != 0 {
+                    7 - column
+                } else {
+                    column
+                };
+                let color = self.tile_pixel(bank_offset + tile as usize * 16, row, tile_column);
+                if color == 0 {
+                    continue;
+                }
+                claimed[x] = true;
+
+                let (bg_color, bg_attributes) = background[x];
+                let behind_background = if self.cgb && self.lcdc & LCDC_BG_ENABLE == 0 {
+                    false
+                } else {
+                    bg_color != 0
+                        && (attributes & ATTR_PRIORITY != 0 || bg_attributes & ATTR_PRIORITY != 0)
+                };
+                if behind_background {
+                    continue;
+                }
+
+                let rgba = if self.cgb {
+                    cgb_color(&self.obj_palettes, attributes & 0x07, color)
+                } else {
+                    let palette = if attributes & ATTR_DMG_PALETTE != 0 {
+                        self.obp1
+                    } else {
+                        self.obp0
+                    };
+                    self.dmg_colors[((palette >> (color * 2)) & 0x03) as usize]
+                };
+                self.set_pixel(x, self.ly as usize, rgba);
+            }
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        self.framebuffer[offset..offset + 4].copy_from_slice(&rgba);
+    }
+}
+
+/// Look up a color in CGB palette RAM and convert it to RGBA.
+fn cgb_color(palettes: &[u8; 64], palette: u8, color: u8) -> [u8; 4] {
+    let offset = palette as usize * 8 + color as usize * 2;
+    let bgr555 = u16::from_le_bytes([palettes[offset], palettes[offset + 1]]);
+    let channel = |shift: u16| {
+        let value = ((bgr555 >> shift) & 0x1F) as u8;
+        (value << 3) | (value >> 2)
+    };
+    [channel(0), channel(5), channel(10), 0xFF]
+}
+
+/// Access to CGB palette RAM through an index register (BCPS/OCPS) which can
+/// increment after each write.
+fn write_palette(palettes: &mut [u8; 64], index: &mut u8, value: u8) {
+    palettes[(*index & 0x3F) as usize] = value;
+    if *index & 0x80 != 0 {
+        *index = 0x80 | ((*index + 1) & 0x3F);
+    }
+}
+
+impl Bus for Ppu {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0x9FFF => {
+                self.vram[self.vbk as usize * VRAM_BANK_SIZE + (address as usize - 0x8000)]
+            }
+            0xFE00..=0xFE9F => self.oam[address as usize - 0xFE00],
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let mode = if self.lcd_enabled() {
+                    self.mode as u8
+                } else {
+                    0
+                };
+                let coincidence = if self.ly == self.lyc { 0x04 } else { 0 };
+                0x80 | self.stat | coincidence | mode
+            }
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            0xFF4F if self.cgb => 0xFE | self.vbk,
+            0xFF68 if self.cgb => 0x40 | self.bcps,
+            0xFF69 if self.cgb => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            0xFF6A if self.cgb => 0x40 | self.ocps,
+            0xFF6B if self.cgb => self.obj_palettes[(self.ocps & 0x3F) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => {
+                self.vram[self.vbk as usize * VRAM_BANK_SIZE + (address as usize - 0x8000)] = value
+            }
+            0xFE00..=0xFE9F => self.oam[address as usize - 0xFE00] = value,
+            0xFF40 => self.write_lcdc(value),
+            0xFF41 => self.stat = value & 0x78,
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF45 => self.lyc = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            0xFF4F if self.cgb => self.vbk = value & 0x01,
+            0xFF68 if self.cgb => self.bcps = value & 0xBF,
+            0xFF69 if self.cgb => write_palette(&mut self.bg_palettes, &mut self.bcps, value),
+            0xFF6A if self.cgb => self.ocps = value & 0xBF,
+            0xFF6B if self.cgb => write_palette(&mut self.obj_palettes, &mut self.ocps, value),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! The real time clock found in MBC3 cartridges.
+//!
+//! The clock is driven by a [`TimeSource`] so that tests can control the
+//! passage of time. State can be persisted in the 48 byte trailer that
+//! emulators such as VBA-M, BGB and mGBA append to `.sav` files.
+//!
+//! ```
+//! use gameboy_rom::rtc::{ManualTimeSource, Rtc};
+//!
+//! let time = ManualTimeSource::new(0);
+//! let mut rtc = Rtc::new(time.now());
+//! time.advance(60 * 60 * 24 * 512 + 61);
+//! rtc.sync(time.now());
+//! rtc.latch();
+//! // 512 days overflow the day counter and set the carry bit
+//! assert_eq!(rtc.read(0x0C), 0x80);
+//! assert_eq!(rtc.read(0x09), 1);
+//! assert_eq!(rtc.read(0x08), 1);
+//! ```
+
+pub use crate::save::{RTC_SAVE_SIZE, RTC_SAVE_SIZE_32};
+
+use serde::{Deserialize, Serialize};
+
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Somewhere to get the current time from, in seconds since the Unix epoch.
+pub trait TimeSource: fmt::Debug {
+    fn now(&self) -> u64;
+}
+
+/// The host's wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// A clock that only moves when told to, clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualTimeSource {
+    seconds: Rc<Cell<u64>>,
+}
+
+impl ManualTimeSource {
+    pub fn new(seconds: u64) -> Self {
+        Self {
+            seconds: Rc::new(Cell::new(seconds)),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.seconds.get()
+    }
+
+    pub fn set(&self, seconds: u64) {
+        self.seconds.set(seconds);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.seconds.set(self.seconds.get() + seconds);
+    }
+}
+
+impl TimeSource for ManualTimeSource {
+    fn now(&self) -> u64 {
+        ManualTimeSource::now(self)
+    }
+}
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// Bit 6 of the DH register stops the clock.
+const DH_HALT: u8 = 0x40;
+/// Bit 7 of the DH register is set when the day counter overflows.
+const DH_DAY_CARRY: u8 = 0x80;
+
+/// Errors that may occur while loading RTC state.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RtcSaveError {
+    /// The trailer must be 44 or 48 bytes long.
+    InvalidLength(usize),
+}
+
+/// The MBC3 real time clock.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    /// The 9 bit day counter.
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    /// The register values visible to the CPU, in register order: S, M, H,
+    /// DL and DH.
+    latched: [u8; 5],
+    /// The last value written to the latch register.
+    latch_write: u8,
+    /// When the clock was last brought up to date.
+    last_sync: u64,
+}
+
+impl Rtc {
+    /// Create a clock reading zero at time `now`.
+    pub fn new(now: u64) -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_write: 0xFF,
+            last_sync: now,
+        }
+    }
+
+    /// Advance the clock to time `now`, unless it's halted.
+    pub fn sync(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.last_sync);
+        self.last_sync = now;
+        if !self.halted {
+            self.advance(elapsed);
+        }
+    }
+
+    /// Copy the current time into the registers visible to the CPU.
+    pub fn latch(&mut self) {
+        self.latched = self.registers();
+    }
+
+    /// Handle a write to the latch register at 0x6000-0x7FFF, writing 0 then 1
+    /// latches the clock.
+    pub fn write_latch(&mut self, value: u8, now: u64) {
+        if self.latch_write == 0x00 && value == 0x01 {
+            self.sync(now);
+            self.latch();
+        }
+        self.latch_write = value;
+    }
+
+    /// Read the latched value of a register, `register` is the value written
+    /// to 0x4000-0x5FFF to select it (0x08-0x0C).
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08..=0x0C => self.latched[register as usize - 0x08],
+            _ => 0xFF,
+        }
+    }
+
+    /// Write to a register, `register` is the value written to 0x4000-0x5FFF
+    /// to select it (0x08-0x0C).
+    pub fn write(&mut self, register: u8, value: u8, now: u64) {
+        self.sync(now);
+        match register {
+            0x08 => self.seconds = value & 0x3F,
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((value as u16 & 0x01) << 8);
+                self.halted = value & DH_HALT != 0;
+                self.day_carry = value & DH_DAY_CARRY != 0;
+            }
+            _ => return,
+        }
+        self.latched[register as usize - 0x08] = self.registers()[register as usize - 0x08];
+    }
+
+    /// The current (unlatched) register values: S, M, H, DL and DH.
+    pub fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            (self.days >> 8) as u8
+                | if self.halted { DH_HALT } else { 0 }
+                | if self.day_carry { DH_DAY_CARRY } else { 0 },
+        ]
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        // registers written with out of range values count up to their bit
+        // width before wrapping without carrying, so step through those
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            seconds -= 1;
+        }
+        let total = seconds
+            + self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 60 * 60
+            + self.days as u64 * SECONDS_PER_DAY;
+        let days = total / SECONDS_PER_DAY;
+        if days >= 512 {
+            self.day_carry = true;
+        }
+        self.days = (days % 512) as u16;
+        self.hours = (total % SECONDS_PER_DAY / (60 * 60)) as u8;
+        self.minutes = (total % (60 * 60) / 60) as u8;
+        self.seconds = (total % 60) as u8;
+    }
+
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days == 512 {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+
+    /// Serialize the clock as the 48 byte `.sav` trailer.
+    ///
+    /// The trailer holds the current and latched registers as little endian
+    /// 32 bit values followed by a 64 bit Unix timestamp.
+    pub fn to_save_bytes(&self) -> [u8; RTC_SAVE_SIZE] {
+        let mut out = [0; RTC_SAVE_SIZE];
+        for (idx, value) in self
+            .registers()
+            .iter()
+            .chain(self.latched.iter())
+            .enumerate()
+        {
+            out[idx * 4..idx * 4 + 4].copy_from_slice(&(*value as u32).to_le_bytes());
+        }
+        out[40..48].copy_from_slice(&self.last_sync.to_le_bytes());
+        out
+    }
+
+    /// Load a clock from a 48 or 44 byte `.sav` trailer.
+    ///
+    /// Call `sync` afterwards to account for the time passed since the save
+    /// was written.
+    pub fn from_save_bytes(bytes: &[u8]) -> Result<Self, RtcSaveError> {
+        let last_sync = match bytes.len() {
+            RTC_SAVE_SIZE => u64::from_le_bytes([
+                bytes[40], bytes[41], bytes[42], bytes[43], bytes[44], bytes[45], bytes[46],
+                bytes[47],
+            ]),
+            RTC_SAVE_SIZE_32 => {
+                u32::from_le_bytes([bytes[40], bytes[41], bytes[42], bytes[43]]) as u64
+            }
+            otherwise => return Err(RtcSaveError::InvalidLength(otherwise)),
+        };
+        let register = |idx: usize| bytes[idx * 4];
+
+        let mut rtc = Self::new(last_sync);
+        rtc.seconds = register(0) & 0x3F;
+        rtc.minutes = register(1) & 0x3F;
+        rtc.hours = register(2) & 0x1F;
+        rtc.days = register(3) as u16 | ((register(4) as u16 & 0x01) << 8);
+        rtc.halted = register(4) & DH_HALT != 0;
+        rtc.day_carry = register(4) & DH_DAY_CARRY != 0;
+        for (idx, latched) in rtc.latched.iter_mut().enumerate() {
+            *latched = register(5 + idx);
+        }
+        Ok(rtc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+
+    /// Sync and latch the clock, returning the latched registers.
+    fn latched(rtc: &mut Rtc, time: &ManualTimeSource) -> [u8; 5] {
+        rtc.sync(time.now());
+        rtc.latch();
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|register| rtc.read(register))
+    }
+
+    #[test]
+    fn counts_up() {
+        let time = ManualTimeSource::new(1_000_000);
+        let mut rtc = Rtc::new(time.now());
+        time.advance(DAY * 3 + 60 * 60 * 4 + 60 * 5 + 6);
+        assert_eq!(latched(&mut rtc, &time), [6, 5, 4, 3, 0]);
+        time.advance(DAY * 256);
+        assert_eq!(latched(&mut rtc, &time), [6, 5, 4, 3, 1]);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let time = ManualTimeSource::new(0);
+        let mut rtc = Rtc::new(time.now());
+        time.advance(10);
+        rtc.write(0x0C, DH_HALT, time.now());
+        time.advance(DAY);
+        assert_eq!(latched(&mut rtc, &time), [10, 0, 0, 0, DH_HALT]);
+
+        // registers can be set while halted and count on from there
+        rtc.write(0x08, 30, time.now());
+        time.advance(DAY);
+        rtc.write(0x0C, 0, time.now());
+        assert_eq!(latched(&mut rtc, &time), [30, 0, 0, 0, 0]);
+        time.advance(5);
+        assert_eq!(latched(&mut rtc, &time), [35, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn latches_on_0_then_1() {
+        let time = ManualTimeSource::new(0);
+        let mut rtc = Rtc::new(time.now());
+        time.advance(7);
+        // the initial latch register value isn't 0
+        rtc.write_latch(0x01, time.now());
+        assert_eq!(rtc.read(0x08), 0);
+
+        rtc.write_latch(0x00, time.now());
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x01, time.now());
+        assert_eq!(rtc.read(0x08), 7);
+
+        // the latched value holds while the clock runs
+        time.advance(3);
+        rtc.sync(time.now());
+        assert_eq!(rtc.read(0x08), 7);
+        assert_eq!(rtc.registers()[0], 10);
+        // writing 1 again doesn't latch, nor does anything but 0 then 1
+        rtc.write_latch(0x01, time.now());
+        assert_eq!(rtc.read(0x08), 7);
+        rtc.write_latch(0x00, time.now());
+        rtc.write_latch(0x02, time.now());
+        rtc.write_latch(0x01, time.now());
+        assert_eq!(rtc.read(0x08), 7);
+        rtc.write_latch(0x00, time.now());
+        rtc.write_latch(0x01, time.now());
+        assert_eq!(rtc.read(0x08), 10);
+    }
+
+    #[test]
+    fn day_carry_is_kept() {
+        let time = ManualTimeSource::new(0);
+        let mut rtc = Rtc::new(time.now());
+        rtc.write(0x0B, 0xFF, time.now());
+        rtc.write(0x0C, 0x01, time.now());
+        time.advance(DAY - 1);
+        assert_eq!(latched(&mut rtc, &time), [59, 59, 23, 0xFF, 0x01]);
+        time.advance(1);
+        assert_eq!(latched(&mut rtc, &time), [0, 0, 0, 0, DH_DAY_CARRY]);
+        // the carry stays set as the days count on
+        time.advance(DAY * 600);
+        assert_eq!(latched(&mut rtc, &time), [0, 0, 0, 88, DH_DAY_CARRY]);
+        // until it's cleared by a write
+        rtc.write(0x0C, 0, time.now());
+        assert_eq!(latched(&mut rtc, &time), [0, 0, 0, 88, 0]);
+    }
+
+    #[test]
+    fn save_trailer_round_trip() {
+        let time = ManualTimeSource::new(1_600_000_000);
+        let mut rtc = Rtc::new(time.now());
+        time.advance(DAY * 300 + 61);
+        rtc.sync(time.now());
+        rtc.latch();
+        time.advance(DAY * 300);
+        rtc.sync(time.now());
+
+        let bytes = rtc.to_save_bytes();
+        assert_eq!(bytes.len(), 48);
+        // current registers, then the latched ones, as 32 bit values
+        assert_eq!(&bytes[0..4], &[1, 0, 0, 0]);
+        assert_eq!(
+            &bytes[12..20],
+            &[(600 - 512) as u8, 0, 0, 0, DH_DAY_CARRY, 0, 0, 0]
+        );
+        assert_eq!(&bytes[20..24], &[1, 0, 0, 0]);
+        assert_eq!(&bytes[32..40], &[(300 - 256) as u8, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(&bytes[40..48], &time.now().to_le_bytes());
+        assert_eq!(Rtc::from_save_bytes(&bytes), Ok(rtc.clone()));
+
+        // the 44 byte trailer has a 32 bit timestamp
+        let short = &bytes[..44];
+        let mut loaded = Rtc::from_save_bytes(short).unwrap();
+        assert_eq!(loaded, rtc);
+        time.advance(5);
+        loaded.sync(time.now());
+        loaded.latch();
+        assert_eq!(loaded.read(0x08), 6);
+
+        for len in [0, 43, 45, 47, 49].iter() {
+            assert_eq!(
+                Rtc::from_save_bytes(&vec![0; *len]),
+                Err(RtcSaveError::InvalidLength(*len))
+            );
+        }
+    }
+}
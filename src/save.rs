@@ -0,0 +1,359 @@
+//! Battery backed save (`.sav`) files.
+//!
+//! A `.sav` file is a dump of the cartridge's external RAM, optionally
+//! followed by a real time clock trailer for MBC3 cartridges with a timer.
+//! The expected size comes from the header, except for MBC2 which has 512
+//! 4 bit values built into the MBC and stores them one per byte.
+//!
+//! Saves written by other emulators are often a little off, so loading one
+//! pads or truncates it to the right size and reports what was changed.
+//!
+//! ```
+//! use gameboy_rom::save;
+//! # fn example(header: &gameboy_rom::RomHeader) -> Result<(), save::SaveError> {
+//! let save = save::load_save(header, &[0; 0x1000])?;
+//! for warning in &save.warnings {
+//!     eprintln!("{}", warning);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::header::{RomHeader, RomType};
+
+use std::fmt;
+
+/// The size in bytes of the RTC trailer appended to `.sav` files.
+pub const RTC_SAVE_SIZE: usize = 48;
+/// The size in bytes of the older RTC trailer which has a 32 bit timestamp.
+pub const RTC_SAVE_SIZE_32: usize = 44;
+/// The size in bytes of MBC2's built in RAM as stored in a `.sav` file.
+pub const MBC2_SAVE_SIZE: usize = 512;
+
+/// Whether the cartridge keeps its RAM (or clock) powered when switched off.
+pub fn has_battery(rom_type: RomType) -> bool {
+    matches!(
+        rom_type,
+        RomType::Mbc1RamBattery
+            | RomType::Mbc2Battery
+            | RomType::RomRamBattery
+            | RomType::Mmm01SramBattery
+            | RomType::Mbc3TimerBattery
+            | RomType::Mbc3TimerRamBattery
+            | RomType::Mbc3RamBattery
+            | RomType::Mbc5RamBattery
+            | RomType::Mbc5RumbleSramBattery
+            | RomType::Huc1
+            | RomType::Huc3
+    )
+}
+
+/// Whether the cartridge has an MBC3 real time clock.
+pub fn has_rtc(rom_type: RomType) -> bool {
+    matches!(
+        rom_type,
+        RomType::Mbc3TimerBattery | RomType::Mbc3TimerRamBattery
+    )
+}
+
+/// The size in bytes of the cartridge's save RAM, not including any RTC
+/// trailer.
+pub fn sram_size(header: &RomHeader) -> usize {
+    match header.rom_type {
+        RomType::Mbc2 | RomType::Mbc2Battery => MBC2_SAVE_SIZE,
+        _ => header.ram_banks as usize * header.ram_bank_size as usize,
+    }
+}
+
+/// Create zeroed save RAM of the right size for the cartridge.
+pub fn blank_sram(header: &RomHeader) -> Vec<u8> {
+    vec![0; sram_size(header)]
+}
+
+/// Errors that may occur while loading a save.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SaveError {
+    /// The cartridge has neither RAM nor a clock to load the save into.
+    NoSaveData(RomType),
+}
+
+/// Adjustments made to a save while loading it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveWarning {
+    /// The save was smaller than the cartridge's RAM and was padded with
+    /// zeros.
+    Padded { expected: usize, actual: usize },
+    /// The save was larger than the cartridge's RAM and the extra bytes were
+    /// dropped.
+    Truncated { expected: usize, actual: usize },
+    /// The cartridge has a clock but the save has no RTC trailer.
+    MissingRtc,
+    /// The save has an RTC trailer but the cartridge has no clock, it was
+    /// dropped.
+    UnexpectedRtc,
+    /// MBC2 RAM only stores the low 4 bits of each byte, this many bytes had
+    /// upper bits set which were cleared. Many emulators set the upper bits
+    /// of every byte, so this is only reported when some bytes have them set
+    /// and others don't.
+    Mbc2UpperBits(usize),
+    /// The cartridge has no battery so the game won't expect saved data.
+    NoBattery,
+}
+
+impl fmt::Display for SaveWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveWarning::Padded { expected, actual } => write!(
+                f,
+                "save is {} bytes but the cartridge has {} bytes of RAM, padded with zeros",
+                actual, expected
+            ),
+            SaveWarning::Truncated { expected, actual } => write!(
+                f,
+                "save is {} bytes but the cartridge has {} bytes of RAM, truncated",
+                actual, expected
+            ),
+            SaveWarning::MissingRtc => write!(f, "save has no RTC data, the clock will be reset"),
+            SaveWarning::UnexpectedRtc => {
+                write!(f, "save has RTC data but the cartridge has no clock")
+            }
+            SaveWarning::Mbc2UpperBits(count) => {
+                write!(f, "cleared the upper 4 bits of {} MBC2 RAM bytes", count)
+            }
+            SaveWarning::NoBattery => write!(f, "the cartridge has no battery"),
+        }
+    }
+}
+
+/// A save file split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveFile {
+    /// The cartridge's RAM, exactly `sram_size` bytes.
+    pub ram: Vec<u8>,
+    /// The raw RTC trailer, either `RTC_SAVE_SIZE` or `RTC_SAVE_SIZE_32`
+    /// bytes.
+    pub rtc: Option<Vec<u8>>,
+    /// Anything that was changed to make the save fit the cartridge.
+    pub warnings: Vec<SaveWarning>,
+}
+
+/// Check a `.sav` file against the header and make it fit the cartridge.
+pub fn load_save(header: &RomHeader, bytes: &[u8]) -> Result<SaveFile, SaveError> {
+    let expected = sram_size(header);
+    let rtc_expected = has_rtc(header.rom_type);
+    if expected == 0 && !rtc_expected {
+        return Err(SaveError::NoSaveData(header.rom_type));
+    }
+
+    let mut warnings = vec![];
+    if !has_battery(header.rom_type) {
+        warnings.push(SaveWarning::NoBattery);
+    }
+
+    let trailer_size = [RTC_SAVE_SIZE, RTC_SAVE_SIZE_32]
+        .iter()
+        .copied()
+        .find(|size| bytes.len() == expected + size);
+    let (ram, rtc) = match trailer_size {
+        Some(size) => {
+            let (ram, rtc) = bytes.split_at(bytes.len() - size);
+            if rtc_expected {
+                (ram, Some(rtc.to_vec()))
+            } else {
+                warnings.push(SaveWarning::UnexpectedRtc);
+                (ram, None)
+            }
+        }
+        None => {
+            if rtc_expected {
+                warnings.push(SaveWarning::MissingRtc);
+            }
+            (bytes, None)
+        }
+    };
+
+    let mut ram = ram.to_vec();
+    if let RomType::Mbc2 | RomType::Mbc2Battery = header.rom_type {
+        let upper_bits = ram.iter().filter(|byte| *byte & 0xF0 != 0).count();
+        if upper_bits > 0 && ram.iter().any(|byte| *byte & 0xF0 != 0xF0) {
+            warnings.push(SaveWarning::Mbc2UpperBits(upper_bits));
+        }
+        ram.iter_mut().for_each(|byte| *byte &= 0x0F);
+    }
+    if ram.len() < expected {
+        warnings.push(SaveWarning::Padded {
+            expected,
+            actual: ram.len(),
+        });
+        ram.resize(expected, 0);
+    } else if ram.len() > expected {
+        warnings.push(SaveWarning::Truncated {
+            expected,
+            actual: ram.len(),
+        });
+        ram.truncate(expected);
+    }
+
+    Ok(SaveFile { ram, rtc, warnings })
+}
+
+/// Build a `.sav` file from the cartridge's RAM and an optional RTC trailer.
+pub fn create_save(ram: &[u8], rtc: Option<&[u8]>) -> Vec<u8> {
+    let mut out = ram.to_vec();
+    if let Some(rtc) = rtc {
+        out.extend_from_slice(rtc);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameBoyRom;
+
+    /// A ROM with the given cartridge type and RAM size bytes.
+    fn rom(cartridge_type: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_size;
+        rom
+    }
+
+    fn load(rom: &[u8], bytes: &[u8]) -> Result<SaveFile, SaveError> {
+        load_save(&GameBoyRom::new(rom).parse_header().unwrap(), bytes)
+    }
+
+    #[test]
+    fn exact_size() {
+        // MBC1+RAM+BATTERY with 8KB
+        let rom = rom(0x03, 0x02);
+        let save = load(&rom, &[0x55; 0x2000]).unwrap();
+        assert_eq!(save.ram, vec![0x55; 0x2000]);
+        assert_eq!(save.rtc, None);
+        assert!(save.warnings.is_empty());
+    }
+
+    #[test]
+    fn padded_and_truncated() {
+        let rom = rom(0x03, 0x02);
+        let save = load(&rom, &[0x55; 0x1000]).unwrap();
+        assert_eq!(
+            save.warnings,
+            vec![SaveWarning::Padded {
+                expected: 0x2000,
+                actual: 0x1000
+            }]
+        );
+        assert_eq!(&save.ram[..0x1000], &[0x55; 0x1000][..]);
+        assert_eq!(&save.ram[0x1000..], &[0; 0x1000][..]);
+
+        let save = load(&rom, &[0x55; 0x2001]).unwrap();
+        assert_eq!(
+            save.warnings,
+            vec![SaveWarning::Truncated {
+                expected: 0x2000,
+                actual: 0x2001
+            }]
+        );
+        assert_eq!(save.ram, vec![0x55; 0x2000]);
+    }
+
+    #[test]
+    fn rtc_trailers() {
+        // MBC3+TIMER+RAM+BATTERY with 8KB
+        let with_ram = rom(0x10, 0x02);
+        for size in [RTC_SAVE_SIZE, RTC_SAVE_SIZE_32].iter() {
+            let mut bytes = vec![0x55; 0x2000];
+            bytes.extend(vec![0xAA; *size]);
+            let save = load(&with_ram, &bytes).unwrap();
+            assert_eq!(save.ram, vec![0x55; 0x2000]);
+            assert_eq!(save.rtc, Some(vec![0xAA; *size]));
+            assert!(save.warnings.is_empty());
+            assert_eq!(create_save(&save.ram, save.rtc.as_deref()), bytes);
+        }
+
+        // MBC3+TIMER+BATTERY has no RAM, only the clock
+        let clock_only = rom(0x0F, 0x00);
+        let save = load(&clock_only, &[0xAA; RTC_SAVE_SIZE]).unwrap();
+        assert!(save.ram.is_empty());
+        assert_eq!(save.rtc, Some(vec![0xAA; RTC_SAVE_SIZE]));
+    }
+
+    #[test]
+    fn missing_rtc() {
+        let rom = rom(0x10, 0x02);
+        let save = load(&rom, &[0x55; 0x2000]).unwrap();
+        assert_eq!(save.rtc, None);
+        assert_eq!(save.warnings, vec![SaveWarning::MissingRtc]);
+
+        // a trailer of the wrong size is treated as RAM
+        let save = load(&rom, &[0x55; 0x2000 + 40]).unwrap();
+        assert_eq!(
+            save.warnings,
+            vec![
+                SaveWarning::MissingRtc,
+                SaveWarning::Truncated {
+                    expected: 0x2000,
+                    actual: 0x2000 + 40
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn unexpected_rtc() {
+        let rom = rom(0x03, 0x02);
+        let mut bytes = vec![0x55; 0x2000];
+        bytes.extend(vec![0xAA; RTC_SAVE_SIZE]);
+        let save = load(&rom, &bytes).unwrap();
+        assert_eq!(save.ram, vec![0x55; 0x2000]);
+        assert_eq!(save.rtc, None);
+        assert_eq!(save.warnings, vec![SaveWarning::UnexpectedRtc]);
+    }
+
+    #[test]
+    fn mbc2() {
+        // MBC2+BATTERY, the header declares no RAM
+        let rom = rom(0x06, 0x00);
+        let save = load(&rom, &[0x0A; MBC2_SAVE_SIZE]).unwrap();
+        assert_eq!(save.ram, vec![0x0A; MBC2_SAVE_SIZE]);
+        assert!(save.warnings.is_empty());
+
+        // upper bits set on every byte are cleared silently
+        let save = load(&rom, &[0xFA; MBC2_SAVE_SIZE]).unwrap();
+        assert_eq!(save.ram, vec![0x0A; MBC2_SAVE_SIZE]);
+        assert!(save.warnings.is_empty());
+
+        let mut bytes = vec![0x0A; MBC2_SAVE_SIZE];
+        bytes[0] = 0xFA;
+        bytes[1] = 0x3A;
+        let save = load(&rom, &bytes).unwrap();
+        assert_eq!(save.ram, vec![0x0A; MBC2_SAVE_SIZE]);
+        assert_eq!(save.warnings, vec![SaveWarning::Mbc2UpperBits(2)]);
+
+        // padding doesn't count as bytes with the upper bits clear
+        let save = load(&rom, &[0xFA; 256]).unwrap();
+        assert_eq!(
+            save.warnings,
+            vec![SaveWarning::Padded {
+                expected: MBC2_SAVE_SIZE,
+                actual: 256
+            }]
+        );
+    }
+
+    #[test]
+    fn no_save_data() {
+        assert_eq!(
+            load(&rom(0x01, 0x00), &[]),
+            Err(SaveError::NoSaveData(RomType::Mbc1))
+        );
+    }
+
+    #[test]
+    fn no_battery() {
+        // MBC1+RAM
+        let save = load(&rom(0x02, 0x02), &[0; 0x2000]).unwrap();
+        assert_eq!(save.warnings, vec![SaveWarning::NoBattery]);
+    }
+}
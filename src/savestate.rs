@@ -0,0 +1,203 @@
+//! Save states, snapshots of a whole [`Machine`] that it can be restored to.
+//!
+//! A save state is the 8 byte [`MAGIC`], the format version as a little
+//! endian `u16`, the CRC32 of the ROM as a little endian `u32` and then the
+//! machine encoded with `bincode`. The ROM isn't included, a state can only be
+//! loaded into a machine running the same ROM.
+//! What the host configured, such as breakpoints, the serial peer and the
+//! audio sample rate, is kept when a state is loaded.
+//!
+//! ```
+//! use gameboy_rom::machine::Machine;
+//! use gameboy_rom::{savestate, GameBoyRom};
+//!
+//! let rom = vec![0; 0x8000];
+//! let mut machine = Machine::new(&GameBoyRom::new(&rom)).unwrap();
+//! let state = savestate::save(&machine);
+//! machine.run_frames(10);
+//! savestate::load(&mut machine, &state).unwrap();
+//! assert_eq!(machine.cycles(), 0);
+//! ```
+
+use crate::cartridge::CartridgeError;
+use crate::machine::Machine;
+
+/// The start of every save state.
+pub const MAGIC: [u8; 8] = *b"GBRSTATE";
+/// The version of the format written by `save`.
+pub const VERSION: u16 = 1;
+
+/// Converts the payload of one version of the format to the next.
+type Migration = fn(Vec<u8>) -> Result<Vec<u8>, SaveStateError>;
+
+/// `MIGRATIONS[n]` converts version `n + 1` to version `n + 2`. When the
+/// encoding of anything in a `Machine` changes, bump `VERSION` and add a
+/// migration here so older states can still be loaded.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Errors that may occur while loading a save state.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+    /// The data doesn't start with `MAGIC`.
+    NotASaveState,
+    /// The state was written by a newer version of the format.
+    UnsupportedVersion(u16),
+    /// The state was saved by a machine running a different ROM.
+    WrongRom,
+    /// The state couldn't be decoded.
+    Corrupt(String),
+    /// The cartridge state doesn't match the machine's cartridge.
+    Cartridge(CartridgeError),
+}
+
+impl From<CartridgeError> for SaveStateError {
+    fn from(error: CartridgeError) -> Self {
+        SaveStateError::Cartridge(error)
+    }
+}
+
+/// Snapshot the machine.
+pub fn save(machine: &Machine) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&machine.rom_crc32().to_le_bytes());
+    bincode::serialize_into(&mut bytes, &(machine, machine.bus.cartridge.save_state()))
+        .expect("machine state can always be encoded");
+    bytes
+}
+
+/// Restore the machine to a snapshot taken by `save`, migrating it from older
+/// versions of the format. The machine is unchanged if this fails.
+pub fn load(machine: &mut Machine, bytes: &[u8]) -> Result<(), SaveStateError> {
+    let header_size = MAGIC.len() + 2 + 4;
+    if bytes.len() < header_size || bytes[..MAGIC.len()] != MAGIC {
+        return Err(SaveStateError::NotASaveState);
+    }
+    let version = u16::from_le_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+    let payload = migrate(version, bytes[header_size..].to_vec())?;
+    let rom_crc32 = u32::from_le_bytes([
+        bytes[MAGIC.len() + 2],
+        bytes[MAGIC.len() + 3],
+        bytes[MAGIC.len() + 4],
+        bytes[MAGIC.len() + 5],
+    ]);
+    if rom_crc32 != machine.rom_crc32 {
+        return Err(SaveStateError::WrongRom);
+    }
+    let (mut state, cartridge): (Machine, Vec<u8>) =
+        bincode::deserialize(&payload).map_err(|e| SaveStateError::Corrupt(e.to_string()))?;
+    machine.bus.cartridge.load_state(&cartridge)?;
+
+    std::mem::swap(&mut state.bus.cartridge, &mut machine.bus.cartridge);
+    state.breakpoints = std::mem::take(&mut machine.breakpoints);
+    state.break_on_ld_b_b = machine.break_on_ld_b_b;
+    state.rom_crc32 = machine.rom_crc32;
+    state.bus.serial.connect(machine.bus.serial.disconnect());
+    if state.bus.apu.sample_rate() != machine.bus.apu.sample_rate() {
+        state.bus.apu.set_sample_rate(machine.bus.apu.sample_rate());
+    }
+    state.bus.ppu.dmg_colors = machine.bus.ppu.dmg_colors;
+    *machine = state;
+    Ok(())
+}
+
+/// Bring a payload of the given version up to `VERSION`.
+fn migrate(version: u16, mut payload: Vec<u8>) -> Result<Vec<u8>, SaveStateError> {
+    if version == 0 || version > VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        payload = migration(payload)?;
+    }
+    Ok(payload)
+}
+
+/// Encodes byte arrays too long for serde's built-in array support.
+pub(crate) mod byte_array {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::convert::TryInto;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        array: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(array)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"a fixed size byte array"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameBoyRom;
+
+    /// A ROM that keeps counting in A and storing it in WRAM, with `id` in an
+    /// unused byte.
+    fn counter_rom(id: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x14F] = id;
+        rom[0x150..0x155].copy_from_slice(&[
+            0x3C, // inc a
+            0xEA, 0x00, 0xC0, // ld (0xC000), a
+            0x18, // jr 0x150
+        ]);
+        rom[0x155] = 0xFA;
+        rom
+    }
+
+    #[test]
+    fn save_run_load() {
+        let rom = counter_rom(0);
+        let mut machine = Machine::new(&GameBoyRom::new(&rom)).unwrap();
+        machine.run_frames(3);
+        let state = save(&machine);
+
+        machine.run_frames(5);
+        let after_run = save(&machine);
+        assert_ne!(state, after_run);
+
+        load(&mut machine, &state).unwrap();
+        assert_eq!(save(&machine), state);
+        machine.run_frames(5);
+        assert_eq!(save(&machine), after_run);
+    }
+
+    #[test]
+    fn wrong_rom() {
+        let first = Machine::new(&GameBoyRom::new(&counter_rom(0))).unwrap();
+        let mut second = Machine::new(&GameBoyRom::new(&counter_rom(1))).unwrap();
+        second.run_frames(1);
+        let before = save(&second);
+        assert_eq!(
+            load(&mut second, &save(&first)),
+            Err(SaveStateError::WrongRom)
+        );
+        assert_eq!(save(&second), before);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut machine = Machine::new(&GameBoyRom::new(&counter_rom(0))).unwrap();
+        let mut state = save(&machine);
+        assert_eq!(
+            load(&mut machine, &state[..MAGIC.len() + 2]),
+            Err(SaveStateError::NotASaveState)
+        );
+        state[MAGIC.len()] = 2;
+        assert_eq!(
+            load(&mut machine, &state),
+            Err(SaveStateError::UnsupportedVersion(2))
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! The serial port (SB and SC) and what's plugged into it.
+//!
+//! A transfer shifts the 8 bits of SB out while shifting 8 bits in from the
+//! other end. The side using its internal clock drives the transfer; the
+//! other side sets SC bit 7 with the external clock selected and waits. What's
+//! on the other end of the cable is a [`SerialPeer`].
+//!
+//! ```
+//! use gameboy_rom::cpu::Bus;
+//! use gameboy_rom::serial::{Loopback, SerialController};
+//!
+//! let mut serial = SerialController::new(false);
+//! serial.connect(Box::new(Loopback));
+//! serial.write(0xFF01, b'A');
+//! serial.write(0xFF02, 0x81);
+//! serial.step(4096);
+//! assert_eq!(serial.read(0xFF01), b'A');
+//! assert_eq!(serial.output(), b"A");
+//! ```
+
+use crate::cpu::Bus;
+use crate::interrupts::INTERRUPT_SERIAL;
+
+use serde::{Deserialize, Serialize};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// T-cycles to transfer a byte with the 8192 Hz internal clock.
+const TRANSFER_CYCLES: u32 = 4096;
+/// T-cycles to transfer a byte with the CGB's 262144 Hz internal clock.
+const FAST_TRANSFER_CYCLES: u32 = 128;
+
+/// Whatever is connected to the other end of the link cable.
+pub trait SerialPeer: fmt::Debug {
+    /// Our internal clock shifted `byte` out, return the byte shifted in.
+    fn exchange(&mut self, byte: u8) -> u8;
+
+    /// We're waiting for the other end to clock a transfer, with `byte` in
+    /// SB.
+    fn listen(&mut self, _byte: u8) {}
+
+    /// The byte shifted in, if the other end clocked a transfer since
+    /// `listen` was called.
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Nothing plugged in, every bit shifted in is 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Disconnected;
+
+impl SerialPeer for Disconnected {
+    fn exchange(&mut self, _byte: u8) -> u8 {
+        0xFF
+    }
+}
+
+/// The output wired to the input, every byte sent comes straight back.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Loopback;
+
+impl SerialPeer for Loopback {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        byte
+    }
+}
+
+#[derive(Debug, Default)]
+struct LinkSide {
+    /// The byte in SB while waiting on the external clock.
+    listening: Option<u8>,
+    /// The byte received by a side that was listening.
+    received: Option<u8>,
+}
+
+/// One end of a link cable.
+#[derive(Debug)]
+pub struct LinkEnd {
+    sides: Rc<RefCell<[LinkSide; 2]>>,
+    side: usize,
+}
+
+impl SerialPeer for LinkEnd {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let mut sides = self.sides.borrow_mut();
+        let other = &mut sides[1 - self.side];
+        match other.listening.take() {
+            Some(theirs) => {
+                other.received = Some(byte);
+                theirs
+            }
+            // the other end isn't ready, its shift register isn't clocked
+            None => 0xFF,
+        }
+    }
+
+    fn listen(&mut self, byte: u8) {
+        let mut sides = self.sides.borrow_mut();
+        sides[self.side] = LinkSide {
+            listening: Some(byte),
+            received: None,
+        };
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.sides.borrow_mut()[self.side].received.take()
+    }
+}
+
+impl LinkEnd {
+    /// Create the two ends of a link cable connecting two serial ports in the
+    /// same process, e.g. two `Machine`s.
+    pub fn pair() -> (LinkEnd, LinkEnd) {
+        let sides = Rc::new(RefCell::new([LinkSide::default(), LinkSide::default()]));
+        (
+            LinkEnd {
+                sides: sides.clone(),
+                side: 0,
+            },
+            LinkEnd { sides, side: 1 },
+        )
+    }
+}
+
+/// The SB and SC registers.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SerialController {
+    cgb: bool,
+    sb: u8,
+    sc: u8,
+    /// T-cycles left in a transfer using the internal clock.
+    remaining: Option<u32>,
+    /// Not part of save states, what's plugged in stays plugged in.
+    #[serde(skip, default = "disconnected")]
+    peer: Box<dyn SerialPeer>,
+    output: Vec<u8>,
+}
+
+impl SerialController {
+    /// Create a serial port with nothing plugged in, `cgb` enables the fast
+    /// clock.
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            cgb,
+            sb: 0,
+            sc: 0,
+            remaining: None,
+            peer: disconnected(),
+            output: vec![],
+        }
+    }
+
+    /// Plug something into the serial port.
+    pub fn connect(&mut self, peer: Box<dyn SerialPeer>) {
+        self.peer = peer;
+    }
+
+    /// Unplug whatever is plugged into the serial port.
+    pub fn disconnect(&mut self) -> Box<dyn SerialPeer> {
+        std::mem::replace(&mut self.peer, disconnected())
+    }
+
+    /// Every byte sent out of the serial port so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Run for the given number of T-cycles, returning the interrupts requested
+    /// as IF bits.
+    pub fn step(&mut self, cycles: u32) -> u8 {
+        if self.sc & 0x80 == 0 {
+            return 0;
+        }
+        match self.remaining {
+            Some(remaining) if remaining > cycles => {
+                self.remaining = Some(remaining - cycles);
+                0
+            }
+            Some(_) => {
+                let received = self.peer.exchange(self.sb);
+                self.finish(received)
+            }
+            None => match self.peer.poll() {
+                Some(received) => {
+                    self.output.push(self.sb);
+                    self.finish(received)
+                }
+                None => 0,
+            },
+        }
+    }
+
+    fn finish(&mut self, received: u8) -> u8 {
+        self.sb = received;
+        self.sc &= 0x7F;
+        self.remaining = None;
+        INTERRUPT_SERIAL
+    }
+
+    fn write_sc(&mut self, value: u8) {
+        self.sc = value & if self.cgb { 0x83 } else { 0x81 };
+        if value & 0x80 == 0 {
+            self.remaining = None;
+            return;
+        }
+        if value & 0x01 != 0 {
+            self.output.push(self.sb);
+            self.remaining = Some(if self.sc & 0x02 != 0 {
+                FAST_TRANSFER_CYCLES
+            } else {
+                TRANSFER_CYCLES
+            });
+        } else {
+            self.remaining = None;
+            self.peer.listen(self.sb);
+        }
+    }
+}
+
+impl Bus for SerialController {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0xFF01 => self.sb,
+            0xFF02 => self.sc | if self.cgb { 0x7C } else { 0x7E },
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF01 => self.sb = value,
+            0xFF02 => self.write_sc(value),
+            _ => (),
+        }
+    }
+}
+
+fn disconnected() -> Box<dyn SerialPeer> {
+    Box::new(Disconnected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{self, Machine};
+    use crate::GameBoyRom;
+
+    /// A machine running `code` at 0x150 from a ROM without an MBC.
+    fn machine(code: &[u8]) -> Machine {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x150..0x150 + code.len()].copy_from_slice(code);
+        Machine::new(&GameBoyRom::new(&rom)).unwrap()
+    }
+
+    /// Put `byte` in SB and start a transfer with the given SC.
+    fn transfer(byte: u8, sc: u8) -> Vec<u8> {
+        vec![
+            0x3E, byte, // ld a, byte
+            0xE0, 0x01, // ldh (SB), a
+            0x3E, sc, // ld a, sc
+            0xE0, 0x02, // ldh (SC), a
+            0x18, 0xFE, // jr @
+        ]
+    }
+
+    fn linked(first: &[u8], second: &[u8]) -> (Machine, Machine) {
+        let (mut first, mut second) = (machine(first), machine(second));
+        let (first_end, second_end) = LinkEnd::pair();
+        first.bus.serial.connect(Box::new(first_end));
+        second.bus.serial.connect(Box::new(second_end));
+        machine::run_linked(&mut first, &mut second, 1);
+        (first, second)
+    }
+
+    #[test]
+    fn run_linked() {
+        let (mut clock, mut listener) = linked(&transfer(0x42, 0x81), &transfer(0x99, 0x80));
+        assert_eq!(clock.bus.read(0xFF01), 0x99);
+        assert_eq!(listener.bus.read(0xFF01), 0x42);
+        for machine in [&mut clock, &mut listener].iter_mut() {
+            assert_eq!(machine.bus.read(0xFF02) & 0x80, 0);
+            assert_ne!(machine.bus.interrupts.flag & INTERRUPT_SERIAL, 0);
+        }
+        assert_eq!(clock.bus.serial_output(), [0x42]);
+        assert_eq!(listener.bus.serial_output(), [0x99]);
+    }
+
+    #[test]
+    fn late_listener() {
+        // wait 256 * 24 cycles, longer than the transfer, before listening
+        let mut late = vec![
+            0x06, 0x00, // ld b, 0
+            0x00, // wait: nop
+            0x00, // nop
+            0x05, // dec b
+            0x20, 0xFB, // jr nz, wait
+        ];
+        late.extend(transfer(0x99, 0x80));
+        let (mut clock, mut listener) = linked(&transfer(0x42, 0x81), &late);
+        // nothing clocked the other end's shift register, so 1s were shifted
+        // in
+        assert_eq!(clock.bus.read(0xFF01), 0xFF);
+        assert_ne!(clock.bus.interrupts.flag & INTERRUPT_SERIAL, 0);
+        // and the listener is still waiting
+        assert_eq!(listener.bus.read(0xFF01), 0x99);
+        assert_eq!(listener.bus.read(0xFF02) & 0x80, 0x80);
+        assert_eq!(listener.bus.interrupts.flag & INTERRUPT_SERIAL, 0);
+    }
+}
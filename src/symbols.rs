@@ -0,0 +1,310 @@
+//! Symbol files mapping banked addresses to labels.
+//!
+//! Both RGBDS and no$gmb style `.sym` files are supported; they share the same
+//! `BB:AAAA Label` line format which is also understood by BGB, SameBoy and
+//! Emulicious.
+//!
+//! ```
+//! use gameboy_rom::symbols::{BankedAddress, SymbolTable};
+//!
+//! let table = SymbolTable::parse("; comment\n01:4000 Init\n").unwrap();
+//! assert_eq!(table.get(BankedAddress::new(1, 0x4000)), Some("Init"));
+//! assert_eq!(table.to_sym_string(), "01:4000 Init\n");
+//! ```
+//!
+//! Labels for ROMs without an MBC may put 0x4000-0x7FFF in bank 0, which
+//! `SymbolTable::lookup` matches to code in bank 1:
+//!
+//! ```
+//! use gameboy_rom::symbols::SymbolTable;
+//!
+//! let table = SymbolTable::parse("00:0150 Start\n00:4000 Main\n00:C000 wBuffer\n").unwrap();
+//! assert_eq!(table.lookup(0, 0x0150), Some("Start"));
+//! assert_eq!(table.lookup(0, 0x4000), Some("Main"));
+//! assert_eq!(table.lookup(1, 0x4000), Some("Main"));
+//! assert_eq!(table.lookup(2, 0x4000), None);
+//! assert_eq!(table.lookup(1, 0xC000), Some("wBuffer"));
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// An address along with the bank it lives in.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BankedAddress {
+    pub bank: u16,
+    pub address: u16,
+}
+
+impl BankedAddress {
+    pub const fn new(bank: u16, address: u16) -> Self {
+        Self { bank, address }
+    }
+
+    /// Resolve an address referenced from code running in `current_bank`.
+    ///
+    /// Addresses in the fixed ROM bank are always in bank 0, addresses in the
+    /// switchable ROM bank are assumed to be in the same bank as the caller (or
+    /// bank 1 if the caller is in bank 0).
+    pub fn resolve(current_bank: u16, address: u16) -> Self {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF => current_bank.max(1),
+            _ => 0,
+        };
+        Self::new(bank, address)
+    }
+
+    /// The offset into the ROM file of this address, if it's in ROM.
+    pub fn rom_offset(&self) -> Option<usize> {
+        match self.address {
+            0x0000..=0x3FFF if self.bank == 0 => Some(self.address as usize),
+            0x4000..=0x7FFF if self.bank != 0 => {
+                Some(self.bank as usize * 0x4000 + (self.address as usize - 0x4000))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BankedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X}:{:04X}", self.bank, self.address)
+    }
+}
+
+/// Errors that may occur while reading a symbol file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SymbolFileError {
+    /// The line (1-indexed) isn't of the form `BB:AAAA Label`.
+    MalformedLine(usize),
+}
+
+impl fmt::Display for SymbolFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolFileError::MalformedLine(line) => {
+                write!(f, "line {} isn't of the form `BB:AAAA Label`", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SymbolFileError {}
+
+/// Labels keyed by banked address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: BTreeMap<BankedAddress, String>,
+    /// The lowest bank with a label at each address outside of ROM.
+    banks_by_address: BTreeMap<u16, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the contents of a `.sym` file.
+    ///
+    /// Comments starting with `;` and section headers like `[labels]` are
+    /// skipped. If an address has multiple labels the first one is kept.
+    pub fn parse(input: &str) -> Result<Self, SymbolFileError> {
+        let mut table = Self::new();
+        for (idx, line) in input.lines().enumerate() {
+            let line = match line.find(';') {
+                Some(comment_start) => &line[..comment_start],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() || line.starts_with('[') {
+                continue;
+            }
+            let malformed = || SymbolFileError::MalformedLine(idx + 1);
+
+            let mut parts = line.split_whitespace();
+            let location = parts.next().ok_or_else(malformed)?;
+            let label = parts.next().ok_or_else(malformed)?;
+            let mut location_parts = location.splitn(2, ':');
+            let bank = location_parts
+                .next()
+                .and_then(|bank| u16::from_str_radix(bank, 16).ok())
+                .ok_or_else(malformed)?;
+            let address = location_parts
+                .next()
+                .and_then(|address| u16::from_str_radix(address, 16).ok())
+                .ok_or_else(malformed)?;
+
+            table.insert_if_vacant(BankedAddress::new(bank, address), label);
+        }
+        Ok(table)
+    }
+
+    /// Render the table as a `.sym` file.
+    pub fn to_sym_string(&self) -> String {
+        let mut out = String::new();
+        for (location, label) in &self.symbols {
+            out.push_str(&format!("{} {}\n", location, label));
+        }
+        out
+    }
+
+    /// Add a label, replacing any existing label at that location.
+    pub fn insert(&mut self, location: BankedAddress, label: String) {
+        self.index(location);
+        self.symbols.insert(location, label);
+    }
+
+    /// Add all labels from `other` that don't conflict with existing labels.
+    pub fn merge(&mut self, other: &SymbolTable) {
+        for (location, label) in &other.symbols {
+            self.insert_if_vacant(*location, label);
+        }
+    }
+
+    fn insert_if_vacant(&mut self, location: BankedAddress, label: &str) {
+        if !self.symbols.contains_key(&location) {
+            self.insert(location, label.to_string());
+        }
+    }
+
+    fn index(&mut self, location: BankedAddress) {
+        if location.address >= 0x8000 {
+            self.banks_by_address
+                .entry(location.address)
+                .and_modify(|bank| *bank = location.bank.min(*bank))
+                .or_insert(location.bank);
+        }
+    }
+
+    /// Get the label at exactly the given location.
+    pub fn get(&self, location: BankedAddress) -> Option<&str> {
+        self.symbols.get(&location).map(|s| s.as_str())
+    }
+
+    /// Get the label for an address referenced from code running in
+    /// `current_bank`.
+    ///
+    /// ROMs without an MBC have no bank switching, so their symbol files
+    /// (from `rgblink -t` or BGB) label 0x4000-0x7FFF as bank 0 where code
+    /// resolves it to bank 1; either is accepted.
+    ///
+    /// RAM banks can't be known statically so for addresses outside of ROM the
+    /// first label at that address in any bank is used.
+    pub fn lookup(&self, current_bank: u16, address: u16) -> Option<&str> {
+        let location = BankedAddress::resolve(current_bank, address);
+        match address {
+            0x0000..=0x3FFF => self.get(location),
+            0x4000..=0x7FFF if location.bank == 1 => self
+                .get(location)
+                .or_else(|| self.get(BankedAddress::new(0, address))),
+            0x4000..=0x7FFF => self.get(location),
+            _ => {
+                let bank = *self.banks_by_address.get(&address)?;
+                self.get(BankedAddress::new(bank, address))
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&BankedAddress, &str)> {
+        self.symbols
+            .iter()
+            .map(|(loc, label)| (loc, label.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_line_numbers() {
+        let cases = [
+            ("; comment\n[labels]\n01:4000 Init\n\nInit\n", 5),
+            ("01:4000 Init\n01:40G0 Bad\n", 2),
+            ("0100 NoBank\n", 1),
+            ("01:4000 Init\n01:4001\n", 2),
+            ("10000:4000 BigBank\n", 1),
+        ];
+        for (input, line) in cases.iter() {
+            assert_eq!(
+                SymbolTable::parse(input),
+                Err(SymbolFileError::MalformedLine(*line)),
+                "{:?}",
+                input
+            );
+        }
+        assert_eq!(
+            SymbolFileError::MalformedLine(3).to_string(),
+            "line 3 isn't of the form `BB:AAAA Label`"
+        );
+    }
+
+    #[test]
+    fn sym_string_round_trip() {
+        let mut table = SymbolTable::new();
+        table.insert(BankedAddress::new(0, 0x0150), "Start".to_string());
+        table.insert(BankedAddress::new(0x1F, 0x7FFF), "End".to_string());
+        table.insert(BankedAddress::new(0x100, 0x4000), "HighBank".to_string());
+        table.insert(BankedAddress::new(1, 0xD000), "wBank1".to_string());
+        let sym = table.to_sym_string();
+        assert_eq!(
+            sym,
+            "00:0150 Start\n01:D000 wBank1\n1F:7FFF End\n100:4000 HighBank\n"
+        );
+        assert_eq!(SymbolTable::parse(&sym).unwrap(), table);
+    }
+
+    #[test]
+    fn merge_keeps_existing_labels() {
+        let mut table = SymbolTable::parse("00:0150 Start\n01:4000 Init\n").unwrap();
+        let other = SymbolTable::parse("00:0150 Entry\n02:4000 Bank2\n").unwrap();
+        table.merge(&other);
+        assert_eq!(table.get(BankedAddress::new(0, 0x0150)), Some("Start"));
+        assert_eq!(table.get(BankedAddress::new(1, 0x4000)), Some("Init"));
+        assert_eq!(table.get(BankedAddress::new(2, 0x4000)), Some("Bank2"));
+        assert_eq!(table.len(), 3);
+
+        // the first label in a file wins too, but insert replaces
+        let mut table = SymbolTable::parse("00:0150 Start\n00:0150 Entry\n").unwrap();
+        assert_eq!(table.get(BankedAddress::new(0, 0x0150)), Some("Start"));
+        table.insert(BankedAddress::new(0, 0x0150), "Entry".to_string());
+        assert_eq!(table.get(BankedAddress::new(0, 0x0150)), Some("Entry"));
+    }
+
+    #[test]
+    fn lookup_rom_only_bank_0() {
+        let table = SymbolTable::parse("00:4000 Main\n02:4000 Bank2\n").unwrap();
+        // 0x4000-0x7FFF resolves to bank 1 from bank 0 and 1
+        assert_eq!(table.lookup(0, 0x4000), Some("Main"));
+        assert_eq!(table.lookup(1, 0x4000), Some("Main"));
+        assert_eq!(table.lookup(2, 0x4000), Some("Bank2"));
+        assert_eq!(table.lookup(3, 0x4000), None);
+
+        // a bank 1 label takes precedence over the bank 0 one
+        let table = SymbolTable::parse("00:4000 Main\n01:4000 Bank1\n").unwrap();
+        assert_eq!(table.lookup(1, 0x4000), Some("Bank1"));
+    }
+
+    #[test]
+    fn lookup_ram() {
+        let mut table = SymbolTable::parse("02:D000 wBank2\n00:FF80 hFoo\n").unwrap();
+        assert_eq!(table.lookup(5, 0xD000), Some("wBank2"));
+        assert_eq!(table.lookup(0, 0xFF80), Some("hFoo"));
+        assert_eq!(table.lookup(0, 0xC000), None);
+
+        // the lowest bank wins, whatever order the labels were added in
+        table.insert(BankedAddress::new(1, 0xD000), "wBank1".to_string());
+        assert_eq!(table.lookup(0, 0xD000), Some("wBank1"));
+        table.merge(&SymbolTable::parse("03:D000 wBank3\n").unwrap());
+        assert_eq!(table.lookup(0, 0xD000), Some("wBank1"));
+    }
+}
@@ -0,0 +1,232 @@
+//! The DIV, TIMA, TMA and TAC timer registers.
+//!
+//! DIV is the upper byte of a 16 bit counter incremented every T-cycle. TIMA
+//! increments whenever the counter bit selected by TAC, ANDed with the timer
+//! enable bit, goes from 1 to 0. Modelling that signal rather than a
+//! frequency reproduces the well known glitches: resetting DIV or changing
+//! TAC can increment TIMA. When TIMA overflows it reads 0 for 4 cycles before
+//! TMA is loaded and the interrupt is requested.
+//!
+//! ```
+//! use gameboy_rom::cpu::Bus;
+//! use gameboy_rom::timer::Timer;
+//!
+//! let mut timer = Timer::new(0);
+//! // enabled, incrementing every 16 cycles on the falling edge of bit 3
+//! timer.write(0xFF07, 0x05);
+//! timer.step(8);
+//! assert_eq!(timer.read(0xFF05), 0);
+//! // bit 3 is set, so resetting DIV is a falling edge
+//! timer.write(0xFF04, 0);
+//! assert_eq!(timer.read(0xFF05), 1);
+//! ```
+//!
+//! Behavior is based on the [Pan Docs].
+//!
+//! [Pan Docs]: https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+
+use crate::cpu::Bus;
+use crate::interrupts::INTERRUPT_TIMER;
+
+use serde::{Deserialize, Serialize};
+
+/// The number of T-cycles between TIMA overflowing and TMA being loaded.
+const RELOAD_DELAY: u8 = 4;
+
+/// The timer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timer {
+    /// DIV is the upper 8 bits of this counter.
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// T-cycles until TMA is loaded after an overflow.
+    overflow_delay: Option<u8>,
+    /// T-cycles left in the M-cycle where TMA was loaded, writes to TIMA are
+    /// ignored and writes to TMA also go to TIMA.
+    reload_cycles: u8,
+}
+
+impl Timer {
+    /// Create a timer with the internal counter at `counter`.
+    pub fn new(counter: u16) -> Self {
+        Self {
+            counter,
+            ..Self::default()
+        }
+    }
+
+    /// The full 16 bit counter behind DIV.
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    /// The input to the falling edge detector that increments TIMA.
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.overflow_delay = Some(RELOAD_DELAY);
+        }
+    }
+
+    /// Run for the given number of T-cycles, returning the interrupts requested
+    /// as IF bits.
+    pub fn step(&mut self, cycles: u32) -> u8 {
+        let mut interrupts = 0;
+        for _ in 0..cycles {
+            self.reload_cycles = self.reload_cycles.saturating_sub(1);
+            if let Some(delay) = self.overflow_delay {
+                if delay == 1 {
+                    self.tima = self.tma;
+                    self.overflow_delay = None;
+                    self.reload_cycles = RELOAD_DELAY;
+                    interrupts |= INTERRUPT_TIMER;
+                } else {
+                    self.overflow_delay = Some(delay - 1);
+                }
+            }
+
+            let before = self.signal();
+            self.counter = self.counter.wrapping_add(1);
+            if before && !self.signal() {
+                self.increment_tima();
+            }
+        }
+        interrupts
+    }
+
+    /// Apply a change to the counter or TAC, incrementing TIMA if it causes a
+    /// falling edge.
+    fn update(&mut self, counter: u16, tac: u8) {
+        let before = self.signal();
+        self.counter = counter;
+        self.tac = tac;
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+    }
+}
+
+impl Bus for Timer {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF04 => self.update(0, self.tac),
+            // writing during the delay cancels the reload, writing on the
+            // cycle TMA is loaded is ignored
+            0xFF05 if self.reload_cycles == 0 => {
+                self.tima = value;
+                self.overflow_delay = None;
+            }
+            0xFF06 => {
+                self.tma = value;
+                if self.reload_cycles != 0 {
+                    self.tima = value;
+                }
+            }
+            0xFF07 => self.update(self.counter, value & 0x07),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A timer incrementing TIMA every 16 cycles, 16 cycles from overflowing
+    /// with TMA set to 0x42.
+    fn about_to_overflow() -> Timer {
+        let mut timer = Timer::new(0);
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF06, 0x42);
+        timer
+    }
+
+    #[test]
+    fn overflow_reload_delay() {
+        let mut timer = about_to_overflow();
+        assert_eq!(timer.step(16), 0);
+        // TIMA reads 0 for 4 cycles
+        assert_eq!(timer.read(0xFF05), 0);
+        assert_eq!(timer.step(3), 0);
+        assert_eq!(timer.read(0xFF05), 0);
+        assert_eq!(timer.step(1), INTERRUPT_TIMER);
+        assert_eq!(timer.read(0xFF05), 0x42);
+        assert_eq!(timer.step(16), 0);
+        assert_eq!(timer.read(0xFF05), 0x43);
+    }
+
+    #[test]
+    fn tima_write_cancels_reload() {
+        let mut timer = about_to_overflow();
+        timer.step(16);
+        timer.step(2);
+        timer.write(0xFF05, 0x10);
+        assert_eq!(timer.step(8), 0);
+        assert_eq!(timer.read(0xFF05), 0x10);
+    }
+
+    #[test]
+    fn writes_during_reload_cycle() {
+        let mut timer = about_to_overflow();
+        assert_eq!(timer.step(20), INTERRUPT_TIMER);
+        // TIMA writes are ignored, TMA writes go through to TIMA
+        timer.write(0xFF05, 0x99);
+        assert_eq!(timer.read(0xFF05), 0x42);
+        timer.write(0xFF06, 0x77);
+        assert_eq!(timer.read(0xFF05), 0x77);
+        assert_eq!(timer.read(0xFF06), 0x77);
+
+        timer.step(4);
+        timer.write(0xFF05, 0x99);
+        assert_eq!(timer.read(0xFF05), 0x99);
+        timer.write(0xFF06, 0x11);
+        assert_eq!(timer.read(0xFF05), 0x99);
+    }
+
+    #[test]
+    fn tac_change_glitch() {
+        // bit 3 of the counter is set, so moving away from it increments TIMA
+        let mut timer = Timer::new(0x0008);
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF07, 0x04);
+        assert_eq!(timer.read(0xFF05), 1);
+        // as does disabling the timer with the selected bit set
+        let mut timer = Timer::new(0x0008);
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF07, 0x01);
+        assert_eq!(timer.read(0xFF05), 1);
+        // but not moving to another bit that's set
+        let mut timer = Timer::new(0x0028);
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF07, 0x06);
+        assert_eq!(timer.read(0xFF05), 0);
+        // or enabling the timer
+        let mut timer = Timer::new(0x0008);
+        timer.write(0xFF07, 0x05);
+        assert_eq!(timer.read(0xFF05), 0);
+    }
+}
@@ -0,0 +1,189 @@
+//! UPS patches, which carry CRC32s of the ROM they apply to, the ROM they
+//! produce and the patch itself.
+//!
+//! A UPS patch is `UPS1`, the source and target sizes and then hunks of a
+//! distance to skip followed by bytes to XOR with the ROM, ending in a 0.
+//! Because it's an XOR, the same patch turns the target back into the
+//! source.
+//!
+//! ```
+//! use gameboy_rom::ups::UpsPatch;
+//!
+//! let source = b"The quick brown fox".to_vec();
+//! let target = b"The quick red fox".to_vec();
+//! let patch = UpsPatch::create(&source, &target);
+//! let patch = UpsPatch::parse(&patch).unwrap();
+//! assert_eq!(patch.apply(&source).unwrap(), target);
+//! assert_eq!(patch.apply(&target).unwrap(), source);
+//! ```
+
+pub use crate::bps::{PatchError, WrongRom};
+
+use crate::bps::{
+    check_source, check_target, read_footer, verify_footer, write_footer, write_number, Reader,
+};
+use crate::ips::PatchReport;
+
+const MAGIC: &[u8] = b"UPS1";
+
+/// A parsed UPS patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpsPatch<'a> {
+    pub source_size: usize,
+    pub target_size: usize,
+    pub source_crc32: u32,
+    pub target_crc32: u32,
+    pub patch_crc32: u32,
+    /// The patch without its footer.
+    body: &'a [u8],
+    /// Where the hunks start in `body`.
+    hunks_offset: usize,
+}
+
+impl<'a> UpsPatch<'a> {
+    /// Parse a UPS patch, checking its CRC32.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, PatchError> {
+        let body_end = verify_footer(bytes, MAGIC)?;
+        let mut reader = Reader::new(&bytes[..body_end], MAGIC.len());
+        let source_size = reader.rom_size()?;
+        let target_size = reader.rom_size()?;
+        let footer = read_footer(bytes);
+        Ok(Self {
+            source_size,
+            target_size,
+            source_crc32: footer[0],
+            target_crc32: footer[1],
+            patch_crc32: footer[2],
+            body: &bytes[..body_end],
+            hunks_offset: reader.position,
+        })
+    }
+
+    /// Apply the patch to `rom`, checking that it's the ROM the patch was made
+    /// for and that the result is the ROM the patch should produce. Given the
+    /// target, the patch is applied in reverse and produces the source.
+    pub fn apply(&self, rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+        let reverse = crc32fast::hash(rom) == self.target_crc32 && rom.len() == self.target_size;
+        let (output_size, output_crc32) = if reverse {
+            (self.source_size, self.source_crc32)
+        } else {
+            check_source(rom, self.source_crc32, self.source_size, self.target_crc32)?;
+            (self.target_size, self.target_crc32)
+        };
+
+        let mut output = rom.to_vec();
+        output.resize(output_size, 0);
+        let mut reader = Reader::new(self.body, self.hunks_offset);
+        let mut position: usize = 0;
+        while reader.position < self.body.len() {
+            let start = reader.position;
+            position = position
+                .checked_add(reader.number()? as usize)
+                .ok_or(PatchError::Malformed(start))?;
+            loop {
+                let byte = reader.byte().map_err(|_| PatchError::Malformed(start))?;
+                if byte == 0 {
+                    break;
+                }
+                // changes past the end of the output only matter in reverse
+                if let Some(output_byte) = output.get_mut(position) {
+                    *output_byte ^= byte;
+                }
+                position += 1;
+            }
+            position += 1;
+        }
+        check_target(&output, output_crc32)?;
+        Ok(output)
+    }
+
+    /// Create a patch turning `source` into `target`, and back.
+    pub fn create(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_number(&mut out, source.len() as u64);
+        write_number(&mut out, target.len() as u64);
+
+        let xor = |idx: usize| source.get(idx).unwrap_or(&0) ^ target.get(idx).unwrap_or(&0);
+        let len = source.len().max(target.len());
+        let mut last = 0;
+        let mut idx = 0;
+        while idx < len {
+            if xor(idx) == 0 {
+                idx += 1;
+                continue;
+            }
+            write_number(&mut out, (idx - last) as u64);
+            while idx < len && xor(idx) != 0 {
+                out.push(xor(idx));
+                idx += 1;
+            }
+            out.push(0);
+            // the terminating 0 covers the unchanged byte after the hunk
+            idx += 1;
+            last = idx;
+        }
+
+        write_footer(&mut out, source, target);
+        out
+    }
+}
+
+/// Apply a UPS patch to `rom`, returning the patched ROM and what changed.
+pub fn patch_rom(rom: &[u8], patch: &[u8]) -> Result<(Vec<u8>, PatchReport), PatchError> {
+    let patched = UpsPatch::parse(patch)?.apply(rom)?;
+    let report = PatchReport::new(rom, &patched);
+    Ok((patched, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_ROM_SIZE;
+
+    fn patch(source_size: u64, target_size: u64) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_number(&mut out, source_size);
+        write_number(&mut out, target_size);
+        write_footer(&mut out, b"", b"");
+        out
+    }
+
+    #[test]
+    fn huge_sizes() {
+        assert_eq!(
+            UpsPatch::parse(&patch(0, 1 << 61)),
+            Err(PatchError::TooLarge(1 << 61))
+        );
+        // applying in reverse produces a ROM of the source size
+        assert_eq!(
+            UpsPatch::parse(&patch(MAX_ROM_SIZE + 1, 0)),
+            Err(PatchError::TooLarge(MAX_ROM_SIZE + 1))
+        );
+        assert!(UpsPatch::parse(&patch(0, MAX_ROM_SIZE)).is_ok());
+    }
+
+    #[test]
+    fn reverse_different_sizes() {
+        let source = b"The quick brown fox".to_vec();
+        for target in [&b"The quick red fox jumps over"[..], b"A quick fox"].iter() {
+            let patch = UpsPatch::create(&source, target);
+            let patch = UpsPatch::parse(&patch).unwrap();
+            assert_eq!(patch.apply(&source).unwrap(), *target);
+            assert_eq!(patch.apply(target).unwrap(), source);
+        }
+    }
+
+    #[test]
+    fn wrong_rom() {
+        let patch = UpsPatch::create(b"ABCD", b"ABCE");
+        let patch = UpsPatch::parse(&patch).unwrap();
+        match patch.apply(b"ABCF") {
+            Err(PatchError::WrongRom(wrong)) => {
+                assert_eq!(wrong.expected_crc32, crc32fast::hash(b"ABCD"));
+                assert_eq!(wrong.actual_crc32, crc32fast::hash(b"ABCF"));
+                assert_eq!(wrong.title, None);
+            }
+            otherwise => panic!("{:?}", otherwise),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! A minimal writer for 16 bit PCM WAV files.
+//!
+//! ```
+//! let mut out = vec![];
+//! gameboy_rom::wav::write_wav(&mut out, 48_000, 2, &[0, 0, 100, -100]).unwrap();
+//! assert_eq!(&out[..4], b"RIFF");
+//! assert_eq!(out.len(), 44 + 4 * 2);
+//! ```
+
+use std::io::{self, Write};
+
+/// Write interleaved samples as a WAV file with the given number of channels.
+pub fn write_wav<W: Write>(
+    mut out: W,
+    sample_rate: u32,
+    channels: u16,
+    samples: &[i16],
+) -> io::Result<()> {
+    let data_size = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // uncompressed PCM
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,413 @@
+//! Renders scenes with the `ppu` module and compares the frames against the
+//! reference images in `tests/ppu`.
+//!
+//! Set `UPDATE_REFERENCE_IMAGES=1` to write the rendered frames as the new
+//! references instead. When a frame doesn't match, it's written next to the
+//! test binaries so it can be compared with the reference.
+use gameboy_rom::cpu::Bus;
+use gameboy_rom::ppu::{Ppu, DMG_GREYSCALE, FRAME_CYCLES, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+const LCDC_ON: u8 = 0x80;
+const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_WINDOW: u8 = 0x20;
+const LCDC_TILE_DATA_8000: u8 = 0x10;
+const LCDC_OBJ_16: u8 = 0x04;
+const LCDC_OBJ: u8 = 0x02;
+const LCDC_BG: u8 = 0x01;
+
+const WHITE: [u8; 4] = DMG_GREYSCALE[0];
+const LIGHT: [u8; 4] = DMG_GREYSCALE[1];
+const BLACK: [u8; 4] = DMG_GREYSCALE[3];
+
+/// Set the color number of every pixel of a tile at 0x8000.
+fn set_tile(ppu: &mut Ppu, tile: u8, color: impl Fn(u8, u8) -> u8) {
+    for y in 0..8 {
+        let (mut low, mut high) = (0, 0);
+        for x in 0..8 {
+            let color = color(x, y);
+            low |= (color & 1) << (7 - x);
+            high |= (color >> 1) << (7 - x);
+        }
+        let address = 0x8000 + tile as u16 * 16 + y as u16 * 2;
+        ppu.write(address, low);
+        ppu.write(address + 1, high);
+    }
+}
+
+/// Fill a 32x32 tile map, at 0x9800 or 0x9C00.
+fn set_map(ppu: &mut Ppu, map: u16, tile: impl Fn(u16, u16) -> u8) {
+    for y in 0..32 {
+        for x in 0..32 {
+            ppu.write(map + y * 32 + x, tile(x, y));
+        }
+    }
+}
+
+fn set_sprite(ppu: &mut Ppu, index: u16, y: u8, x: u8, tile: u8, attributes: u8) {
+    let address = 0xFE00 + index * 4;
+    for (offset, value) in [y, x, tile, attributes].iter().enumerate() {
+        ppu.write(address + offset as u16, *value);
+    }
+}
+
+/// The tiles shared by the scenes: 0 is blank, 1 a checkerboard of colors 1
+/// and 2, 2 vertical stripes of each color, 3 a color 3 border, 6 solid color
+/// 3 and 8 color 3 on its right half only.
+fn dmg_scene() -> Ppu {
+    let mut ppu = Ppu::new(false);
+    set_tile(&mut ppu, 0, |_, _| 0);
+    set_tile(&mut ppu, 1, |x, y| 1 + (x / 2 + y / 2) % 2);
+    set_tile(&mut ppu, 2, |x, _| x / 2);
+    set_tile(&mut ppu, 3, |x, y| {
+        if x == 0 || y == 0 || x == 7 || y == 7 {
+            3
+        } else {
+            0
+        }
+    });
+    set_tile(&mut ppu, 6, |_, _| 3);
+    set_tile(&mut ppu, 8, |x, _| if x < 4 { 0 } else { 3 });
+    ppu.write(0xFF47, 0xE4);
+    ppu.write(0xFF48, 0xE4);
+    // OBP1 maps color 3 to light grey
+    ppu.write(0xFF49, 0x54);
+    ppu
+}
+
+/// Write the four BGR555 colors of a CGB palette through BCPS/BCPD, or
+/// OCPS/OCPD when `index_register` is 0xFF6A, using auto-increment.
+fn set_palette(ppu: &mut Ppu, index_register: u16, palette: u8, colors: [u16; 4]) {
+    ppu.write(index_register, 0x80 | (palette * 8));
+    for color in colors.iter() {
+        for byte in color.to_le_bytes().iter() {
+            ppu.write(index_register + 1, *byte);
+        }
+    }
+}
+
+fn set_vram_bank(ppu: &mut Ppu, bank: u8) {
+    ppu.write(0xFF4F, bank);
+}
+
+/// Turn the LCD on with the given LCDC flags and render a frame.
+fn render(mut ppu: Ppu, lcdc: u8) -> Ppu {
+    ppu.write(0xFF40, LCDC_ON | lcdc);
+    ppu.step(FRAME_CYCLES);
+    ppu
+}
+
+/// Compare the frame against `tests/ppu/<name>.png`.
+fn assert_matches_reference(name: &str, ppu: &Ppu) {
+    let frame = ppu.framebuffer();
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/ppu")
+        .join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_REFERENCE_IMAGES").is_some() {
+        write_png(&reference, frame);
+        return;
+    }
+
+    let decoder = png::Decoder::new(File::open(&reference).unwrap());
+    let mut reader = decoder
+        .read_info()
+        .unwrap_or_else(|e| panic!("{}: {}", reference.display(), e));
+    let mut expected = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut expected).unwrap();
+    assert_eq!(
+        (info.width, info.height, info.color_type, info.bit_depth),
+        (
+            SCREEN_WIDTH as u32,
+            SCREEN_HEIGHT as u32,
+            png::ColorType::Rgba,
+            png::BitDepth::Eight
+        )
+    );
+    let differences = expected
+        .chunks(4)
+        .zip(frame.chunks(4))
+        .enumerate()
+        .filter(|(_, (expected, actual))| expected != actual)
+        .map(|(idx, _)| (idx % SCREEN_WIDTH, idx / SCREEN_WIDTH))
+        .collect::<Vec<_>>();
+    if let Some((x, y)) = differences.first() {
+        let actual = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", name));
+        write_png(&actual, frame);
+        panic!(
+            "{} pixels differ from {}, starting at ({}, {}); the frame was written to {}",
+            differences.len(),
+            reference.display(),
+            x,
+            y,
+            actual.display()
+        );
+    }
+}
+
+fn write_png(path: &Path, frame: &[u8]) {
+    let file = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(file, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(frame).unwrap();
+}
+
+#[test]
+fn background() {
+    let mut ppu = dmg_scene();
+    set_map(&mut ppu, 0x9800, |x, y| ((x + y) % 4) as u8);
+    // scrolled so the map wraps vertically
+    ppu.write(0xFF42, 250);
+    ppu.write(0xFF43, 4);
+    let ppu = render(ppu, LCDC_TILE_DATA_8000 | LCDC_BG);
+    assert_matches_reference("background", &ppu);
+}
+
+#[test]
+fn window() {
+    let mut ppu = dmg_scene();
+    set_map(&mut ppu, 0x9800, |_, _| 1);
+    set_map(&mut ppu, 0x9C00, |_, y| if y == 0 { 2 } else { 3 });
+    ppu.write(0xFF4A, 72);
+    ppu.write(0xFF4B, 80 + 7);
+    let ppu = render(
+        ppu,
+        LCDC_WINDOW_MAP | LCDC_WINDOW | LCDC_TILE_DATA_8000 | LCDC_BG,
+    );
+    assert_eq!(ppu.pixel(79, 72), DMG_GREYSCALE[2]);
+    // the window starts with its first line wherever WY puts it
+    assert_eq!(ppu.pixel(80, 72), WHITE);
+    assert_eq!(ppu.pixel(87, 72), BLACK);
+    assert_eq!(ppu.pixel(80, 80), BLACK);
+    assert_eq!(ppu.pixel(81, 81), WHITE);
+    assert_matches_reference("window", &ppu);
+}
+
+#[test]
+fn sprites_8x16() {
+    let mut ppu = dmg_scene();
+    // the top tile has a diagonal, the bottom is color 2 on its left half
+    set_tile(&mut ppu, 4, |x, y| if x == y { 3 } else { 1 });
+    set_tile(&mut ppu, 5, |x, _| if x < 4 { 2 } else { 0 });
+    set_sprite(&mut ppu, 0, 16 + 10, 8 + 10, 4, 0);
+    // the low bit of the tile number is ignored
+    set_sprite(&mut ppu, 1, 16 + 10, 8 + 30, 5, 0);
+    set_sprite(&mut ppu, 2, 16 + 10, 8 + 50, 4, 0x40);
+    set_sprite(&mut ppu, 3, 16 + 10, 8 + 70, 4, 0x20);
+    let ppu = render(ppu, LCDC_TILE_DATA_8000 | LCDC_OBJ_16 | LCDC_OBJ | LCDC_BG);
+    for left in [10, 30].iter() {
+        assert_eq!(ppu.pixel(left + 1, 11), BLACK);
+        assert_eq!(ppu.pixel(left + 2, 11), LIGHT);
+        assert_eq!(ppu.pixel(left + 3, 20), DMG_GREYSCALE[2]);
+        assert_eq!(ppu.pixel(left + 4, 20), WHITE);
+    }
+    // y flip swaps the two tiles
+    assert_eq!(ppu.pixel(50, 10), DMG_GREYSCALE[2]);
+    assert_eq!(ppu.pixel(57, 25 - 7), BLACK);
+    // and x flip mirrors them
+    assert_eq!(ppu.pixel(76, 11), BLACK);
+    assert_eq!(ppu.pixel(74, 20), DMG_GREYSCALE[2]);
+    assert_eq!(ppu.pixel(73, 20), WHITE);
+    assert_matches_reference("sprites_8x16", &ppu);
+}
+
+#[test]
+fn ten_sprites_per_line() {
+    let mut ppu = dmg_scene();
+    // off screen sprites still count towards the limit
+    set_sprite(&mut ppu, 0, 16 + 20, 0, 6, 0);
+    for idx in 1..12 {
+        set_sprite(&mut ppu, idx, 16 + 20, 8 + idx as u8 * 12, 6, 0);
+    }
+    // only lines 28-31 of this one are below the limit
+    set_sprite(&mut ppu, 12, 16 + 24, 8 + 150, 6, 0);
+    set_sprite(&mut ppu, 13, 16 + 40, 8, 6, 0);
+    let ppu = render(ppu, LCDC_TILE_DATA_8000 | LCDC_OBJ | LCDC_BG);
+    assert_eq!(ppu.pixel(9 * 12, 20), BLACK);
+    assert_eq!(ppu.pixel(10 * 12, 20), WHITE);
+    assert_eq!(ppu.pixel(11 * 12, 20), WHITE);
+    assert_eq!(ppu.pixel(150, 27), WHITE);
+    assert_eq!(ppu.pixel(150, 28), BLACK);
+    assert_eq!(ppu.pixel(0, 40), BLACK);
+    assert_matches_reference("ten_sprites_per_line", &ppu);
+}
+
+#[test]
+fn dmg_x_priority() {
+    let mut ppu = dmg_scene();
+    const OBP1: u8 = 0x10;
+    // the sprite further left wins, even though it's later in OAM
+    set_sprite(&mut ppu, 0, 16 + 10, 8 + 24, 6, 0);
+    set_sprite(&mut ppu, 1, 16 + 10, 8 + 20, 6, OBP1);
+    // ties go to the first in OAM
+    set_sprite(&mut ppu, 2, 16 + 30, 8 + 20, 6, 0);
+    set_sprite(&mut ppu, 3, 16 + 30, 8 + 20, 6, OBP1);
+    // the winner's transparent pixels show the sprite behind it
+    set_sprite(&mut ppu, 4, 16 + 50, 8 + 20, 8, 0);
+    set_sprite(&mut ppu, 5, 16 + 50, 8 + 22, 6, OBP1);
+    let ppu = render(ppu, LCDC_TILE_DATA_8000 | LCDC_OBJ | LCDC_BG);
+    assert_eq!(ppu.pixel(27, 10), LIGHT);
+    assert_eq!(ppu.pixel(28, 10), BLACK);
+    assert_eq!(ppu.pixel(20, 30), BLACK);
+    assert_eq!(ppu.pixel(21, 50), WHITE);
+    assert_eq!(ppu.pixel(22, 50), LIGHT);
+    assert_eq!(ppu.pixel(24, 50), BLACK);
+    assert_eq!(ppu.pixel(28, 50), LIGHT);
+    assert_matches_reference("dmg_x_priority", &ppu);
+}
+
+const BCPS: u16 = 0xFF68;
+const OCPS: u16 = 0xFF6A;
+
+// BGR555 colors and the RGBA they expand to, each 5 bit channel c becomes
+// c << 3 | c >> 2
+const BGR_WHITE: u16 = 0x7FFF;
+const BGR_RED: u16 = 0x001F;
+const BGR_GREEN: u16 = 0x03E0;
+const BGR_BLUE: u16 = 0x7C00;
+const BGR_GREY: u16 = 0x4210;
+const BGR_MAGENTA: u16 = 0x7C1F;
+const RGBA_WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const RGBA_RED: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+const RGBA_GREEN: [u8; 4] = [0x00, 0xFF, 0x00, 0xFF];
+const RGBA_BLUE: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+const RGBA_GREY: [u8; 4] = [0x84, 0x84, 0x84, 0xFF];
+const RGBA_MAGENTA: [u8; 4] = [0xFF, 0x00, 0xFF, 0xFF];
+
+#[test]
+fn cgb_background_attributes() {
+    let mut ppu = Ppu::new(true);
+    set_tile(&mut ppu, 1, |_, _| 1);
+    // left half color 3
+    set_tile(&mut ppu, 2, |x, _| if x < 4 { 3 } else { 0 });
+    // top half color 1, bottom half color 3
+    set_tile(&mut ppu, 3, |_, y| if y < 4 { 1 } else { 3 });
+    set_vram_bank(&mut ppu, 1);
+    // the same tile number in bank 1 is solid color 2
+    set_tile(&mut ppu, 1, |_, _| 2);
+    set_vram_bank(&mut ppu, 0);
+    set_palette(&mut ppu, BCPS, 0, [BGR_WHITE, BGR_RED, BGR_GREEN, BGR_BLUE]);
+    set_palette(
+        &mut ppu,
+        BCPS,
+        3,
+        [BGR_WHITE, BGR_GREY, BGR_MAGENTA, BGR_GREEN],
+    );
+
+    let columns = [
+        (1, 0x00),
+        // palette 3
+        (1, 0x03),
+        // tile data from bank 1
+        (1, 0x08),
+        // x flip
+        (2, 0x20),
+        (2, 0x00),
+        // y flip
+        (3, 0x40),
+        // every attribute at once
+        (1, 0x08 | 0x40 | 0x20 | 0x03),
+        (0, 0x00),
+    ];
+    set_map(&mut ppu, 0x9800, |x, _| columns[x as usize % 8].0);
+    set_vram_bank(&mut ppu, 1);
+    set_map(&mut ppu, 0x9800, |x, _| columns[x as usize % 8].1);
+    set_vram_bank(&mut ppu, 0);
+    let ppu = render(ppu, LCDC_TILE_DATA_8000 | LCDC_BG);
+
+    assert_eq!(ppu.pixel(0, 0), RGBA_RED);
+    assert_eq!(ppu.pixel(8, 0), RGBA_GREY);
+    assert_eq!(ppu.pixel(16, 0), RGBA_GREEN);
+    assert_eq!(ppu.pixel(27, 0), RGBA_WHITE);
+    assert_eq!(ppu.pixel(28, 0), RGBA_BLUE);
+    assert_eq!(ppu.pixel(32, 0), RGBA_BLUE);
+    assert_eq!(ppu.pixel(36, 0), RGBA_WHITE);
+    assert_eq!(ppu.pixel(40, 0), RGBA_BLUE);
+    assert_eq!(ppu.pixel(40, 7), RGBA_RED);
+    assert_eq!(ppu.pixel(48, 3), RGBA_MAGENTA);
+    assert_eq!(ppu.pixel(56, 0), RGBA_WHITE);
+    assert_matches_reference("cgb_background_attributes", &ppu);
+}
+
+/// Solid color 1 background tiles with BG-to-OAM priority set on the third
+/// row of tiles, and color 0 tiles in the third column and fifth row.
+fn cgb_priority_scene() -> Ppu {
+    let mut ppu = Ppu::new(true);
+    set_tile(&mut ppu, 1, |_, _| 1);
+    set_tile(&mut ppu, 6, |_, _| 3);
+    set_vram_bank(&mut ppu, 1);
+    set_tile(&mut ppu, 6, |_, _| 1);
+    set_vram_bank(&mut ppu, 0);
+    set_palette(&mut ppu, BCPS, 0, [BGR_WHITE, BGR_RED, BGR_RED, BGR_RED]);
+    set_palette(
+        &mut ppu,
+        OCPS,
+        0,
+        [BGR_WHITE, BGR_WHITE, BGR_WHITE, BGR_BLUE],
+    );
+    set_palette(
+        &mut ppu,
+        OCPS,
+        2,
+        [BGR_WHITE, BGR_GREEN, BGR_WHITE, BGR_GREY],
+    );
+
+    set_map(
+        &mut ppu,
+        0x9800,
+        |x, y| if x == 2 || y == 4 { 0 } else { 1 },
+    );
+    set_vram_bank(&mut ppu, 1);
+    set_map(
+        &mut ppu,
+        0x9800,
+        |_, y| if y == 2 || y == 4 { 0x80 } else { 0 },
+    );
+    set_vram_bank(&mut ppu, 0);
+
+    const OBJ_BEHIND_BG: u8 = 0x80;
+    const OBJ_BANK_1: u8 = 0x08;
+    set_sprite(&mut ppu, 0, 16, 8, 6, 0);
+    set_sprite(&mut ppu, 1, 16, 8 + 8, 6, OBJ_BEHIND_BG | 2);
+    set_sprite(&mut ppu, 2, 16, 8 + 16, 6, OBJ_BEHIND_BG | 2);
+    set_sprite(&mut ppu, 3, 16, 8 + 24, 6, OBJ_BANK_1 | 2);
+    set_sprite(&mut ppu, 4, 16 + 16, 8, 6, 0);
+    set_sprite(&mut ppu, 5, 16 + 32, 8, 6, 0);
+    ppu
+}
+
+#[test]
+fn cgb_sprite_priority() {
+    let ppu = render(
+        cgb_priority_scene(),
+        LCDC_TILE_DATA_8000 | LCDC_OBJ | LCDC_BG,
+    );
+    assert_eq!(ppu.pixel(0, 0), RGBA_BLUE);
+    // OBJ-behind-BG only hides the sprite behind background colors 1-3
+    assert_eq!(ppu.pixel(8, 0), RGBA_RED);
+    assert_eq!(ppu.pixel(16, 0), RGBA_GREY);
+    // OBP palette 2, tile data from bank 1
+    assert_eq!(ppu.pixel(24, 0), RGBA_GREEN);
+    // BG-to-OAM priority, likewise only for colors 1-3
+    assert_eq!(ppu.pixel(0, 16), RGBA_RED);
+    assert_eq!(ppu.pixel(0, 32), RGBA_BLUE);
+    assert_eq!(ppu.pixel(40, 0), RGBA_RED);
+    assert_matches_reference("cgb_sprite_priority", &ppu);
+}
+
+#[test]
+fn cgb_master_priority() {
+    // with LCDC bit 0 clear the background is still drawn, but every sprite
+    // is drawn over it
+    let ppu = render(cgb_priority_scene(), LCDC_TILE_DATA_8000 | LCDC_OBJ);
+    assert_eq!(ppu.pixel(0, 0), RGBA_BLUE);
+    assert_eq!(ppu.pixel(8, 0), RGBA_GREY);
+    assert_eq!(ppu.pixel(16, 0), RGBA_GREY);
+    assert_eq!(ppu.pixel(24, 0), RGBA_GREEN);
+    assert_eq!(ppu.pixel(0, 16), RGBA_BLUE);
+    assert_eq!(ppu.pixel(0, 32), RGBA_BLUE);
+    assert_eq!(ppu.pixel(40, 0), RGBA_RED);
+}
@@ -0,0 +1,196 @@
+//! Runs the SM83 single-step tests in `tests/sm83/v1` against the `cpu`
+//! module.
+//!
+//! Each test executes one instruction from an initial state and checks the
+//! registers, IME, the RAM it lists and the cycles taken against the final
+//! state. See `tests/sm83/README.md` for where the tests come from, nothing
+//! is run until they've been vendored.
+use gameboy_rom::cpu::{Bus, Cpu, Registers};
+use gameboy_rom::GameBoyRom;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// How many failures are printed before giving up on a file.
+const MAX_FAILURES: usize = 10;
+
+#[derive(Debug, Deserialize)]
+struct Test {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    /// One entry per M-cycle, only the number of them is checked.
+    cycles: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct State {
+    pc: u16,
+    sp: u16,
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    #[serde(default)]
+    ime: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            f: self.f,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+        }
+    }
+}
+
+/// 64KB of RAM with nothing mapped.
+struct FlatBus(Vec<u8>);
+
+impl Bus for FlatBus {
+    fn read(&mut self, address: u16) -> u8 {
+        self.0[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.0[address as usize] = value;
+    }
+}
+
+/// Run a test, describing how the final state differs if it does.
+fn run(test: &Test) -> Result<(), String> {
+    let mut bus = FlatBus(vec![0; 0x10000]);
+    for (address, value) in &test.initial.ram {
+        bus.0[*address as usize] = *value;
+    }
+    let mut cpu = Cpu::new(test.initial.registers());
+    cpu.ime = test.initial.ime != 0;
+    let cycles = cpu.step(&mut bus);
+
+    let mut differences = vec![];
+    if cpu.registers != test.expected.registers() {
+        differences.push(format!(
+            "registers {:?}, expected {:?}",
+            cpu.registers,
+            test.expected.registers()
+        ));
+    }
+    if cpu.ime != (test.expected.ime != 0) {
+        differences.push(format!("ime {}, expected {}", cpu.ime, test.expected.ime));
+    }
+    for (address, value) in &test.expected.ram {
+        if bus.0[*address as usize] != *value {
+            differences.push(format!(
+                "[{:04X}] = {:02X}, expected {:02X}",
+                address, bus.0[*address as usize], value
+            ));
+        }
+    }
+    if cycles as usize != test.cycles.len() * 4 {
+        differences.push(format!(
+            "{} T-cycles, expected {}",
+            cycles,
+            test.cycles.len() * 4
+        ));
+    }
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(differences.join(", "))
+    }
+}
+
+/// The opcode a test executes, 0xCBxx for the 0xCB instructions.
+fn opcode(test: &Test) -> u16 {
+    let byte = |offset: u16| {
+        let address = test.initial.pc.wrapping_add(offset);
+        test.initial
+            .ram
+            .iter()
+            .find(|(ram_address, _)| *ram_address == address)
+            .map(|(_, value)| *value)
+            .unwrap_or(0)
+    };
+    match byte(0) {
+        0xCB => 0xCB00 | byte(1) as u16,
+        opcode => opcode as u16,
+    }
+}
+
+#[test]
+fn single_step() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sm83/v1");
+    if !directory.exists() {
+        eprintln!(
+            "{} is missing, run tests/sm83/vendor.py",
+            directory.display()
+        );
+        return;
+    }
+    let mut paths = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut tested = BTreeSet::new();
+    let mut failures = vec![];
+    for path in &paths {
+        let tests: Vec<Test> = serde_json::from_slice(&fs::read(path).unwrap())
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let mut file_failures = 0;
+        for test in &tests {
+            tested.insert(opcode(test));
+            if let Err(difference) = run(test) {
+                failures.push(format!("{}: {}", test.name, difference));
+                file_failures += 1;
+                if file_failures == MAX_FAILURES {
+                    break;
+                }
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+
+    // every instruction but STOP, which depends on the hardware around it
+    let untested = (0..=0xFFu16)
+        .chain(0xCB00..=0xCBFF)
+        .filter(|opcode| {
+            let bytes = opcode.to_be_bytes();
+            let bytes: &[u8] = if bytes[0] == 0 { &bytes[1..] } else { &bytes };
+            let mut padded = bytes.to_vec();
+            padded.extend_from_slice(&[0, 0]);
+            let decodes = GameBoyRom::new(&padded)
+                .get_instructions_at(0)
+                .next()
+                .is_some();
+            decodes && *opcode != 0x10 && *opcode != 0xCB && !tested.contains(opcode)
+        })
+        .map(|opcode| format!("{:02X}", opcode))
+        .collect::<Vec<_>>();
+    assert!(untested.is_empty(), "no tests for {}", untested.join(" "));
+}
@@ -0,0 +1,51 @@
+#!/usr/bin/env python3
+"""Copy the first few cases of every SingleStepTests/sm83 test file into v1.
+
+Usage: python3 tests/sm83/vendor.py [git ref] [cases per opcode]
+"""
+import json
+import os
+import sys
+import urllib.error
+import urllib.request
+
+REPOSITORY = "https://raw.githubusercontent.com/SingleStepTests/sm83"
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+
+def fetch(ref, path):
+    url = "{}/{}/{}".format(REPOSITORY, ref, urllib.request.quote(path))
+    with urllib.request.urlopen(url) as response:
+        return response.read()
+
+
+def main():
+    ref = sys.argv[1] if len(sys.argv) > 1 else "main"
+    cases = int(sys.argv[2]) if len(sys.argv) > 2 else 10
+
+    license = fetch(ref, "LICENSE")
+    with open(os.path.join(HERE, "LICENSE"), "wb") as f:
+        f.write(license)
+
+    out = os.path.join(HERE, "v1")
+    os.makedirs(out, exist_ok=True)
+    names = ["{:02x}".format(op) for op in range(0x100)]
+    names += ["cb {:02x}".format(op) for op in range(0x100)]
+    missing = []
+    for name in names:
+        try:
+            tests = json.loads(fetch(ref, "v1/{}.json".format(name)))
+        except urllib.error.HTTPError as e:
+            # illegal opcodes have no tests
+            if e.code != 404:
+                raise
+            missing.append(name)
+            continue
+        with open(os.path.join(out, name + ".json"), "w") as f:
+            json.dump(tests[:cases], f, separators=(",", ":"))
+            f.write("\n")
+    print("no tests for", " ".join(missing))
+
+
+if __name__ == "__main__":
+    main()